@@ -0,0 +1,113 @@
+// Command-line Arguments
+//
+// `typesafe [--root <main.tex>] [--forward-search] [<file>[:LINE[:COL]] | <folder>]`.
+// The `build` subcommand is handled separately in `main`.
+
+use std::path::{Path, PathBuf};
+
+pub const USAGE: &str = "usage: typesafe [--root <main.tex>] [--forward-search] [<file>[:LINE[:COL]] | <folder>]\n       typesafe build [--trust-hooks] <root.tex>";
+
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct LaunchArgs {
+    // File or folder to open, absolute
+    pub path: Option<PathBuf>,
+    // 1-based, as printed by compilers and SyncTeX
+    pub line: Option<usize>,
+    pub column: Option<usize>,
+    pub root: Option<PathBuf>,
+    // Also show `line` in the PDF preview
+    pub forward_search: bool,
+}
+
+impl LaunchArgs {
+    // `args` excludes the program name. Relative paths are resolved against `cwd`.
+    pub fn parse(args: &[String], cwd: &Path) -> Result<Self, String> {
+        let mut launch = Self::default();
+        let mut args = args.iter();
+        while let Some(arg) = args.next() {
+            if let Some(value) = arg.strip_prefix("--root=") {
+                launch.root = Some(cwd.join(value));
+            } else if arg == "--root" {
+                let value = args.next().ok_or("--root needs a file")?;
+                launch.root = Some(cwd.join(value));
+            } else if arg == "--forward-search" {
+                launch.forward_search = true;
+            } else if arg.starts_with("--") {
+                return Err(format!("unknown option {}", arg));
+            } else if launch.path.is_some() {
+                return Err(format!("unexpected argument {}", arg));
+            } else {
+                let (file, line, column) = split_location(arg, cwd);
+                launch.path = Some(cwd.join(file));
+                launch.line = line;
+                launch.column = column;
+            }
+        }
+        Ok(launch)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.path.is_none() && self.root.is_none()
+    }
+}
+
+// Splits `file.tex:LINE[:COL]`. Arguments naming an existing path are taken
+// as-is, so file names containing ':' still open.
+fn split_location<'a>(arg: &'a str, cwd: &Path) -> (&'a str, Option<usize>, Option<usize>) {
+    if cwd.join(arg).exists() {
+        return (arg, None, None);
+    }
+    let Some((rest, last)) = arg.rsplit_once(':') else { return (arg, None, None) };
+    let Ok(last) = last.parse::<usize>() else { return (arg, None, None) };
+    match rest.rsplit_once(':').map(|(file, line)| (file, line.parse::<usize>())) {
+        Some((file, Ok(line))) if !file.is_empty() => (file, Some(line), Some(last)),
+        _ => (rest, Some(last), None),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(args: &[&str]) -> Result<LaunchArgs, String> {
+        let args: Vec<String> = args.iter().map(|a| a.to_string()).collect();
+        LaunchArgs::parse(&args, Path::new("/nonexistent/work"))
+    }
+
+    #[test]
+    fn file_with_line_and_column() {
+        let launch = parse(&["thesis.tex:12:3"]).unwrap();
+        assert_eq!(launch.path, Some(PathBuf::from("/nonexistent/work/thesis.tex")));
+        assert_eq!((launch.line, launch.column), (Some(12), Some(3)));
+
+        let launch = parse(&["chapters/intro.tex:40"]).unwrap();
+        assert_eq!(launch.path, Some(PathBuf::from("/nonexistent/work/chapters/intro.tex")));
+        assert_eq!((launch.line, launch.column), (Some(40), None));
+    }
+
+    #[test]
+    fn drive_letters_are_not_locations() {
+        let (file, line, column) = split_location(r"C:\papers\main.tex", Path::new("/nonexistent"));
+        assert_eq!((file, line, column), (r"C:\papers\main.tex", None, None));
+
+        let (file, line, column) = split_location(r"C:\papers\main.tex:7", Path::new("/nonexistent"));
+        assert_eq!((file, line, column), (r"C:\papers\main.tex", Some(7), None));
+    }
+
+    #[test]
+    fn root_option() {
+        let launch = parse(&["--root", "main.tex", "ch1.tex"]).unwrap();
+        assert_eq!(launch.root, Some(PathBuf::from("/nonexistent/work/main.tex")));
+        assert_eq!(launch.path, Some(PathBuf::from("/nonexistent/work/ch1.tex")));
+
+        let launch = parse(&["--root=/abs/main.tex"]).unwrap();
+        assert_eq!(launch.root, Some(PathBuf::from("/abs/main.tex")));
+        assert!(launch.path.is_none());
+
+        assert!(parse(&["--forward-search", "ch1.tex:9"]).unwrap().forward_search);
+        assert!(parse(&["--root"]).is_err());
+        assert!(parse(&["--frobnicate"]).is_err());
+        assert!(parse(&["a.tex", "b.tex"]).is_err());
+        assert!(parse(&[]).unwrap().is_empty());
+    }
+}
@@ -0,0 +1,1437 @@
+// Compile Pipeline
+//
+// Shared by the editor (on a worker thread) and the headless `typesafe build`
+// command. Progress is reported through `CompilationMsg` so both front-ends
+// consume the same stream.
+
+use crossbeam_channel::Sender;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use std::process::{Child, Command, Output, Stdio};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+// Extra engine passes allowed when the log asks for a rerun
+const MAX_RERUNS: usize = 3;
+// How often a running tool is checked for cancellation and timeout
+const POLL_INTERVAL: Duration = Duration::from_millis(25);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Severity {
+    Error,
+    Warning,
+    Badbox,
+}
+
+impl Severity {
+    pub fn all() -> &'static [Severity] {
+        &[Severity::Error, Severity::Warning, Severity::Badbox]
+    }
+
+    pub fn label(&self) -> &'static str {
+        match self {
+            Severity::Error => "error",
+            Severity::Warning => "warning",
+            Severity::Badbox => "badbox",
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct Diagnostic {
+    pub severity: Severity,
+    pub message: String,
+    // 1-based; 0 when the log gives no line
+    pub line: usize,
+    // 0-based character offset into the line, when known
+    pub column: Option<usize>,
+    pub file: String,
+}
+
+pub enum CompilationMsg {
+    Start,
+    Log(String),
+    Diagnostics(Vec<Diagnostic>),
+    Success(PathBuf, String, String),
+    Error(String),
+    Cancelled,
+}
+
+// Engines
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default, Serialize, Deserialize)]
+pub enum Engine {
+    #[default]
+    Tectonic,
+    Latexmk,
+    Pdflatex,
+    Xelatex,
+    Lualatex,
+}
+
+impl Engine {
+    pub fn all() -> &'static [Engine] {
+        &[
+            Engine::Tectonic,
+            Engine::Latexmk,
+            Engine::Pdflatex,
+            Engine::Xelatex,
+            Engine::Lualatex,
+        ]
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Engine::Tectonic => "Tectonic",
+            Engine::Latexmk => "latexmk",
+            Engine::Pdflatex => "pdfLaTeX",
+            Engine::Xelatex => "XeLaTeX",
+            Engine::Lualatex => "LuaLaTeX",
+        }
+    }
+
+    // Accepts either the display name or the program name, in any case
+    pub fn from_name(name: &str) -> Option<Engine> {
+        let name = name.trim();
+        Engine::all().iter().copied().find(|e| e.name().eq_ignore_ascii_case(name) || e.program().eq_ignore_ascii_case(name))
+    }
+
+    pub fn program(&self) -> &'static str {
+        match self {
+            Engine::Tectonic => "tectonic",
+            Engine::Latexmk => "latexmk",
+            Engine::Pdflatex => "pdflatex",
+            Engine::Xelatex => "xelatex",
+            Engine::Lualatex => "lualatex",
+        }
+    }
+
+    // SyncTeX and file:line:error output are needed by the editor, so every
+    // default asks for them
+    pub fn default_args(&self) -> Vec<String> {
+        let args: &[&str] = match self {
+            Engine::Tectonic => &["--synctex", "--keep-intermediates", "--keep-logs"],
+            Engine::Latexmk => &["-pdf", "-interaction=nonstopmode", "-synctex=1", "-file-line-error"],
+            Engine::Pdflatex | Engine::Xelatex | Engine::Lualatex => {
+                &["-interaction=nonstopmode", "-synctex=1", "-file-line-error"]
+            }
+        };
+        args.iter().map(|a| a.to_string()).collect()
+    }
+}
+
+// Engine choice plus a space-separated argument list per engine. Engines
+// without an entry use `Engine::default_args`.
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct EngineSettings {
+    #[serde(default)]
+    pub engine: Engine,
+    #[serde(default)]
+    pub args: HashMap<Engine, String>,
+}
+
+impl EngineSettings {
+    pub fn args_for(&self, engine: Engine) -> Vec<String> {
+        match self.args.get(&engine) {
+            Some(args) => args.split_whitespace().map(str::to_string).collect(),
+            None => engine.default_args(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BibTool {
+    Biber,
+    Bibtex,
+}
+
+impl BibTool {
+    pub fn from_name(name: &str) -> Option<BibTool> {
+        match name.trim().to_lowercase().as_str() {
+            "biber" => Some(BibTool::Biber),
+            "bibtex" | "bibtex8" => Some(BibTool::Bibtex),
+            _ => None,
+        }
+    }
+}
+
+// Shell commands run around a build, in the root document's folder. A failing
+// `before_build` command stops the build; `after_build` runs once the PDF exists
+// and its failures are only logged. Both see TYPESAFE_ROOT and TYPESAFE_PDF.
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct BuildHooks {
+    pub before_build: Vec<String>,
+    pub after_build: Vec<String>,
+}
+
+// Locates a bundled binary next to the executable, in `deps/` beside it, or in
+// the project's `deps/` during development. Falls back to the bare name so the
+// system PATH is searched.
+pub fn locate_binary(name: &str) -> PathBuf {
+    let file_name = if cfg!(windows) { format!("{}.exe", name) } else { name.to_string() };
+
+    if let Ok(current_exe) = std::env::current_exe() {
+        if let Some(exe_dir) = current_exe.parent() {
+            let mut candidates = vec![exe_dir.join(&file_name), exe_dir.join("deps").join(&file_name)];
+            // target/<profile>/typesafe -> <project>/deps
+            if let Some(project_root) = exe_dir.parent().and_then(|p| p.parent()) {
+                candidates.push(project_root.join("deps").join(&file_name));
+            }
+            if let Some(found) = candidates.into_iter().find(|c| c.exists()) {
+                return found;
+            }
+        }
+    }
+
+    PathBuf::from(file_name)
+}
+
+#[derive(Clone, Debug)]
+pub struct Toolchain {
+    pub tectonic: PathBuf,
+    pub latexmk: PathBuf,
+    pub pdflatex: PathBuf,
+    pub xelatex: PathBuf,
+    pub lualatex: PathBuf,
+    pub bibtex: PathBuf,
+    pub biber: PathBuf,
+    pub makeindex: PathBuf,
+}
+
+impl Toolchain {
+    pub fn locate() -> Self {
+        Self {
+            tectonic: locate_binary("tectonic"),
+            latexmk: locate_binary("latexmk"),
+            pdflatex: locate_binary("pdflatex"),
+            xelatex: locate_binary("xelatex"),
+            lualatex: locate_binary("lualatex"),
+            bibtex: locate_binary("bibtex"),
+            biber: locate_binary("biber"),
+            makeindex: locate_binary("makeindex"),
+        }
+    }
+
+    pub fn engine(&self, engine: Engine) -> &Path {
+        match engine {
+            Engine::Tectonic => &self.tectonic,
+            Engine::Latexmk => &self.latexmk,
+            Engine::Pdflatex => &self.pdflatex,
+            Engine::Xelatex => &self.xelatex,
+            Engine::Lualatex => &self.lualatex,
+        }
+    }
+}
+
+// Shared between a running job and whoever may stop it
+#[derive(Debug, Default)]
+pub struct BuildControl {
+    cancelled: AtomicBool,
+    deadline: Mutex<Option<Instant>>,
+}
+
+impl BuildControl {
+    pub fn cancel(&self) {
+        self.cancelled.store(true, Ordering::SeqCst);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.cancelled.load(Ordering::SeqCst)
+    }
+
+    fn start(&self, timeout: Option<Duration>) {
+        *self.deadline.lock().unwrap() = timeout.map(|t| Instant::now() + t);
+    }
+
+    fn timed_out(&self) -> bool {
+        self.deadline.lock().unwrap().is_some_and(|d| Instant::now() >= d)
+    }
+
+    // Error to abort the current tool with, if any
+    fn interruption(&self) -> Option<std::io::Error> {
+        if self.is_cancelled() {
+            Some(std::io::Error::new(std::io::ErrorKind::Interrupted, "cancelled"))
+        } else if self.timed_out() {
+            Some(std::io::Error::new(std::io::ErrorKind::TimedOut, "timed out"))
+        } else {
+            None
+        }
+    }
+}
+
+pub struct CompileJob {
+    pub target: PathBuf,
+    pub engine: Engine,
+    pub args: Vec<String>,
+    pub has_biblatex: bool,
+    // Forced bibliography tool; otherwise biber for biblatex documents, bibtex for the rest
+    pub bib_tool: Option<BibTool>,
+    pub last_bcf_hash: String,
+    pub last_bib_hash: String,
+    pub toolchain: Toolchain,
+    // Editor buffers written to disk right before the build starts
+    pub sources: Vec<(PathBuf, String)>,
+    pub timeout: Option<Duration>,
+    pub control: Arc<BuildControl>,
+    pub hooks: BuildHooks,
+    // Build directory for the PDF, log and auxiliary files; None builds beside the sources
+    pub out_dir: Option<PathBuf>,
+}
+
+#[derive(Debug, Default)]
+pub struct CompileResult {
+    pub pdf_path: Option<PathBuf>,
+    pub diagnostics: Vec<Diagnostic>,
+    pub bcf_hash: String,
+    pub bib_hash: String,
+    pub error: Option<String>,
+    pub cancelled: bool,
+}
+
+impl CompileResult {
+    pub fn succeeded(&self) -> bool {
+        self.error.is_none() && self.pdf_path.is_some()
+    }
+}
+
+impl CompileJob {
+    pub fn new(target: impl Into<PathBuf>) -> Self {
+        Self {
+            target: target.into(),
+            engine: Engine::Tectonic,
+            args: Engine::Tectonic.default_args(),
+            has_biblatex: false,
+            bib_tool: None,
+            last_bcf_hash: String::new(),
+            last_bib_hash: String::new(),
+            toolchain: Toolchain::locate(),
+            sources: Vec::new(),
+            timeout: None,
+            control: Arc::new(BuildControl::default()),
+            hooks: BuildHooks::default(),
+            out_dir: None,
+        }
+    }
+
+    pub fn with_engine(mut self, settings: &EngineSettings) -> Self {
+        self.engine = settings.engine;
+        self.args = settings.args_for(settings.engine);
+        self
+    }
+
+    // Folder holding the root document. Engines run here so relative \input
+    // and \includegraphics paths resolve as usual.
+    pub fn source_dir(&self) -> &Path {
+        match self.target.parent() {
+            Some(p) if !p.as_os_str().is_empty() => p,
+            _ => Path::new("."),
+        }
+    }
+
+    // Where the PDF, log and auxiliary files end up
+    pub fn output_dir(&self) -> &Path {
+        self.out_dir.as_deref().unwrap_or(self.source_dir())
+    }
+
+    fn separate_output(&self) -> bool {
+        self.output_dir() != self.source_dir()
+    }
+
+    // Creates the build directory, mirroring source subfolders that hold .tex
+    // files: \include writes its .aux next to where the file would be
+    fn prepare_output_dir(&self) -> std::io::Result<()> {
+        if !self.separate_output() {
+            return Ok(());
+        }
+        std::fs::create_dir_all(self.output_dir())?;
+        for dir in tex_subdirs(self.source_dir(), self.output_dir()) {
+            if let Ok(relative) = dir.strip_prefix(self.source_dir()) {
+                std::fs::create_dir_all(self.output_dir().join(relative))?;
+            }
+        }
+        Ok(())
+    }
+
+    fn uses_biber(&self) -> bool {
+        self.bib_tool.map_or(self.has_biblatex, |tool| tool == BibTool::Biber)
+    }
+
+    fn stem(&self) -> String {
+        self.target.file_stem().unwrap_or_default().to_string_lossy().to_string()
+    }
+
+    // Runs the configured engine (plus any bibliography/index passes it needs),
+    // sending progress to `tx` and calling `notify` after every message. The
+    // final Success/Error/Cancelled message is sent before returning.
+    pub fn run(&self, tx: &Sender<CompilationMsg>, notify: &dyn Fn()) -> CompileResult {
+        let send = |msg: CompilationMsg| {
+            let _ = tx.send(msg);
+            notify();
+        };
+
+        let mut result = CompileResult::default();
+        for (path, content) in &self.sources {
+            if let Err(e) = std::fs::write(path, content) {
+                let err = format!("Write error: {}", e);
+                result.error = Some(err.clone());
+                send(CompilationMsg::Error(err));
+                return result;
+            }
+        }
+
+        if let Err(e) = self.prepare_output_dir() {
+            let err = format!("Cannot create build directory {}: {}", self.output_dir().display(), e);
+            result.error = Some(err.clone());
+            send(CompilationMsg::Error(err));
+            return result;
+        }
+
+        self.control.start(self.timeout);
+        let started = std::time::SystemTime::now();
+        let output = match self.run_hooks(&send, "before_build", &self.hooks.before_build) {
+            // A failed hook is reported like a failed engine run
+            Ok(Some(failed)) => Ok(failed),
+            Ok(None) => match self.engine {
+                Engine::Tectonic => self.run_tectonic_build(&send, &mut result),
+                Engine::Latexmk => {
+                    send(CompilationMsg::Log("Compiling document with latexmk...".to_string()));
+                    self.run_engine()
+                }
+                _ => self.run_engine_cycle(&send, &mut result),
+            },
+            Err(e) => Err(e),
+        };
+        // A helper tool may have been the one interrupted
+        let output = match self.control.interruption() {
+            Some(err) => Err(err),
+            None => output,
+        };
+
+        if let Ok(out) = &output {
+            let log_path = self.output_dir().join(format!("{}.log", self.stem()));
+            if let Some(log) = read_fresh_log(&log_path, started) {
+                result.diagnostics = crate::texlog::parse_log(&log, &self.target);
+            }
+            // Without a log (or when it stops short of the failure) fall back to the console output
+            if result.diagnostics.is_empty() || (!out.status.success() && !result.diagnostics.iter().any(|d| d.severity == Severity::Error)) {
+                let stdout = String::from_utf8_lossy(&out.stdout);
+                let stderr = String::from_utf8_lossy(&out.stderr);
+                result.diagnostics.extend(parse_diagnostics(&stdout, self.source_dir()));
+                result.diagnostics.extend(parse_diagnostics(&stderr, self.source_dir()));
+            }
+            if !result.diagnostics.is_empty() {
+                send(CompilationMsg::Diagnostics(result.diagnostics.clone()));
+            }
+        }
+
+        match output {
+            Ok(output) if output.status.success() => {
+                let pdf_path = self.output_dir().join(format!("{}.pdf", self.stem()));
+                if !pdf_path.exists() {
+                    result.error = Some("PDF file not found after compilation".to_string());
+                } else {
+                    match self.run_hooks(&send, "after_build", &self.hooks.after_build) {
+                        // A failing after_build hook does not fail the build, but a Stop or timeout while it runs does
+                        Err(e) if is_interruption(&e) => self.record_interruption(&e, &mut result),
+                        _ => {
+                            result.pdf_path = Some(pdf_path.clone());
+                            send(CompilationMsg::Success(pdf_path, result.bcf_hash.clone(), result.bib_hash.clone()));
+                        }
+                    }
+                }
+            }
+            Ok(output) => {
+                result.error = Some(format!(
+                    "Compilation failed with code: {}\n\nSTDOUT:\n{}\n\nSTDERR:\n{}",
+                    output.status.code().unwrap_or(-1),
+                    String::from_utf8_lossy(&output.stdout),
+                    String::from_utf8_lossy(&output.stderr)
+                ));
+            }
+            Err(e) if is_interruption(&e) => self.record_interruption(&e, &mut result),
+            Err(e) => {
+                result.error = Some(format!("Failed to run {}: {}", self.engine.program(), e));
+            }
+        }
+
+        if result.cancelled {
+            send(CompilationMsg::Cancelled);
+        } else if let Some(err) = &result.error {
+            send(CompilationMsg::Error(err.clone()));
+        }
+        result
+    }
+
+    fn run_tectonic_build(&self, send: &dyn Fn(CompilationMsg), result: &mut CompileResult) -> std::io::Result<Output> {
+        // Optimistic First Pass (Full Convergence)
+        // We run Tectonic fully. Most of the time, this is all we need.
+        // If we detect that Biber was needed (citations changed), we run it and then re-run Tectonic.
+        send(CompilationMsg::Log("Compiling document...".to_string()));
+        let output = self.run_engine();
+
+        if output.as_ref().map(|o| o.status.success()).unwrap_or(false) && self.process_biblatex(send, result) {
+            // Run Tectonic again to incorporate bibliography
+            send(CompilationMsg::Log("Re-compiling document to link citations...".to_string()));
+            return self.run_engine();
+        }
+        output
+    }
+
+    // Classic engine -> bibtex/biber -> makeindex -> engine cycle, rerunning
+    // while the log reports unresolved references
+    fn run_engine_cycle(&self, send: &dyn Fn(CompilationMsg), result: &mut CompileResult) -> std::io::Result<Output> {
+        send(CompilationMsg::Log(format!("Compiling document with {}...", self.engine.name())));
+        let mut output = self.run_engine()?;
+        if !output.status.success() {
+            return Ok(output);
+        }
+
+        let stem = self.stem();
+        let dir = self.output_dir();
+        let mut rerun = self.process_biblatex(send, result);
+
+        if !self.uses_biber() && aux_needs_bibtex(&dir.join(format!("{}.aux", stem))) {
+            send(CompilationMsg::Log("Processing bibliography with BibTeX...".to_string()));
+            let mut cmd = self.aux_command(&self.toolchain.bibtex);
+            cmd.arg(&stem);
+            self.run_aux_tool(send, cmd, "BibTeX");
+            rerun = true;
+        }
+
+        if dir.join(format!("{}.idx", stem)).exists() {
+            send(CompilationMsg::Log("Building index with makeindex...".to_string()));
+            let mut cmd = self.aux_command(&self.toolchain.makeindex);
+            cmd.arg(format!("{}.idx", stem));
+            self.run_aux_tool(send, cmd, "makeindex");
+            rerun = true;
+        }
+
+        let mut passes = 0;
+        while (rerun || log_requests_rerun(&dir.join(format!("{}.log", stem)))) && passes < MAX_RERUNS {
+            send(CompilationMsg::Log("Re-compiling document to resolve references...".to_string()));
+            output = self.run_engine()?;
+            if !output.status.success() {
+                break;
+            }
+            rerun = false;
+            passes += 1;
+        }
+        Ok(output)
+    }
+
+    // Runs biber when the citations or .bib files changed since the last
+    // build. Returns true if biber ran and the engine must run again.
+    fn process_biblatex(&self, send: &dyn Fn(CompilationMsg), result: &mut CompileResult) -> bool {
+        let bcf_path = self.output_dir().join(format!("{}.bcf", self.stem()));
+        if !self.uses_biber() || !bcf_path.exists() {
+            return false;
+        }
+
+        result.bcf_hash = hash_file(&bcf_path);
+        result.bib_hash = hash_bib_files(self.source_dir());
+
+        // If hashes match previous run, we assume bibliography is stable.
+        if result.bcf_hash == self.last_bcf_hash && result.bib_hash == self.last_bib_hash && !result.bcf_hash.is_empty() {
+            send(CompilationMsg::Log("Citations unchanged.".to_string()));
+            return false;
+        }
+
+        send(CompilationMsg::Log("Citations changed. Processing bibliography with Biber...".to_string()));
+        let mut cmd = tool_command(&self.toolchain.biber);
+        if self.separate_output() {
+            // The .bcf is in the build directory; .bib paths stay relative to the sources
+            cmd.current_dir(self.source_dir())
+                .arg("--input-directory")
+                .arg(self.output_dir())
+                .arg("--output-directory")
+                .arg(self.output_dir());
+        } else {
+            cmd.current_dir(self.output_dir());
+        }
+        cmd.arg(self.stem());
+        self.run_aux_tool(send, cmd, "Biber")
+    }
+
+    // A bibtex/makeindex command running in the output directory, still able
+    // to find .bib and .bst files next to the sources
+    fn aux_command(&self, program: &Path) -> Command {
+        let mut cmd = tool_command(program);
+        cmd.current_dir(self.output_dir());
+        if self.separate_output() {
+            // The trailing separator keeps the default search path
+            let sep = if cfg!(windows) { ";" } else { ":" };
+            let search = format!("{}{}", self.source_dir().display(), sep);
+            cmd.env("BIBINPUTS", &search).env("BSTINPUTS", &search);
+        }
+        cmd
+    }
+
+    // Runs a bibliography/index helper. Failures are logged but do not stop
+    // the build; returns false if it could not start.
+    fn run_aux_tool(&self, send: &dyn Fn(CompilationMsg), cmd: Command, name: &str) -> bool {
+        match self.execute(cmd) {
+            Ok(out) => {
+                if !out.status.success() {
+                    let err = String::from_utf8_lossy(&out.stderr);
+                    send(CompilationMsg::Log(format!("{} warning/error: {}", name, err)));
+                }
+                true
+            }
+            Err(_) => {
+                send(CompilationMsg::Log(format!("Failed to execute {}.", name)));
+                false
+            }
+        }
+    }
+
+    // Runs `commands` in order, stopping at the first failure. Returns the
+    // failed command's output, or None if all of them succeeded.
+    // Marks `result` as stopped by the user or by the timeout
+    fn record_interruption(&self, err: &std::io::Error, result: &mut CompileResult) {
+        if err.kind() == std::io::ErrorKind::TimedOut {
+            let secs = self.timeout.unwrap_or_default().as_secs();
+            result.error = Some(format!("Compilation timed out after {} seconds", secs));
+        } else {
+            result.cancelled = true;
+            result.error = Some("Compilation cancelled".to_string());
+        }
+    }
+
+    fn run_hooks(&self, send: &dyn Fn(CompilationMsg), stage: &str, commands: &[String]) -> std::io::Result<Option<Output>> {
+        let pdf_path = self.output_dir().join(format!("{}.pdf", self.stem()));
+        for command in commands {
+            send(CompilationMsg::Log(format!("Running {} hook: {}", stage, command)));
+            let mut cmd = shell_command(command);
+            cmd.current_dir(self.source_dir()).env("TYPESAFE_ROOT", &self.target).env("TYPESAFE_PDF", &pdf_path);
+            let output = self.execute(cmd)?;
+            if !output.status.success() {
+                send(CompilationMsg::Log(format!("{} hook failed: {}\n{}", stage, command, String::from_utf8_lossy(&output.stderr))));
+                return Ok(Some(output));
+            }
+        }
+        Ok(None)
+    }
+
+    fn run_engine(&self) -> std::io::Result<Output> {
+        let mut cmd = tool_command(self.toolchain.engine(self.engine));
+        cmd.current_dir(self.source_dir());
+        let out_dir = self.out_dir.as_ref().filter(|_| self.separate_output());
+        match self.engine {
+            // Tectonic takes options after the input file
+            Engine::Tectonic => {
+                cmd.arg(&self.target).args(&self.args);
+                if let Some(out) = out_dir {
+                    cmd.arg("--outdir").arg(out);
+                }
+            }
+            // TeX engines run inside the source dir, so pass the bare file name
+            _ => {
+                cmd.args(&self.args);
+                if let Some(out) = out_dir {
+                    let flag = if self.engine == Engine::Latexmk { "-outdir" } else { "-output-directory" };
+                    cmd.arg(format!("{}={}", flag, out.display()));
+                }
+                cmd.arg(self.target.file_name().unwrap_or_default());
+            }
+        }
+        self.execute(cmd)
+    }
+
+    // Like `Command::output`, but kills the child when the job is cancelled or
+    // runs past its deadline
+    fn execute(&self, mut cmd: Command) -> std::io::Result<Output> {
+        if let Some(err) = self.control.interruption() {
+            return Err(err);
+        }
+
+        let mut child = cmd.spawn()?;
+        // Drain the pipes on their own threads so a chatty tool cannot block on a full pipe
+        let drain = |pipe: Option<Box<dyn Read + Send>>| {
+            std::thread::spawn(move || {
+                let mut buf = Vec::new();
+                if let Some(mut pipe) = pipe {
+                    let _ = pipe.read_to_end(&mut buf);
+                }
+                buf
+            })
+        };
+        let stdout = drain(child.stdout.take().map(|p| Box::new(p) as Box<dyn Read + Send>));
+        let stderr = drain(child.stderr.take().map(|p| Box::new(p) as Box<dyn Read + Send>));
+
+        loop {
+            if let Some(status) = child.try_wait()? {
+                return Ok(Output {
+                    status,
+                    stdout: stdout.join().unwrap_or_default(),
+                    stderr: stderr.join().unwrap_or_default(),
+                });
+            }
+            if let Some(err) = self.control.interruption() {
+                kill_tree(&mut child);
+                let _ = child.wait();
+                return Err(err);
+            }
+            std::thread::sleep(POLL_INTERVAL);
+        }
+    }
+}
+
+// Build Manager
+//
+// Runs one job at a time on a worker thread. Requests made while a build is
+// running wait behind it, and a newer request replaces an older waiting one,
+// so bursts of saves only build the latest state.
+
+#[derive(Default)]
+struct BuildQueue {
+    running: Option<Arc<BuildControl>>,
+    pending: Option<CompileJob>,
+}
+
+pub struct BuildManager {
+    tx: Sender<CompilationMsg>,
+    queue: Arc<Mutex<BuildQueue>>,
+}
+
+impl BuildManager {
+    pub fn new(tx: Sender<CompilationMsg>) -> Self {
+        Self {
+            tx,
+            queue: Arc::new(Mutex::new(BuildQueue::default())),
+        }
+    }
+
+    pub fn is_running(&self) -> bool {
+        self.queue.lock().unwrap().running.is_some()
+    }
+
+    pub fn request(&self, job: CompileJob, notify: impl Fn() + Send + 'static) {
+        let mut queue = self.queue.lock().unwrap();
+        if queue.running.is_some() {
+            if queue.pending.replace(job).is_none() {
+                let _ = self.tx.send(CompilationMsg::Log("Build queued until the current one finishes.".to_string()));
+                notify();
+            }
+            return;
+        }
+        queue.running = Some(job.control.clone());
+        drop(queue);
+
+        let tx = self.tx.clone();
+        let queue = self.queue.clone();
+        std::thread::spawn(move || {
+            let mut job = job;
+            loop {
+                let _ = tx.send(CompilationMsg::Start);
+                notify();
+                job.run(&tx, &notify);
+
+                let mut queue = queue.lock().unwrap();
+                match queue.pending.take() {
+                    Some(next) => {
+                        queue.running = Some(next.control.clone());
+                        job = next;
+                    }
+                    None => {
+                        queue.running = None;
+                        break;
+                    }
+                }
+            }
+        });
+    }
+
+    // Stops the running build (killing its tools) and drops any waiting one.
+    // Returns false if nothing was running.
+    pub fn cancel(&self) -> bool {
+        let mut queue = self.queue.lock().unwrap();
+        queue.pending = None;
+        match &queue.running {
+            Some(control) => {
+                control.cancel();
+                true
+            }
+            None => false,
+        }
+    }
+}
+
+// How `execute` reports a Stop or a timeout
+fn is_interruption(err: &std::io::Error) -> bool {
+    matches!(err.kind(), std::io::ErrorKind::Interrupted | std::io::ErrorKind::TimedOut)
+}
+
+// Piped, windowless command for a build tool. On Unix it leads its own
+// process group, so whatever it starts can be stopped along with it.
+fn tool_command(program: &Path) -> Command {
+    let mut cmd = Command::new(program);
+    cmd.stdout(Stdio::piped()).stderr(Stdio::piped());
+
+    #[cfg(unix)]
+    {
+        use std::os::unix::process::CommandExt;
+        cmd.process_group(0);
+    }
+    #[cfg(windows)]
+    {
+        use std::os::windows::process::CommandExt;
+        const CREATE_NO_WINDOW: u32 = 0x08000000;
+        cmd.creation_flags(CREATE_NO_WINDOW);
+    }
+    cmd
+}
+
+// Stops a tool started by `tool_command` and the processes it started, such as
+// the engine runs under latexmk or a hook's commands
+fn kill_tree(child: &mut Child) {
+    #[cfg(unix)]
+    // SAFETY: plain syscall; the group keeps the child's id, which is not
+    // reused before the child is waited for
+    unsafe {
+        libc::kill(-(child.id() as libc::pid_t), libc::SIGKILL);
+    }
+    #[cfg(windows)]
+    {
+        let _ = tool_command(Path::new("taskkill")).args(["/F", "/T", "/PID", &child.id().to_string()]).output();
+    }
+    let _ = child.kill();
+}
+
+// Runs a hook command line through the platform shell
+fn shell_command(command: &str) -> Command {
+    let mut cmd = if cfg!(windows) { tool_command(Path::new("cmd")) } else { tool_command(Path::new("sh")) };
+    cmd.arg(if cfg!(windows) { "/C" } else { "-c" }).arg(command);
+    cmd
+}
+
+pub fn hash_file(path: &Path) -> String {
+    if let Ok(mut file) = std::fs::File::open(path) {
+        let mut hasher = Sha256::new();
+        if std::io::copy(&mut file, &mut hasher).is_ok() {
+            return format!("{:x}", hasher.finalize());
+        }
+    }
+    String::new()
+}
+
+// Combined hash of every .bib file in `dir`, in name order
+pub fn hash_bib_files(dir: &Path) -> String {
+    let mut hasher = Sha256::new();
+    if let Ok(entries) = std::fs::read_dir(dir) {
+        let mut paths: Vec<_> = entries
+            .filter_map(|e| e.ok())
+            .map(|e| e.path())
+            .filter(|p| p.extension().is_some_and(|e| e == "bib"))
+            .collect();
+        paths.sort();
+        for p in paths {
+            if let Ok(bytes) = std::fs::read(&p) {
+                hasher.update(&bytes);
+            }
+        }
+    }
+    format!("{:x}", hasher.finalize())
+}
+
+// Auxiliary Files
+
+// Suffixes of files written by the engines and helpers; never the PDF
+pub const AUX_SUFFIXES: &[&str] = &[
+    ".aux", ".bbl", ".bcf", ".blg", ".fdb_latexmk", ".fls", ".idx", ".ilg", ".ind", ".lof", ".log", ".lot", ".nav", ".out",
+    ".run.xml", ".snm", ".synctex", ".synctex.gz", ".synctex(busy)", ".toc", ".vrb", ".xdv", "-blx.bib",
+];
+
+// Subfolders too deep to be part of a document
+const MAX_SCAN_DEPTH: usize = 4;
+
+fn aux_stem(name: &str) -> Option<&str> {
+    AUX_SUFFIXES.iter().find_map(|suffix| name.strip_suffix(suffix)).filter(|stem| !stem.is_empty())
+}
+
+// Visible subfolders of `dir` (at any depth, excluding `skip`) that hold .tex files
+fn tex_subdirs(dir: &Path, skip: &Path) -> Vec<PathBuf> {
+    fn walk(dir: &Path, skip: &Path, depth: usize, found: &mut Vec<PathBuf>) {
+        let Ok(entries) = std::fs::read_dir(dir) else { return };
+        for path in entries.filter_map(|e| e.ok()).map(|e| e.path()) {
+            let hidden = path.file_name().is_some_and(|n| n.to_string_lossy().starts_with('.'));
+            if !path.is_dir() || hidden || path == skip || depth >= MAX_SCAN_DEPTH {
+                continue;
+            }
+            let has_tex = std::fs::read_dir(&path).is_ok_and(|mut e| e.any(|e| e.is_ok_and(|e| e.path().extension().is_some_and(|x| x == "tex"))));
+            if has_tex {
+                found.push(path.clone());
+            }
+            walk(&path, skip, depth + 1, found);
+        }
+    }
+    let mut found = Vec::new();
+    walk(dir, skip, 0, &mut found);
+    found
+}
+
+// Deletes build leftovers: `name.<aux>` files next to a `name.tex` of the
+// project, or in the matching folder of a separate `out_dir`. An `out_dir`
+// outside `source_dir` is refused rather than searched. Returns how many files
+// were removed.
+pub fn clean_aux_files(source_dir: &Path, out_dir: Option<&Path>) -> std::io::Result<usize> {
+    let source_dir = source_dir.canonicalize()?;
+    let out_dir = match out_dir {
+        // Nothing was built there yet
+        Some(out) if !out.exists() => return Ok(0),
+        Some(out) => out.canonicalize()?,
+        None => source_dir.clone(),
+    };
+    if !out_dir.starts_with(&source_dir) {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::InvalidInput,
+            format!("the build directory {} is outside the project folder {}", out_dir.display(), source_dir.display()),
+        ));
+    }
+
+    // Folders holding the project's .tex files, relative to source_dir
+    let skip = if out_dir == source_dir { PathBuf::new() } else { out_dir.clone() };
+    let tex_dirs: Vec<PathBuf> = std::iter::once(PathBuf::new())
+        .chain(tex_subdirs(&source_dir, &skip).into_iter().filter_map(|d| d.strip_prefix(&source_dir).ok().map(Path::to_path_buf)))
+        .collect();
+
+    let mut removed = 0;
+    for rel in tex_dirs {
+        let Ok(entries) = std::fs::read_dir(out_dir.join(&rel)) else { continue };
+        for path in entries.filter_map(|e| e.ok()).map(|e| e.path()).filter(|p| p.is_file()) {
+            let name = path.file_name().unwrap_or_default().to_string_lossy().to_string();
+            let Some(stem) = aux_stem(&name) else { continue };
+            if !source_dir.join(&rel).join(format!("{}.tex", stem)).is_file() {
+                continue;
+            }
+            std::fs::remove_file(&path)?;
+            removed += 1;
+        }
+    }
+    Ok(removed)
+}
+
+// Classic BibTeX documents write \bibdata to the .aux file
+fn aux_needs_bibtex(aux_path: &Path) -> bool {
+    std::fs::read_to_string(aux_path).is_ok_and(|aux| aux.contains("\\bibdata"))
+}
+
+fn log_requests_rerun(log_path: &Path) -> bool {
+    const RERUN_HINTS: &[&str] = &["Rerun to get", "Label(s) may have changed", "Please rerun LaTeX"];
+    std::fs::read_to_string(log_path).is_ok_and(|log| RERUN_HINTS.iter().any(|h| log.contains(h)))
+}
+
+// The build's log, unless it predates this build (e.g. the engine never started)
+fn read_fresh_log(log_path: &Path, started: std::time::SystemTime) -> Option<String> {
+    let modified = std::fs::metadata(log_path).and_then(|m| m.modified()).ok()?;
+    // Allow for coarse filesystem timestamps
+    if modified + std::time::Duration::from_secs(2) < started {
+        return None;
+    }
+    std::fs::read_to_string(log_path).ok()
+}
+
+// Parses `file:line: message` lines from console output, as printed by Tectonic
+// ("error: "/"warning: " prefix) and by TeX engines run with -file-line-error.
+// Relative paths are resolved against `base_dir`.
+pub fn parse_diagnostics(output: &str, base_dir: &Path) -> Vec<Diagnostic> {
+    let message_regex = regex::Regex::new(r"^(?:(error|warning): )?((?:[A-Za-z]:)?[^:\n]+?):(\d+): (.*)$").unwrap();
+    output
+        .lines()
+        .filter_map(|line| {
+            let caps = message_regex.captures(line)?;
+            let line_num = caps[3].parse::<usize>().ok()?;
+            let file = Path::new(&caps[2]);
+            let file = if file.is_relative() { base_dir.join(file.strip_prefix(".").unwrap_or(file)) } else { file.to_path_buf() };
+            let severity = match caps.get(1).map(|m| m.as_str()) {
+                Some("warning") if caps[4].starts_with("Overfull") || caps[4].starts_with("Underfull") => Severity::Badbox,
+                Some("warning") => Severity::Warning,
+                _ => Severity::Error,
+            };
+            Some(Diagnostic {
+                severity,
+                line: line_num,
+                column: None,
+                message: caps[4].to_string(),
+                file: file.to_string_lossy().to_string(),
+            })
+        })
+        .collect()
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+    use crossbeam_channel::unbounded;
+    use std::os::unix::fs::PermissionsExt;
+
+    // Fresh scratch directory per test
+    fn scratch_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("typesafe-compiler-{}-{}", name, std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn write_script(path: &Path, body: &str) {
+        std::fs::write(path, format!("#!/bin/sh\n{}", body)).unwrap();
+        std::fs::set_permissions(path, std::fs::Permissions::from_mode(0o755)).unwrap();
+    }
+
+    // Fake tectonic: fails on a FAIL marker, writes a .bcf for biblatex documents,
+    // honours --outdir, and records every invocation in tectonic.runs
+    const FAKE_TECTONIC: &str = r#"
+src="$1"
+stem=$(basename "$src" .tex)
+out=.
+while [ $# -gt 0 ]; do
+    [ "$1" = "--outdir" ] && out="$2"
+    shift
+done
+echo run >> tectonic.runs
+head -n 1 "$src" >> tectonic.inputs
+if grep -q HANG "$src"; then
+    (sleep 1; echo survived > survived) &
+    sleep 5
+fi
+if grep -q FAIL "$src"; then
+    echo "error: $src:7: Undefined control sequence" >&2
+    exit 1
+fi
+if grep -q biblatex "$src"; then
+    grep cite "$src" > "$out/$stem.bcf"
+fi
+echo "%PDF" > "$out/$stem.pdf"
+"#;
+
+    // Fake TeX engine (options first, file last): writes \bibdata to the .aux
+    // for \bibliography, an .idx for \makeindex, warns about \ref, and asks
+    // for one rerun when the document has labels. Outputs go to -output-directory.
+    const FAKE_LATEX: &str = r#"
+out=.
+for file; do
+    case "$file" in -output-directory=*) out="${file#-output-directory=}" ;; esac
+done
+stem="$out/$(basename "$file" .tex)"
+echo "$*" >> latex.runs
+if grep -q FAIL "$file"; then
+    echo "./$file:3: Undefined control sequence."
+    exit 1
+fi
+: > "$stem.aux"
+if grep -q bibliography "$file"; then
+    printf '%s\n' '\bibdata{refs}' >> "$stem.aux"
+fi
+if grep -q makeindex "$file"; then
+    : > "$stem.idx"
+fi
+if grep -q '\\ref{' "$file"; then
+    printf '%s\n' "LaTeX Warning: Reference \`missing' on page 1 undefined on input line 2." > "$stem.log"
+elif grep -q label "$file" && [ ! -e "$stem.log" ]; then
+    echo "LaTeX Warning: Label(s) may have changed. Rerun to get cross-references right." > "$stem.log"
+else
+    echo "Output written on $stem.pdf" > "$stem.log"
+fi
+echo "%PDF" > "$stem.pdf"
+"#;
+
+    const FAKE_BIBER: &str = r#"
+echo "$*" >> biber.runs
+"#;
+
+    // Shared by the fake bibtex/makeindex/latexmk: log the arguments (and
+    // BIBINPUTS, when set), and produce a PDF when invoked as latexmk
+    const FAKE_TOOL: &str = r#"
+echo "$*${BIBINPUTS:+ BIBINPUTS=$BIBINPUTS}" >> "$(basename "$0").runs"
+out=.
+for file; do
+    case "$file" in -outdir=*) out="${file#-outdir=}" ;; esac
+done
+case "$file" in *.tex) echo "%PDF" > "$out/$(basename "$file" .tex).pdf" ;; esac
+"#;
+
+    fn job_in(dir: &Path, source: &str) -> CompileJob {
+        let script = |name: &str, body: &str| {
+            let path = dir.join(name);
+            write_script(&path, body);
+            path
+        };
+        let latex = script("latex", FAKE_LATEX);
+        let toolchain = Toolchain {
+            tectonic: script("tectonic", FAKE_TECTONIC),
+            latexmk: script("latexmk", FAKE_TOOL),
+            pdflatex: latex.clone(),
+            xelatex: latex.clone(),
+            lualatex: latex,
+            bibtex: script("bibtex", FAKE_TOOL),
+            biber: script("biber", FAKE_BIBER),
+            makeindex: script("makeindex", FAKE_TOOL),
+        };
+
+        let target = dir.join("main.tex");
+        std::fs::write(&target, source).unwrap();
+
+        let mut job = CompileJob::new(target);
+        job.has_biblatex = source.contains("biblatex");
+        job.toolchain = toolchain;
+        job
+    }
+
+    fn runs(dir: &Path, file: &str) -> Vec<String> {
+        std::fs::read_to_string(dir.join(file)).map(|s| s.lines().map(str::to_string).collect()).unwrap_or_default()
+    }
+
+    fn run_collect(job: &CompileJob) -> (CompileResult, Vec<CompilationMsg>) {
+        let (tx, rx) = unbounded();
+        let result = job.run(&tx, &|| {});
+        drop(tx);
+        (result, rx.iter().collect())
+    }
+
+    #[test]
+    fn successful_build_reports_pdf() {
+        let dir = scratch_dir("success");
+        let job = job_in(&dir, "\\documentclass{article}\n\\begin{document}Hi\\end{document}\n");
+        let (result, msgs) = run_collect(&job);
+
+        assert!(result.succeeded());
+        assert_eq!(result.pdf_path, Some(dir.join("main.pdf")));
+        assert!(matches!(msgs.last(), Some(CompilationMsg::Success(p, _, _)) if p == &dir.join("main.pdf")));
+        assert_eq!(runs(&dir, "tectonic.runs").len(), 1);
+        assert_eq!(runs(&dir, "biber.runs").len(), 0);
+    }
+
+    #[test]
+    fn failed_build_reports_diagnostics_and_error() {
+        let dir = scratch_dir("failure");
+        let job = job_in(&dir, "FAIL\n");
+        let (result, msgs) = run_collect(&job);
+
+        assert!(!result.succeeded());
+        assert_eq!(result.diagnostics.len(), 1);
+        assert_eq!(result.diagnostics[0].line, 7);
+        assert_eq!(result.diagnostics[0].message, "Undefined control sequence");
+        assert!(msgs.iter().any(|m| matches!(m, CompilationMsg::Diagnostics(d) if d.len() == 1)));
+        assert!(matches!(msgs.last(), Some(CompilationMsg::Error(e)) if e.contains("failed with code: 1")));
+    }
+
+    #[test]
+    fn biber_runs_only_when_citations_change() {
+        let dir = scratch_dir("biber");
+        std::fs::write(dir.join("refs.bib"), "@book{knuth, title={TAOCP}}\n").unwrap();
+        let mut job = job_in(&dir, "\\usepackage{biblatex}\n\\cite{knuth}\n");
+
+        let (first, _) = run_collect(&job);
+        assert!(first.succeeded());
+        assert!(!first.bcf_hash.is_empty());
+        assert_eq!(runs(&dir, "biber.runs").len(), 1);
+        assert_eq!(runs(&dir, "tectonic.runs").len(), 2);
+
+        job.last_bcf_hash = first.bcf_hash.clone();
+        job.last_bib_hash = first.bib_hash.clone();
+        let (second, msgs) = run_collect(&job);
+        assert!(second.succeeded());
+        assert_eq!(runs(&dir, "biber.runs").len(), 1);
+        assert_eq!(runs(&dir, "tectonic.runs").len(), 3);
+        assert!(msgs.iter().any(|m| matches!(m, CompilationMsg::Log(l) if l == "Citations unchanged.")));
+
+        std::fs::write(dir.join("refs.bib"), "@book{knuth, title={TAOCP, Vol. 2}}\n").unwrap();
+        let (third, _) = run_collect(&job);
+        assert!(third.succeeded());
+        assert_ne!(third.bib_hash, first.bib_hash);
+        assert_eq!(runs(&dir, "biber.runs").len(), 2);
+    }
+
+    #[test]
+    fn missing_tectonic_is_an_error() {
+        let dir = scratch_dir("missing");
+        let mut job = job_in(&dir, "Hello\n");
+        job.toolchain.tectonic = dir.join("does-not-exist");
+        let (result, msgs) = run_collect(&job);
+
+        assert!(!result.succeeded());
+        assert!(result.error.unwrap().starts_with("Failed to run tectonic"));
+        assert!(matches!(msgs.last(), Some(CompilationMsg::Error(_))));
+    }
+
+    #[test]
+    fn cancel_kills_the_running_tool() {
+        let dir = scratch_dir("cancel");
+        let job = job_in(&dir, "HANG\n");
+        let control = job.control.clone();
+        let started = Instant::now();
+        std::thread::spawn(move || {
+            std::thread::sleep(Duration::from_millis(200));
+            control.cancel();
+        });
+        let (result, msgs) = run_collect(&job);
+
+        assert!(started.elapsed() < Duration::from_secs(3));
+        assert!(result.cancelled);
+        assert!(matches!(msgs.last(), Some(CompilationMsg::Cancelled)));
+
+        // The tool's own children went with it
+        std::thread::sleep(Duration::from_millis(1500));
+        assert!(!dir.join("survived").exists());
+    }
+
+    #[test]
+    fn timeout_stops_a_stuck_build() {
+        let dir = scratch_dir("timeout");
+        let mut job = job_in(&dir, "HANG\n");
+        job.timeout = Some(Duration::from_millis(200));
+        let started = Instant::now();
+        let (result, msgs) = run_collect(&job);
+
+        assert!(started.elapsed() < Duration::from_secs(3));
+        assert!(!result.cancelled);
+        assert!(result.error.unwrap().contains("timed out"));
+        assert!(matches!(msgs.last(), Some(CompilationMsg::Error(_))));
+    }
+
+    #[test]
+    fn manager_runs_only_the_newest_waiting_request() {
+        let dir = scratch_dir("queue");
+        let (tx, rx) = unbounded();
+        let manager = BuildManager::new(tx);
+        let jobs: Vec<_> = ["HANG", "second", "third"]
+            .iter()
+            .map(|first_line| {
+                let mut job = job_in(&dir, "");
+                job.sources.push((dir.join("main.tex"), format!("{}\n", first_line)));
+                job.timeout = Some(Duration::from_millis(500));
+                job
+            })
+            .collect();
+        for job in jobs {
+            manager.request(job, || {});
+        }
+
+        let mut finished = 0;
+        while finished < 2 {
+            match rx.recv_timeout(Duration::from_secs(5)).expect("build did not finish") {
+                CompilationMsg::Success(..) | CompilationMsg::Error(_) | CompilationMsg::Cancelled => finished += 1,
+                _ => {}
+            }
+        }
+        while manager.is_running() {
+            std::thread::sleep(Duration::from_millis(10));
+        }
+        assert_eq!(runs(&dir, "tectonic.inputs"), vec!["HANG", "third"]);
+        assert!(!manager.cancel());
+    }
+
+    #[test]
+    fn engine_args_precede_the_file() {
+        let dir = scratch_dir("args");
+        let job = job_in(&dir, "Hello\n").with_engine(&EngineSettings { engine: Engine::Pdflatex, ..Default::default() });
+        assert!(run_collect(&job).0.succeeded());
+        assert_eq!(runs(&dir, "latex.runs"), vec!["-interaction=nonstopmode -synctex=1 -file-line-error main.tex"]);
+
+        let mut settings = EngineSettings { engine: Engine::Latexmk, ..Default::default() };
+        settings.args.insert(Engine::Latexmk, "-pdflua  -shell-escape".to_string());
+        let job = job_in(&dir, "Hello\n").with_engine(&settings);
+        assert!(run_collect(&job).0.succeeded());
+        assert_eq!(runs(&dir, "latexmk.runs"), vec!["-pdflua -shell-escape main.tex"]);
+    }
+
+    #[test]
+    fn engine_cycle_runs_bibtex_and_makeindex() {
+        let dir = scratch_dir("cycle");
+        let job = job_in(&dir, "\\makeindex\n\\bibliography{refs}\n").with_engine(&EngineSettings { engine: Engine::Xelatex, ..Default::default() });
+        let (result, _) = run_collect(&job);
+
+        assert!(result.succeeded());
+        assert_eq!(runs(&dir, "bibtex.runs"), vec!["main"]);
+        assert_eq!(runs(&dir, "makeindex.runs"), vec!["main.idx"]);
+        assert_eq!(runs(&dir, "latex.runs").len(), 2);
+    }
+
+    #[test]
+    fn bib_tool_can_be_forced() {
+        let dir = scratch_dir("bibtool");
+        let mut job = job_in(&dir, "\\usepackage[backend=bibtex]{biblatex}\n\\bibliography{refs}\n").with_engine(&EngineSettings { engine: Engine::Pdflatex, ..Default::default() });
+        assert!(run_collect(&job).0.succeeded());
+        assert!(runs(&dir, "bibtex.runs").is_empty());
+
+        job.bib_tool = BibTool::from_name("BibTeX");
+        assert!(run_collect(&job).0.succeeded());
+        assert_eq!(runs(&dir, "bibtex.runs"), vec!["main"]);
+        assert!(runs(&dir, "biber.runs").is_empty());
+    }
+
+    #[test]
+    fn engine_cycle_reruns_until_references_settle() {
+        let dir = scratch_dir("rerun");
+        let job = job_in(&dir, "\\label{intro}\n").with_engine(&EngineSettings { engine: Engine::Lualatex, ..Default::default() });
+        assert!(run_collect(&job).0.succeeded());
+        assert_eq!(runs(&dir, "latex.runs").len(), 2);
+        assert!(runs(&dir, "bibtex.runs").is_empty());
+
+        std::fs::remove_file(dir.join("latex.runs")).unwrap();
+        let job = job_in(&dir, "No labels\n").with_engine(&EngineSettings { engine: Engine::Lualatex, ..Default::default() });
+        assert!(run_collect(&job).0.succeeded());
+        assert_eq!(runs(&dir, "latex.runs").len(), 1);
+    }
+
+    #[test]
+    fn engine_errors_become_diagnostics() {
+        let dir = scratch_dir("engine-failure");
+        let job = job_in(&dir, "FAIL\n").with_engine(&EngineSettings { engine: Engine::Pdflatex, ..Default::default() });
+        let (result, msgs) = run_collect(&job);
+
+        assert!(!result.succeeded());
+        assert_eq!(result.diagnostics.len(), 1);
+        assert_eq!(result.diagnostics[0].file, dir.join("main.tex").to_string_lossy());
+        assert_eq!(result.diagnostics[0].line, 3);
+        assert!(msgs.iter().any(|m| matches!(m, CompilationMsg::Diagnostics(_))));
+    }
+
+    #[test]
+    fn hooks_run_around_the_build() {
+        let dir = scratch_dir("hooks");
+        let mut job = job_in(&dir, "Hello\n");
+        job.hooks.before_build = vec!["echo before > hooks.log".to_string()];
+        job.hooks.after_build = vec!["test -e \"$TYPESAFE_PDF\" && echo after >> hooks.log".to_string(), "false".to_string()];
+        let (result, _) = run_collect(&job);
+
+        // A failing after_build hook does not fail the build
+        assert!(result.succeeded());
+        assert_eq!(runs(&dir, "hooks.log"), vec!["before", "after"]);
+
+        let mut job = job_in(&dir, "Hello\n");
+        std::fs::remove_file(dir.join("tectonic.runs")).unwrap();
+        job.hooks.before_build = vec!["echo broken >&2; exit 3".to_string()];
+        let (result, msgs) = run_collect(&job);
+
+        assert!(!result.succeeded());
+        assert!(runs(&dir, "tectonic.runs").is_empty());
+        assert!(msgs.iter().any(|m| matches!(m, CompilationMsg::Log(l) if l.starts_with("before_build hook failed"))));
+        assert!(result.error.unwrap().contains("failed with code: 3"));
+    }
+
+    #[test]
+    fn stopping_an_after_build_hook_cancels_the_build() {
+        let dir = scratch_dir("hook-cancel");
+        let mut job = job_in(&dir, "Hello\n");
+        job.hooks.after_build = vec!["sleep 5".to_string()];
+        let control = job.control.clone();
+        let started = Instant::now();
+        std::thread::spawn(move || {
+            std::thread::sleep(Duration::from_millis(300));
+            control.cancel();
+        });
+        let (result, msgs) = run_collect(&job);
+
+        assert!(started.elapsed() < Duration::from_secs(3));
+        assert!(result.cancelled);
+        assert!(result.pdf_path.is_none());
+        assert!(!msgs.iter().any(|m| matches!(m, CompilationMsg::Success(..))));
+        assert!(matches!(msgs.last(), Some(CompilationMsg::Cancelled)));
+    }
+
+    #[test]
+    fn separate_build_directory_keeps_sources_clean() {
+        let dir = scratch_dir("outdir");
+        let build = dir.join("build");
+        std::fs::create_dir_all(dir.join("chapters")).unwrap();
+        std::fs::write(dir.join("chapters/intro.tex"), "Intro\n").unwrap();
+
+        let mut job = job_in(&dir, "\\makeindex\n\\bibliography{refs}\n").with_engine(&EngineSettings { engine: Engine::Pdflatex, ..Default::default() });
+        job.out_dir = Some(build.clone());
+        let (result, _) = run_collect(&job);
+
+        assert_eq!(result.pdf_path, Some(build.join("main.pdf")));
+        assert!(build.join("chapters").is_dir());
+        assert!(!dir.join("main.aux").exists() && !dir.join("main.pdf").exists());
+        assert!(runs(&dir, "latex.runs")[0].ends_with(&format!("-output-directory={} main.tex", build.display())));
+        assert_eq!(runs(&build, "bibtex.runs"), vec![format!("main BIBINPUTS={}:", dir.display())]);
+        assert!(runs(&build, "makeindex.runs")[0].starts_with("main.idx"));
+
+        let mut job = job_in(&dir, "\\usepackage{biblatex}\n\\cite{knuth}\n");
+        job.out_dir = Some(build.clone());
+        assert!(run_collect(&job).0.succeeded());
+        assert!(build.join("main.bcf").exists());
+        let out = build.display();
+        assert_eq!(runs(&dir, "biber.runs"), vec![format!("--input-directory {} --output-directory {} main", out, out)]);
+    }
+
+    #[test]
+    fn cleaning_only_removes_build_leftovers() {
+        let dir = scratch_dir("clean");
+        std::fs::create_dir_all(dir.join("chapters")).unwrap();
+        for file in ["main.tex", "main.aux", "main.synctex.gz", "main.pdf", "notes.log", "refs.bib", "chapters/intro.tex", "chapters/intro.aux"] {
+            std::fs::write(dir.join(file), "").unwrap();
+        }
+        assert_eq!(clean_aux_files(&dir, None).unwrap(), 3);
+        assert!(dir.join("main.pdf").exists() && dir.join("refs.bib").exists());
+        // No notes.tex, so notes.log is not ours
+        assert!(dir.join("notes.log").exists());
+        assert!(!dir.join("chapters/intro.aux").exists());
+
+        let build = dir.join("build");
+        std::fs::create_dir_all(build.join("chapters")).unwrap();
+        for file in ["main.log", "main.bbl", "main.pdf", "chapters/intro.aux"] {
+            std::fs::write(build.join(file), "").unwrap();
+        }
+        std::fs::write(build.join("unrelated.log"), "").unwrap();
+        assert_eq!(clean_aux_files(&dir, Some(&build)).unwrap(), 3);
+        assert!(build.join("main.pdf").exists() && build.join("unrelated.log").exists());
+        assert!(dir.join("notes.log").exists());
+
+        // A build directory outside the project is never searched
+        assert!(clean_aux_files(&build, Some(&dir)).is_err());
+        assert!(dir.join("notes.log").exists());
+    }
+
+    #[test]
+    fn engines_are_found_by_either_name() {
+        assert_eq!(Engine::from_name("latexmk"), Some(Engine::Latexmk));
+        assert_eq!(Engine::from_name(" XeLaTeX "), Some(Engine::Xelatex));
+        assert_eq!(Engine::from_name("lualatex"), Some(Engine::Lualatex));
+        assert_eq!(Engine::from_name("context"), None);
+    }
+
+    #[test]
+    fn parse_diagnostics_reads_line_and_message() {
+        let out = "note: Running TeX ...\nerror: /work/main.tex:12: Missing $ inserted\nerror: halted on potentially-recoverable error\n";
+        let diags = parse_diagnostics(out, Path::new("/work"));
+        assert_eq!(diags.len(), 1);
+        assert_eq!(diags[0].line, 12);
+        assert_eq!(diags[0].message, "Missing $ inserted");
+        assert_eq!(diags[0].file, "/work/main.tex");
+
+        let out = "(./chapters/intro.tex\n./chapters/intro.tex:4: Undefined control sequence.\nwarning: main.tex:2: Overfull \\hbox (3.0pt too wide)\n";
+        let diags = parse_diagnostics(out, Path::new("/work"));
+        assert_eq!(diags.len(), 2);
+        assert_eq!((diags[0].severity, diags[0].line), (Severity::Error, 4));
+        assert_eq!(diags[0].file, "/work/chapters/intro.tex");
+        assert_eq!((diags[1].severity, diags[1].line), (Severity::Badbox, 2));
+        assert_eq!(diags[1].file, "/work/main.tex");
+    }
+
+    #[test]
+    fn diagnostics_come_from_the_fresh_log() {
+        let dir = scratch_dir("log");
+        let job = job_in(&dir, "\\ref{missing}\n").with_engine(&EngineSettings { engine: Engine::Pdflatex, ..Default::default() });
+        let (result, _) = run_collect(&job);
+
+        assert!(result.succeeded());
+        assert_eq!(result.diagnostics.len(), 1);
+        assert_eq!(result.diagnostics[0].severity, Severity::Warning);
+        assert_eq!(result.diagnostics[0].line, 2);
+        assert_eq!(result.diagnostics[0].file, dir.join("main.tex").to_string_lossy());
+
+        // A failed run leaves the old log behind; its warnings must not be reported
+        let job = job_in(&dir, "FAIL\n").with_engine(&EngineSettings { engine: Engine::Pdflatex, ..Default::default() });
+        let hour_ago = std::time::SystemTime::now() - std::time::Duration::from_secs(3600);
+        std::fs::File::options().write(true).open(dir.join("main.log")).unwrap().set_modified(hour_ago).unwrap();
+        let (result, _) = run_collect(&job);
+        assert_eq!(result.diagnostics.len(), 1);
+        assert_eq!(result.diagnostics[0].severity, Severity::Error);
+    }
+}
@@ -0,0 +1,238 @@
+// Desktop Integration
+//
+// Registers Typesafe as the default application for LaTeX sources, and undoes
+// it. Each OS has its own `platform` module: the registry under
+// `HKCU\Software\Classes` on Windows, a `.desktop` entry plus `xdg-mime` on Linux.
+
+// Extensions the association covers
+pub const EXTENSIONS: &[&str] = &["tex", "bib", "cls", "sty"];
+
+pub fn is_supported() -> bool {
+    platform::SUPPORTED
+}
+
+pub fn install() -> Result<(), String> {
+    let exe = std::env::current_exe().map_err(|e| format!("Cannot locate the executable: {}", e))?;
+    platform::install(&exe)
+}
+
+pub fn uninstall() -> Result<(), String> {
+    platform::uninstall()
+}
+
+#[cfg(windows)]
+mod platform {
+    use super::EXTENSIONS;
+    use std::path::Path;
+    use winreg::enums::HKEY_CURRENT_USER;
+    use winreg::RegKey;
+
+    pub const SUPPORTED: bool = true;
+    const PROG_ID: &str = "Typesafe.Document";
+
+    fn set_default(hkcu: &RegKey, path: &str, value: &str) -> Result<(), String> {
+        let (key, _) = hkcu.create_subkey(Path::new(path)).map_err(|e| format!("Cannot write {}: {}", path, e))?;
+        key.set_value("", &value).map_err(|e| format!("Cannot write {}: {}", path, e))
+    }
+
+    pub fn install(exe: &Path) -> Result<(), String> {
+        let exe = exe.to_string_lossy();
+        let hkcu = RegKey::predef(HKEY_CURRENT_USER);
+        for ext in EXTENSIONS {
+            set_default(&hkcu, &format!("Software\\Classes\\.{}", ext), PROG_ID)?;
+        }
+        set_default(&hkcu, &format!("Software\\Classes\\{}", PROG_ID), "LaTeX Document")?;
+        set_default(&hkcu, &format!("Software\\Classes\\{}\\DefaultIcon", PROG_ID), &format!("{},0", exe))?;
+        set_default(&hkcu, &format!("Software\\Classes\\{}\\shell\\open\\command", PROG_ID), &format!("\"{}\" \"%1\"", exe))
+    }
+
+    pub fn uninstall() -> Result<(), String> {
+        let hkcu = RegKey::predef(HKEY_CURRENT_USER);
+        for ext in EXTENSIONS {
+            // Leave extensions that another program has claimed since
+            let Ok(key) = hkcu.open_subkey_with_flags(format!("Software\\Classes\\.{}", ext), winreg::enums::KEY_ALL_ACCESS) else { continue };
+            if key.get_value::<String, _>("").is_ok_and(|v| v == PROG_ID) {
+                let _ = key.delete_value("");
+            }
+        }
+        match hkcu.delete_subkey_all(format!("Software\\Classes\\{}", PROG_ID)) {
+            Err(e) if e.kind() != std::io::ErrorKind::NotFound => Err(format!("Cannot remove {}: {}", PROG_ID, e)),
+            _ => Ok(()),
+        }
+    }
+}
+
+#[cfg(target_os = "linux")]
+mod platform {
+    use std::path::{Path, PathBuf};
+    use std::process::Command;
+
+    pub const SUPPORTED: bool = true;
+    const DESKTOP_ID: &str = "typesafe.desktop";
+    // shared-mime-info files .cls and .sty under text/x-tex as well
+    const MIME_TYPES: &[&str] = &["text/x-tex", "text/x-bibtex"];
+    const ICON: &[u8] = include_bytes!("../icon.png");
+
+    fn data_dir() -> Result<PathBuf, String> {
+        directories::BaseDirs::new().map(|d| d.data_dir().to_path_buf()).ok_or_else(|| "Cannot find the home folder".to_string())
+    }
+
+    fn desktop_path(data: &Path) -> PathBuf {
+        data.join("applications").join(DESKTOP_ID)
+    }
+
+    fn icon_path(data: &Path) -> PathBuf {
+        data.join("icons/hicolor/256x256/apps/typesafe.png")
+    }
+
+    fn write(path: &Path, contents: &[u8]) -> Result<(), String> {
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent).map_err(|e| format!("Cannot create {}: {}", parent.display(), e))?;
+        }
+        std::fs::write(path, contents).map_err(|e| format!("Cannot write {}: {}", path.display(), e))
+    }
+
+    pub fn install(exe: &Path) -> Result<(), String> {
+        let data = data_dir()?;
+        write(&desktop_path(&data), desktop_entry(exe).as_bytes())?;
+        write(&icon_path(&data), ICON)?;
+        // Refreshes the menu cache where the tool exists; not required
+        let _ = Command::new("update-desktop-database").arg(data.join("applications")).status();
+
+        let status = Command::new("xdg-mime")
+            .arg("default")
+            .arg(DESKTOP_ID)
+            .args(MIME_TYPES)
+            .status()
+            .map_err(|e| format!("Cannot run xdg-mime: {}", e))?;
+        if !status.success() {
+            return Err(format!("xdg-mime failed ({})", status));
+        }
+        Ok(())
+    }
+
+    pub fn uninstall() -> Result<(), String> {
+        let data = data_dir()?;
+        for path in [desktop_path(&data), icon_path(&data)] {
+            match std::fs::remove_file(&path) {
+                Err(e) if e.kind() != std::io::ErrorKind::NotFound => return Err(format!("Cannot remove {}: {}", path.display(), e)),
+                _ => {}
+            }
+        }
+        let _ = Command::new("update-desktop-database").arg(data.join("applications")).status();
+
+        // xdg-mime can set defaults but not unset them
+        if let Some(dirs) = directories::BaseDirs::new() {
+            let list = dirs.config_dir().join("mimeapps.list");
+            if let Ok(contents) = std::fs::read_to_string(&list) {
+                let cleaned = remove_defaults(&contents, DESKTOP_ID);
+                if cleaned != contents {
+                    std::fs::write(&list, cleaned).map_err(|e| format!("Cannot write {}: {}", list.display(), e))?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    pub(super) fn desktop_entry(exe: &Path) -> String {
+        format!(
+            "[Desktop Entry]\n\
+             Type=Application\n\
+             Name=Typesafe\n\
+             GenericName=LaTeX Editor\n\
+             Comment=Edit and build LaTeX documents\n\
+             Exec={} %f\n\
+             Icon=typesafe\n\
+             Terminal=false\n\
+             Categories=Office;Publishing;TextEditor;\n\
+             MimeType={};\n",
+            quote_exec(&exe.to_string_lossy()),
+            MIME_TYPES.join(";")
+        )
+    }
+
+    // Quoting rules for the Exec key of the Desktop Entry spec
+    fn quote_exec(arg: &str) -> String {
+        let mut quoted = String::from("\"");
+        for c in arg.chars() {
+            if matches!(c, '"' | '`' | '$' | '\\') {
+                quoted.push('\\');
+            }
+            quoted.push(c);
+        }
+        quoted.push('"');
+        // The key itself is also unescaped once, so backslashes double up again
+        quoted.replace('\\', "\\\\")
+    }
+
+    // Drops `desktop_id` from mimeapps.list, removing keys that are left empty
+    pub(super) fn remove_defaults(contents: &str, desktop_id: &str) -> String {
+        let mut out = String::new();
+        for line in contents.lines() {
+            let kept = match line.split_once('=') {
+                Some((key, value)) if !line.trim_start().starts_with(['#', '[']) && value.split(';').any(|a| a == desktop_id) => {
+                    let apps: Vec<&str> = value.split(';').filter(|a| !a.is_empty() && *a != desktop_id).collect();
+                    if apps.is_empty() {
+                        None
+                    } else {
+                        Some(format!("{}={};", key, apps.join(";")))
+                    }
+                }
+                _ => Some(line.to_string()),
+            };
+            if let Some(kept) = kept {
+                out.push_str(&kept);
+                out.push('\n');
+            }
+        }
+        out
+    }
+}
+
+#[cfg(not(any(windows, target_os = "linux")))]
+mod platform {
+    use std::path::Path;
+
+    pub const SUPPORTED: bool = false;
+
+    pub fn install(_exe: &Path) -> Result<(), String> {
+        Err("File associations are not supported on this platform".to_string())
+    }
+
+    pub fn uninstall() -> Result<(), String> {
+        install(Path::new(""))
+    }
+}
+
+#[cfg(all(test, target_os = "linux"))]
+mod tests {
+    use super::platform::*;
+    use std::path::Path;
+
+    #[test]
+    fn desktop_entry_quotes_the_executable() {
+        let entry = desktop_entry(Path::new("/opt/Type Safe/typesafe"));
+        assert!(entry.contains("Exec=\"/opt/Type Safe/typesafe\" %f\n"));
+        assert!(entry.contains("MimeType=text/x-tex;text/x-bibtex;\n"));
+    }
+
+    #[test]
+    fn uninstall_only_removes_our_defaults() {
+        let list = "[Default Applications]\n\
+                    text/x-tex=typesafe.desktop;\n\
+                    text/x-bibtex=typesafe.desktop;jabref.desktop;\n\
+                    text/html=firefox.desktop\n\
+                    \n\
+                    [Added Associations]\n\
+                    text/x-tex=texstudio.desktop;typesafe.desktop;\n";
+        assert_eq!(
+            remove_defaults(list, "typesafe.desktop"),
+            "[Default Applications]\n\
+             text/x-bibtex=jabref.desktop;\n\
+             text/html=firefox.desktop\n\
+             \n\
+             [Added Associations]\n\
+             text/x-tex=texstudio.desktop;\n"
+        );
+    }
+}
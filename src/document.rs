@@ -0,0 +1,355 @@
+// Document
+//
+// The text of an editor buffer. Positions are character indices throughout,
+// the same as egui's cursors; the rope holds the text and turns them into bytes
+// and lines. egui lays out a `&str`, so a flat copy is made when one is asked
+// for and kept until the next edit, at most once per frame while typing.
+//
+// Every change is recorded in the undo history. Typing arrives through
+// `egui::TextBuffer` and runs of adjacent keystrokes are grouped into one undo
+// step; everything else goes through `transact`, and each transaction is
+// undone as a whole however many edits it made.
+
+use ropey::Rope;
+use std::cell::OnceCell;
+use std::ops::{Deref, Range};
+use std::time::{Duration, Instant};
+
+// Keystrokes further apart than this start a new undo step
+const TYPING_PAUSE: Duration = Duration::from_millis(1000);
+// Undo steps kept per document
+const MAX_HISTORY: usize = 1000;
+
+#[derive(Debug, Clone, PartialEq)]
+struct Edit {
+    at: usize,
+    removed: String,
+    inserted: String,
+}
+
+#[derive(Debug, Clone)]
+struct Group {
+    edits: Vec<Edit>,
+    typing: bool,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Document {
+    rope: Rope,
+    // The rope as one string, dropped on every edit
+    flat: OnceCell<String>,
+    undo: Vec<Group>,
+    redo: Vec<Group>,
+    last_typed: Option<Instant>,
+}
+
+impl From<String> for Document {
+    fn from(text: String) -> Self {
+        Self { rope: Rope::from_str(&text), flat: OnceCell::from(text), ..Default::default() }
+    }
+}
+
+impl From<&str> for Document {
+    fn from(text: &str) -> Self {
+        Self::from(text.to_string())
+    }
+}
+
+impl Deref for Document {
+    type Target = str;
+
+    fn deref(&self) -> &str {
+        self.as_str()
+    }
+}
+
+impl Document {
+    pub fn as_str(&self) -> &str {
+        self.flat.get_or_init(|| self.rope.to_string())
+    }
+
+    pub fn len_chars(&self) -> usize {
+        self.rope.len_chars()
+    }
+
+    pub fn char_to_byte(&self, char_idx: usize) -> usize {
+        self.rope.char_to_byte(char_idx.min(self.len_chars()))
+    }
+
+    pub fn byte_to_char(&self, byte_idx: usize) -> usize {
+        self.rope.byte_to_char(byte_idx.min(self.rope.len_bytes()))
+    }
+
+    // 0-based line containing `char_idx`
+    pub fn char_to_line(&self, char_idx: usize) -> usize {
+        self.rope.char_to_line(char_idx.min(self.len_chars()))
+    }
+
+    // Character index where 0-based `line` starts, or the end of the text past the last line
+    pub fn line_to_char(&self, line: usize) -> usize {
+        if line >= self.rope.len_lines() {
+            return self.len_chars();
+        }
+        self.rope.line_to_char(line)
+    }
+
+    // Text of 0-based `line` without its line break
+    pub fn line(&self, line: usize) -> String {
+        if line >= self.rope.len_lines() {
+            return String::new();
+        }
+        let text = self.rope.line(line).to_string();
+        text.trim_end_matches(['\n', '\r']).to_string()
+    }
+
+    // Text of a character range
+    pub fn slice(&self, range: Range<usize>) -> &str {
+        let range = self.clamp(range);
+        &self.as_str()[self.char_to_byte(range.start)..self.char_to_byte(range.end)]
+    }
+
+    fn clamp(&self, range: Range<usize>) -> Range<usize> {
+        let end = range.end.min(self.len_chars());
+        range.start.min(end)..end
+    }
+
+    // Applies `f`'s edits as a single undo step
+    pub fn transact<R>(&mut self, f: impl FnOnce(&mut Transaction) -> R) -> R {
+        let mut tx = Transaction { doc: self, edits: Vec::new() };
+        let result = f(&mut tx);
+        let edits = tx.edits;
+        if !edits.is_empty() {
+            self.push_group(Group { edits, typing: false });
+        }
+        result
+    }
+
+    // Replaces the whole text, as one undoable step
+    pub fn replace_all(&mut self, text: &str) {
+        if text != self.as_str() {
+            let len = self.len_chars();
+            self.transact(|tx| tx.replace(0..len, text));
+        }
+    }
+
+    // Ends the current run of typing, so the next keystroke starts a new undo step
+    pub fn break_group(&mut self) {
+        self.last_typed = None;
+    }
+
+    pub fn can_undo(&self) -> bool {
+        !self.undo.is_empty()
+    }
+
+    pub fn can_redo(&self) -> bool {
+        !self.redo.is_empty()
+    }
+
+    // Reverts the last undo step and returns where the cursor belongs
+    pub fn undo(&mut self) -> Option<usize> {
+        let group = self.undo.pop()?;
+        for edit in group.edits.iter().rev() {
+            self.apply(edit.at..edit.at + edit.inserted.chars().count(), &edit.removed);
+        }
+        let first = &group.edits[0];
+        let cursor = first.at + first.removed.chars().count();
+        self.redo.push(group);
+        self.last_typed = None;
+        Some(cursor)
+    }
+
+    // Reapplies the last undone step and returns where the cursor belongs
+    pub fn redo(&mut self) -> Option<usize> {
+        let group = self.redo.pop()?;
+        for edit in &group.edits {
+            self.apply(edit.at..edit.at + edit.removed.chars().count(), &edit.inserted);
+        }
+        let last = &group.edits[group.edits.len() - 1];
+        let cursor = last.at + last.inserted.chars().count();
+        self.undo.push(group);
+        self.last_typed = None;
+        Some(cursor)
+    }
+
+    fn apply(&mut self, range: Range<usize>, text: &str) -> Edit {
+        let range = self.clamp(range);
+        let removed = self.rope.slice(range.clone()).to_string();
+        self.flat.take();
+        self.rope.remove(range.clone());
+        self.rope.insert(range.start, text);
+        Edit { at: range.start, removed, inserted: text.to_string() }
+    }
+
+    fn push_group(&mut self, group: Group) {
+        self.undo.push(group);
+        if self.undo.len() > MAX_HISTORY {
+            self.undo.remove(0);
+        }
+        self.redo.clear();
+        self.last_typed = None;
+    }
+
+    // Records a keystroke, folding it into the current run of typing when it
+    // continues right where the previous one left off
+    fn type_edit(&mut self, edit: Edit) {
+        let now = Instant::now();
+        let recent = self.last_typed.is_some_and(|t| now.duration_since(t) < TYPING_PAUSE);
+        if recent {
+            if let Some(prev) = self.undo.last_mut().filter(|g| g.typing).and_then(|g| g.edits.last_mut()) {
+                let prev_end = prev.at + prev.inserted.chars().count();
+                if edit.removed.is_empty() && edit.at == prev_end {
+                    prev.inserted.push_str(&edit.inserted);
+                    self.redo.clear();
+                    self.last_typed = Some(now);
+                    return;
+                }
+                // Backspacing over what was just typed, or further back
+                if edit.inserted.is_empty() && edit.at + edit.removed.chars().count() == prev_end {
+                    let typed = prev.inserted.chars().count();
+                    let removed = edit.removed.chars().count();
+                    if removed <= typed {
+                        let keep = prev.inserted.char_indices().nth(typed - removed).map_or(prev.inserted.len(), |(b, _)| b);
+                        prev.inserted.truncate(keep);
+                    } else {
+                        let extra: String = edit.removed.chars().take(removed - typed).collect();
+                        prev.inserted.clear();
+                        prev.at = edit.at;
+                        prev.removed.insert_str(0, &extra);
+                    }
+                    self.redo.clear();
+                    self.last_typed = Some(now);
+                    return;
+                }
+            }
+        }
+        self.push_group(Group { edits: vec![edit], typing: true });
+        self.last_typed = Some(now);
+    }
+}
+
+impl egui::TextBuffer for Document {
+    fn is_mutable(&self) -> bool {
+        true
+    }
+
+    fn as_str(&self) -> &str {
+        Document::as_str(self)
+    }
+
+    fn insert_text(&mut self, text: &str, char_index: usize) -> usize {
+        let at = char_index.min(self.len_chars());
+        let edit = self.apply(at..at, text);
+        self.type_edit(edit);
+        text.chars().count()
+    }
+
+    fn delete_char_range(&mut self, char_range: Range<usize>) {
+        if char_range.start >= char_range.end {
+            return;
+        }
+        let edit = self.apply(char_range, "");
+        self.type_edit(edit);
+    }
+}
+
+// A batch of edits that is undone in one step. Edits apply immediately, so
+// positions passed to later calls refer to the text as changed by earlier ones.
+pub struct Transaction<'a> {
+    doc: &'a mut Document,
+    edits: Vec<Edit>,
+}
+
+impl Transaction<'_> {
+    pub fn doc(&self) -> &Document {
+        self.doc
+    }
+
+    pub fn replace(&mut self, range: Range<usize>, text: &str) {
+        let range = self.doc.clamp(range);
+        if range.is_empty() && text.is_empty() {
+            return;
+        }
+        let edit = self.doc.apply(range, text);
+        self.edits.push(edit);
+    }
+
+    pub fn insert(&mut self, at: usize, text: &str) {
+        self.replace(at..at, text);
+    }
+
+    pub fn delete(&mut self, range: Range<usize>) {
+        self.replace(range, "");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use egui::TextBuffer;
+
+    #[test]
+    fn edits_use_character_positions() {
+        let mut doc = Document::from("Grüße, \\o{}\nzweite Zeile\n");
+        doc.transact(|tx| {
+            tx.insert(5, " Welt");
+            tx.replace(13..14, "ø");
+        });
+        assert_eq!(doc.as_str(), "Grüße Welt, \\ø{}\nzweite Zeile\n");
+        assert_eq!(doc.slice(0..5), "Grüße");
+        assert_eq!(doc.char_to_line(18), 1);
+        assert_eq!(doc.line_to_char(1), 17);
+        assert_eq!(doc.line(1), "zweite Zeile");
+        assert_eq!(doc.byte_to_char(doc.char_to_byte(12)), 12);
+
+        assert_eq!(doc.undo(), Some(5));
+        assert_eq!(doc.as_str(), "Grüße, \\o{}\nzweite Zeile\n");
+        assert_eq!(doc.redo(), Some(14));
+        assert_eq!(doc.as_str(), "Grüße Welt, \\ø{}\nzweite Zeile\n");
+    }
+
+    #[test]
+    fn typing_is_undone_in_runs() {
+        let mut doc = Document::from("é");
+        for (i, c) in ["a", "b", "c"].iter().enumerate() {
+            doc.insert_text(c, 1 + i);
+        }
+        doc.delete_char_range(3..4);
+        assert_eq!(doc.as_str(), "éab");
+
+        // A programmatic edit is its own step and ends the run
+        doc.transact(|tx| tx.insert(0, "% "));
+        doc.insert_text("!", 5);
+        assert_eq!(doc.as_str(), "% éab!");
+
+        assert_eq!(doc.undo(), Some(5));
+        assert_eq!(doc.as_str(), "% éab");
+        assert_eq!(doc.undo(), Some(0));
+        assert_eq!(doc.as_str(), "éab");
+        assert_eq!(doc.undo(), Some(1));
+        assert_eq!(doc.as_str(), "é");
+        assert!(!doc.can_undo());
+
+        doc.redo();
+        assert_eq!(doc.as_str(), "éab");
+        doc.insert_text("x", 0);
+        assert!(!doc.can_redo());
+    }
+
+    #[test]
+    fn separate_places_are_separate_steps() {
+        let mut doc = Document::from("one two");
+        doc.insert_text("1", 3);
+        doc.insert_text("2", 8);
+        doc.break_group();
+        doc.insert_text("3", 9);
+        assert_eq!(doc.as_str(), "one1 two23");
+        doc.undo();
+        doc.undo();
+        assert_eq!(doc.as_str(), "one1 two");
+
+        doc.replace_all("fresh");
+        assert_eq!(doc.as_str(), "fresh");
+        doc.undo();
+        assert_eq!(doc.as_str(), "one1 two");
+    }
+}
@@ -0,0 +1,305 @@
+// Code Folding
+//
+// Regions of a LaTeX document that can be collapsed to their first line:
+// environments from \begin to \end, sections up to the next heading at the
+// same or a higher level (the hierarchy the outline uses), and runs of comment
+// lines. Regions are read from the lexer tokens the highlighting cache keeps
+// per line, so commands in comments and verbatim blocks open none, and nothing
+// is lexed again for folding. They are only gathered again when the cache
+// reports changed lines. A folded region is remembered by its first line,
+// which follows the text as lines are added or removed before it.
+
+use crate::highlight::{CachedLine, LineEdit, SpanStyle};
+use crate::lexer::{Token, TokenKind};
+use std::collections::BTreeSet;
+use std::ops::Range;
+
+// 0-based lines; `start` stays visible and the lines after it through `end` are hidden
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FoldRegion {
+    pub start: usize,
+    pub end: usize,
+}
+
+// Outline level of a sectioning command, as in \section or \chapter*
+pub fn section_level(command: &str) -> Option<usize> {
+    match command.trim_end_matches('*') {
+        "part" | "chapter" => Some(0),
+        "section" => Some(1),
+        "subsection" => Some(2),
+        "subsubsection" => Some(3),
+        _ => None,
+    }
+}
+
+// Every foldable region, from each line's text and tokens, ordered by first
+// line. Of regions starting on the same line only the largest is kept, since
+// one toggle serves the line.
+pub fn fold_regions<'a, T: IntoIterator<Item = Token>>(lines: impl IntoIterator<Item = (&'a str, T)>) -> Vec<FoldRegion> {
+    let mut regions = Vec::new();
+    // Open environments and the line of their \begin
+    let mut environments: Vec<(String, usize)> = Vec::new();
+    // Open sections with their level and heading line
+    let mut sections: Vec<(usize, usize)> = Vec::new();
+    let mut comment_start: Option<usize> = None;
+    // Sections end at the last non-blank line before whatever closes them
+    let mut last_content = 0;
+    let mut line_count = 0;
+
+    for (i, (line, tokens)) in lines.into_iter().enumerate() {
+        line_count = i + 1;
+        let tokens: Vec<Token> = tokens.into_iter().collect();
+
+        let is_comment = line.trim_start().starts_with('%')
+            && tokens.iter().any(|(range, kind)| *kind == TokenKind::Comment && range.start == line.len() - line.trim_start().len());
+        if is_comment {
+            comment_start.get_or_insert(i);
+        } else if let Some(start) = comment_start.take() {
+            if i - 1 > start {
+                regions.push(FoldRegion { start, end: i - 1 });
+            }
+        }
+
+        for (range, kind) in &tokens {
+            match kind {
+                TokenKind::Environment => {
+                    let name = &line[range.clone()];
+                    let before = &line[..range.start];
+                    if before.ends_with("\\begin{") {
+                        environments.push((name.to_string(), i));
+                    } else if before.ends_with("\\end{") {
+                        if name == "document" {
+                            close_sections(&mut sections, 0, last_content, &mut regions);
+                        }
+                        // Environments left open inside this one are dropped with it
+                        if let Some(k) = environments.iter().rposition(|(open, _)| open == name) {
+                            let start = environments[k].1;
+                            environments.truncate(k);
+                            if i > start {
+                                regions.push(FoldRegion { start, end: i });
+                            }
+                        }
+                    }
+                }
+                TokenKind::Command => {
+                    let name = line[range.clone()].rsplit('\\').next().unwrap_or("");
+                    if let Some(level) = section_level(name) {
+                        close_sections(&mut sections, level, last_content, &mut regions);
+                        sections.push((level, i));
+                    }
+                }
+                _ => {}
+            }
+        }
+
+        if !line.trim().is_empty() {
+            last_content = i;
+        }
+    }
+
+    if let Some(start) = comment_start {
+        if line_count - 1 > start {
+            regions.push(FoldRegion { start, end: line_count - 1 });
+        }
+    }
+    close_sections(&mut sections, 0, last_content, &mut regions);
+
+    regions.sort_by_key(|r| (r.start, std::cmp::Reverse(r.end)));
+    regions.dedup_by_key(|r| r.start);
+    regions
+}
+
+// Ends the open sections at `level` or deeper, at the last non-blank line before the closing line
+fn close_sections(sections: &mut Vec<(usize, usize)>, level: usize, last_content: usize, regions: &mut Vec<FoldRegion>) {
+    while let Some(&(open_level, start)) = sections.last() {
+        if open_level < level {
+            break;
+        }
+        sections.pop();
+        if last_content > start {
+            regions.push(FoldRegion { start, end: last_content });
+        }
+    }
+}
+
+// The fold regions of the editor's document and which of them are folded
+#[derive(Debug, Clone, Default)]
+pub struct Folds {
+    regions: Vec<FoldRegion>,
+    // First lines of the folded regions
+    folded: BTreeSet<usize>,
+    // Whether the regions were gathered since the folds were set
+    synced: bool,
+}
+
+impl Folds {
+    // Starts with the regions at `folded` folded, e.g. as saved in the session.
+    // Lines that turn out not to start a region are dropped on the first update.
+    pub fn new(folded: Vec<usize>) -> Self {
+        Self { folded: folded.into_iter().collect(), ..Default::default() }
+    }
+
+    // Gathers the regions again from the highlighted `lines` after `edit`,
+    // moving folds along with lines inserted or removed before them
+    pub fn update<S>(&mut self, lines: &[CachedLine<S, SpanStyle>], edit: Option<LineEdit>) {
+        if self.synced && edit.is_none() {
+            return;
+        }
+        if let Some(edit) = edit.filter(|_| self.synced) {
+            let after = edit.start + edit.removed;
+            self.folded = std::mem::take(&mut self.folded)
+                .into_iter()
+                .filter_map(|line| {
+                    if line >= after {
+                        Some(line + edit.inserted - edit.removed)
+                    } else {
+                        // The first changed line keeps its fold, as when its heading is edited
+                        (line <= edit.start).then_some(line)
+                    }
+                })
+                .collect();
+        }
+        self.regions = fold_regions(lines.iter().map(|line| {
+            let tokens = line.spans.iter().filter_map(|(range, style)| match style {
+                SpanStyle::Token(kind) => Some((range.clone(), *kind)),
+                SpanStyle::Color(_) => None,
+            });
+            (line.text.as_str(), tokens)
+        }));
+        let regions = &self.regions;
+        self.folded.retain(|&line| regions.binary_search_by_key(&line, |r| r.start).is_ok());
+        self.synced = true;
+    }
+
+    // The region whose first line is `line`
+    pub fn region_at(&self, line: usize) -> Option<FoldRegion> {
+        let i = self.regions.binary_search_by_key(&line, |r| r.start).ok()?;
+        Some(self.regions[i])
+    }
+
+    pub fn is_folded(&self, line: usize) -> bool {
+        self.folded.contains(&line)
+    }
+
+    pub fn toggle(&mut self, line: usize) {
+        if self.region_at(line).is_some() && !self.folded.remove(&line) {
+            self.folded.insert(line);
+        }
+    }
+
+    pub fn fold_all(&mut self) {
+        self.folded = self.regions.iter().map(|r| r.start).collect();
+    }
+
+    pub fn unfold_all(&mut self) {
+        self.folded.clear();
+    }
+
+    // Unfolds whatever hides `line`
+    pub fn reveal(&mut self, line: usize) {
+        let regions = &self.regions;
+        self.folded.retain(|&start| {
+            let end = regions.binary_search_by_key(&start, |r| r.start).map_or(start, |i| regions[i].end);
+            !(start < line && line <= end)
+        });
+    }
+
+    // Ranges of hidden lines, sorted and disjoint
+    pub fn hidden_lines(&self) -> Vec<Range<usize>> {
+        let mut hidden: Vec<Range<usize>> = Vec::new();
+        for region in self.folded.iter().filter_map(|&line| self.region_at(line)) {
+            let lines = region.start + 1..region.end + 1;
+            match hidden.last_mut() {
+                Some(last) if lines.start <= last.end => last.end = last.end.max(lines.end),
+                _ => hidden.push(lines),
+            }
+        }
+        hidden
+    }
+
+    pub fn folded_lines(&self) -> Vec<usize> {
+        self.folded.iter().copied().collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::highlight::LineCache;
+    use crate::lexer::{self, LexState};
+
+    fn lex_into(cache: &mut LineCache<LexState, SpanStyle>, text: &str) -> Option<LineEdit> {
+        cache.update(text, "LaTeX", LexState::default(), |state, line| {
+            lexer::lex_line(state, line).into_iter().map(|(range, kind)| (range, SpanStyle::Token(kind))).collect()
+        })
+    }
+
+    fn spans(text: &str) -> Vec<(usize, usize)> {
+        let mut state = LexState::default();
+        let lines = text.split_inclusive('\n').map(|line| (line, lexer::lex_line(&mut state, line)));
+        fold_regions(lines).into_iter().map(|r| (r.start, r.end)).collect()
+    }
+
+    #[test]
+    fn regions_from_environments_sections_and_comments() {
+        let text = "\
+\\begin{document}
+\\section{One}
+% a comment
+% that goes on
+\\begin{itemize}
+  \\item x % \\begin{quote}
+\\end{itemize}
+
+\\subsection*{Two}
+\\begin{verbatim}
+\\section{not a heading}
+\\end{verbatim}
+
+\\section{Three}
+text
+\\end{document}
+";
+        assert_eq!(
+            spans(text),
+            vec![(0, 15), (1, 11), (2, 3), (4, 6), (8, 11), (9, 11), (13, 14)]
+        );
+    }
+
+    #[test]
+    fn sections_nest_like_the_outline() {
+        assert_eq!(section_level("chapter*"), Some(0));
+        assert_eq!(section_level("paragraph"), None);
+        let text = "\\chapter{A}\n\\section{B}\nb\n\\subsection{C}\nc\n\\section{D}\nd\n\\chapter{E}\ne\n% single comment\n";
+        assert_eq!(spans(text), vec![(0, 6), (1, 4), (3, 4), (5, 6), (7, 9)]);
+    }
+
+    #[test]
+    fn folds_follow_their_lines() {
+        let mut cache = LineCache::default();
+        let mut folds = Folds::new(vec![1, 3]);
+        let edit = lex_into(&mut cache, "intro\n\\begin{a}\nx\n\\end{a}\n");
+        folds.update(cache.lines(), edit);
+        assert_eq!(folds.folded_lines(), vec![1]);
+        assert_eq!(folds.hidden_lines(), vec![2..4]);
+
+        // Lines added above move the fold down; editing its heading keeps it
+        let edit = lex_into(&mut cache, "new\nintro\n\\begin{a}\nx\n\\end{a}\n");
+        folds.update(cache.lines(), edit);
+        assert!(folds.is_folded(2));
+        let edit = lex_into(&mut cache, "new\nintro\n\\begin{a} % edited\nx\n\\end{a}\n");
+        folds.update(cache.lines(), edit);
+        assert!(folds.is_folded(2));
+        // Unchanged text leaves everything as it is
+        assert_eq!(lex_into(&mut cache, "new\nintro\n\\begin{a} % edited\nx\n\\end{a}\n"), None);
+
+        folds.reveal(3);
+        assert!(folds.hidden_lines().is_empty());
+        folds.fold_all();
+        folds.toggle(0);
+        assert_eq!(folds.folded_lines(), vec![2]);
+        folds.toggle(2);
+        folds.unfold_all();
+        assert!(folds.folded_lines().is_empty());
+    }
+}
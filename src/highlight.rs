@@ -0,0 +1,232 @@
+// Highlighting Cache
+//
+// The editor is laid out every frame, so highlighting is kept per line along
+// with the parser state at the end of each line. After an edit, lines before
+// the change are reused as they are; from the first changed line on, lines are
+// highlighted again until one starts in the same state as before, at which
+// point the rest of the old results still hold.
+//
+// LaTeX lines keep their lexer tokens rather than colors, so the theme is
+// applied when the text is laid out and code folding reads the same tokens.
+//
+// Error, spelling and search ranges are drawn over the result. They are kept
+// as sorted intervals so each token only looks at the ranges that touch it.
+
+use crate::lexer::{LexState, TokenKind};
+use egui::Color32;
+use std::ops::Range;
+use syntect::highlighting::HighlightState;
+use syntect::parsing::ParseState;
+
+// Parser state at a line boundary: LaTeX files use the built-in lexer, with
+// syntect for the code blocks in them, and other files a syntect syntax
+#[derive(Debug, Clone, PartialEq)]
+pub enum LineState {
+    Latex(LexState, Option<(ParseState, HighlightState)>),
+    Syntect(ParseState, HighlightState),
+}
+
+// How a span is colored: LaTeX tokens by the theme, embedded code and other
+// files by syntect
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SpanStyle {
+    Token(TokenKind),
+    Color(Color32),
+}
+
+// Lines an update replaced: `removed` old lines from `start` on became `inserted` new ones
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LineEdit {
+    pub start: usize,
+    pub removed: usize,
+    pub inserted: usize,
+}
+
+#[derive(Debug, Clone)]
+pub struct CachedLine<S, T> {
+    // The line including its line break
+    pub text: String,
+    // Byte ranges within the line and their styles
+    pub spans: Vec<(Range<usize>, T)>,
+    // Parser state after the line
+    end: S,
+}
+
+#[derive(Debug, Clone)]
+pub struct LineCache<S, T> {
+    // What the lines were highlighted with (syntax, theme); a change starts over
+    key: String,
+    lines: Vec<CachedLine<S, T>>,
+}
+
+impl<S, T> Default for LineCache<S, T> {
+    fn default() -> Self {
+        Self { key: String::new(), lines: Vec::new() }
+    }
+}
+
+impl<S: Clone + PartialEq, T> LineCache<S, T> {
+    pub fn lines(&self) -> &[CachedLine<S, T>] {
+        &self.lines
+    }
+
+    // Brings the cache up to date with `text`. `highlight` styles one line,
+    // advancing the state, and is only called for lines that need it. Returns
+    // the lines that changed, or None if nothing was highlighted again.
+    pub fn update(
+        &mut self,
+        text: &str,
+        key: &str,
+        initial: S,
+        mut highlight: impl FnMut(&mut S, &str) -> Vec<(Range<usize>, T)>,
+    ) -> Option<LineEdit> {
+        let new: Vec<&str> = text.split_inclusive('\n').collect();
+        let mut old: Vec<Option<CachedLine<S, T>>> = std::mem::take(&mut self.lines).into_iter().map(Some).collect();
+        let common = old.len().min(new.len());
+        let same = |old: &Option<CachedLine<S, T>>, new: &str| old.as_ref().is_some_and(|l| l.text == new);
+        let text_prefix = (0..common).take_while(|&i| same(&old[i], new[i])).count();
+        let text_suffix = (0..common - text_prefix).take_while(|&i| same(&old[old.len() - 1 - i], new[new.len() - 1 - i])).count();
+        let edit = LineEdit { start: text_prefix, removed: old.len() - text_prefix - text_suffix, inserted: new.len() - text_prefix - text_suffix };
+
+        // A new syntax or theme styles every line again, though the text may be the same
+        let (prefix, suffix) = if key != self.key {
+            self.key = key.to_string();
+            (0, 0)
+        } else if edit.removed == 0 && edit.inserted == 0 {
+            self.lines = old.into_iter().flatten().collect();
+            return None;
+        } else {
+            (text_prefix, text_suffix)
+        };
+
+        let mut lines: Vec<CachedLine<S, T>> = Vec::with_capacity(new.len());
+        lines.extend(old[..prefix].iter_mut().filter_map(Option::take));
+        let mut state = lines.last().map_or(initial.clone(), |l| l.end.clone());
+
+        for (i, line) in new.iter().enumerate().skip(prefix) {
+            if i >= new.len() - suffix {
+                // Unchanged lines after the edit; reusable once the state has caught up
+                let j = i + old.len() - new.len();
+                let before = match j.checked_sub(1) {
+                    None => Some(&initial),
+                    Some(k) if k < prefix => lines.get(k).map(|l| &l.end),
+                    Some(k) => old[k].as_ref().map(|l| &l.end),
+                };
+                if before == Some(&state) {
+                    lines.extend(old[j..].iter_mut().filter_map(Option::take));
+                    break;
+                }
+            }
+            let spans = highlight(&mut state, line);
+            lines.push(CachedLine { text: line.to_string(), spans, end: state.clone() });
+        }
+        self.lines = lines;
+        Some(edit)
+    }
+}
+
+// Byte ranges merged into sorted, disjoint intervals
+#[derive(Debug, Clone, Default)]
+pub struct Intervals {
+    ranges: Vec<Range<usize>>,
+}
+
+impl Intervals {
+    pub fn new(mut ranges: Vec<Range<usize>>) -> Self {
+        ranges.retain(|r| r.start < r.end);
+        ranges.sort_by_key(|r| r.start);
+        let mut merged: Vec<Range<usize>> = Vec::with_capacity(ranges.len());
+        for r in ranges {
+            match merged.last_mut() {
+                Some(last) if r.start <= last.end => last.end = last.end.max(r.end),
+                _ => merged.push(r),
+            }
+        }
+        Self { ranges: merged }
+    }
+
+    // Index of the first interval ending after `pos`
+    fn first_after(&self, pos: usize) -> usize {
+        self.ranges.partition_point(|r| r.end <= pos)
+    }
+
+    pub fn contains(&self, pos: usize) -> bool {
+        self.ranges.get(self.first_after(pos)).is_some_and(|r| r.start <= pos)
+    }
+
+    // Interval edges strictly inside `range`
+    pub fn boundaries_in(&self, range: Range<usize>, out: &mut Vec<usize>) {
+        for r in self.ranges[self.first_after(range.start)..].iter().take_while(|r| r.start < range.end) {
+            for edge in [r.start, r.end] {
+                if edge > range.start && edge < range.end {
+                    out.push(edge);
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Counts open braces across lines; each line is a single span styled with the depth
+    fn update(cache: &mut LineCache<usize, usize>, text: &str, calls: &mut Vec<String>) -> Option<LineEdit> {
+        cache.update(text, "braces", 0, |depth, line| {
+            calls.push(line.trim_end().to_string());
+            *depth += line.matches('{').count();
+            *depth -= line.matches('}').count().min(*depth);
+            vec![(0..line.len(), *depth)]
+        })
+    }
+
+    fn depths(cache: &LineCache<usize, usize>) -> Vec<usize> {
+        cache.lines().iter().map(|l| l.spans[0].1).collect()
+    }
+
+    #[test]
+    fn only_changed_lines_are_highlighted_again() {
+        let mut cache = LineCache::default();
+        let mut calls = Vec::new();
+        update(&mut cache, "a\nb\nc\nd\n", &mut calls);
+        assert_eq!(calls.len(), 4);
+
+        calls.clear();
+        update(&mut cache, "a\nB\nc\nd\n", &mut calls);
+        assert_eq!(calls, vec!["B"]);
+
+        // An inserted line shifts the rest, which is still reused
+        calls.clear();
+        let edit = update(&mut cache, "a\nB\nnew\nc\nd\n", &mut calls);
+        assert_eq!(calls, vec!["new"]);
+        assert_eq!(edit, Some(LineEdit { start: 2, removed: 0, inserted: 1 }));
+        assert_eq!(cache.lines().len(), 5);
+
+        // Changing the state carries on until it matches again
+        calls.clear();
+        update(&mut cache, "a\n{\nnew\nc}\nd\n", &mut calls);
+        assert_eq!(calls, vec!["{", "new", "c}"]);
+        assert_eq!(depths(&cache), vec![0, 1, 1, 0, 0]);
+
+        calls.clear();
+        assert_eq!(update(&mut cache, "a\n{\nnew\nc}\nd\n", &mut calls), None);
+        assert!(calls.is_empty());
+        let edit = cache.update("a\n", "other", 0, |_, line| vec![(0..line.len(), 9)]);
+        assert_eq!(edit, Some(LineEdit { start: 1, removed: 4, inserted: 0 }));
+        assert_eq!(depths(&cache), vec![9]);
+    }
+
+    #[test]
+    fn intervals_answer_by_position() {
+        let intervals = Intervals::new(vec![10..20, 5..8, 15..25, 30..30]);
+        assert!(intervals.contains(5) && intervals.contains(24));
+        assert!(!intervals.contains(8) && !intervals.contains(25) && !intervals.contains(30));
+
+        let mut edges = Vec::new();
+        intervals.boundaries_in(0..12, &mut edges);
+        assert_eq!(edges, vec![5, 8, 10]);
+        edges.clear();
+        intervals.boundaries_in(12..40, &mut edges);
+        assert_eq!(edges, vec![25]);
+    }
+}
@@ -0,0 +1,248 @@
+// Single Instance
+//
+// The first window listens on a per-user local socket (a named pipe on
+// Windows). Later launches send it their arguments and exit, so scripts and PDF
+// viewers can drive the running editor with `typesafe file.tex:LINE`.
+//
+// On Unix the socket lives in $XDG_RUNTIME_DIR or else in a private folder
+// under the temp dir, and is only used or replaced when the current user owns
+// it, so another account cannot take the name or feed us paths.
+
+use serde::{Deserialize, Serialize};
+use std::io::{self, Read, Write};
+use std::path::PathBuf;
+use std::sync::Arc;
+
+// Requests are a handful of paths; anything bigger is not from us
+const MAX_REQUEST: u64 = 64 * 1024;
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Request {
+    // Where the sender was started, for resolving relative paths
+    pub cwd: PathBuf,
+    pub args: Vec<String>,
+}
+
+fn endpoint_name() -> String {
+    let user = std::env::var("USER").or_else(|_| std::env::var("USERNAME")).unwrap_or_default();
+    let user: String = user.chars().filter(|c| c.is_ascii_alphanumeric() || *c == '_' || *c == '-').collect();
+    format!("typesafe-{}", user)
+}
+
+// Hands `request` to the running instance. Fails if there is none.
+pub fn send(request: &Request) -> io::Result<()> {
+    let mut stream = platform::connect(&endpoint_name())?;
+    let body = serde_json::to_vec(request).map_err(io::Error::other)?;
+    stream.write_all(&body)?;
+    stream.flush()
+}
+
+// Starts listening in the background, calling `on_request` for each launch
+// forwarded by `send`. Fails if another instance is already listening.
+pub fn listen(on_request: impl Fn(Request) + Send + Sync + 'static) -> io::Result<()> {
+    let listener = platform::bind(&endpoint_name())?;
+    let on_request = Arc::new(on_request);
+    std::thread::spawn(move || {
+        for stream in listener {
+            // Read each sender on its own thread so a stalled one holds up nobody
+            let on_request = on_request.clone();
+            std::thread::spawn(move || {
+                let mut body = Vec::new();
+                if stream.take(MAX_REQUEST).read_to_end(&mut body).is_err() {
+                    return;
+                }
+                if let Ok(request) = serde_json::from_slice(&body) {
+                    on_request(request);
+                }
+            });
+        }
+    });
+    Ok(())
+}
+
+#[cfg(unix)]
+mod platform {
+    use std::fs::{DirBuilder, Metadata};
+    use std::io;
+    use std::os::unix::fs::{DirBuilderExt, FileTypeExt, MetadataExt, PermissionsExt};
+    use std::os::unix::net::{UnixListener, UnixStream};
+    use std::path::{Path, PathBuf};
+    use std::time::Duration;
+
+    // A sender that connects and stays quiet is dropped after this long
+    const READ_TIMEOUT: Duration = Duration::from_secs(2);
+    // Pause before accepting again after the system ran short of resources
+    const ACCEPT_RETRY: Duration = Duration::from_millis(500);
+
+    fn socket_path(name: &str) -> io::Result<PathBuf> {
+        let dir = match std::env::var_os("XDG_RUNTIME_DIR").filter(|dir| !dir.is_empty()) {
+            Some(dir) => PathBuf::from(dir),
+            None => {
+                // SAFETY: getuid has no preconditions
+                let dir = std::env::temp_dir().join(format!("typesafe-{}", unsafe { libc::getuid() }));
+                match DirBuilder::new().mode(0o700).create(&dir) {
+                    Err(e) if e.kind() != io::ErrorKind::AlreadyExists => return Err(e),
+                    _ => dir,
+                }
+            }
+        };
+        let meta = std::fs::symlink_metadata(&dir)?;
+        if !meta.is_dir() || meta.permissions().mode() & 0o077 != 0 {
+            return Err(io::Error::new(io::ErrorKind::PermissionDenied, format!("{} is not a private folder", dir.display())));
+        }
+        check_owner(&dir, &meta)?;
+        Ok(dir.join(format!("{}.sock", name)))
+    }
+
+    fn check_owner(path: &Path, meta: &Metadata) -> io::Result<()> {
+        // SAFETY: getuid has no preconditions
+        if meta.uid() != unsafe { libc::getuid() } {
+            return Err(io::Error::new(io::ErrorKind::PermissionDenied, format!("{} belongs to another user", path.display())));
+        }
+        Ok(())
+    }
+
+    // Our own socket at `path`, if there is anything there
+    fn check_socket(path: &Path) -> io::Result<()> {
+        let meta = std::fs::symlink_metadata(path)?;
+        if !meta.file_type().is_socket() {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, format!("{} is not a socket", path.display())));
+        }
+        check_owner(path, &meta)
+    }
+
+    pub fn connect(name: &str) -> io::Result<UnixStream> {
+        let path = socket_path(name)?;
+        check_socket(&path)?;
+        UnixStream::connect(path)
+    }
+
+    pub fn bind(name: &str) -> io::Result<impl Iterator<Item = UnixStream>> {
+        bind_at(socket_path(name)?)
+    }
+
+    pub(super) fn bind_at(path: PathBuf) -> io::Result<impl Iterator<Item = UnixStream>> {
+        let listener = match UnixListener::bind(&path) {
+            Ok(listener) => listener,
+            Err(e) if e.kind() == io::ErrorKind::AddrInUse => {
+                check_socket(&path)?;
+                if UnixStream::connect(&path).is_ok() {
+                    return Err(e);
+                }
+                // Left behind by an instance that did not exit cleanly
+                std::fs::remove_file(&path)?;
+                UnixListener::bind(&path)?
+            }
+            Err(e) => return Err(e),
+        };
+        Ok(std::iter::from_fn(move || loop {
+            match listener.accept() {
+                Ok((stream, _)) => {
+                    if stream.set_read_timeout(Some(READ_TIMEOUT)).is_ok() {
+                        return Some(stream);
+                    }
+                }
+                Err(e) if matches!(e.kind(), io::ErrorKind::Interrupted | io::ErrorKind::ConnectionAborted) => {}
+                // Running out of descriptors or memory may pass; wait for it instead of spinning
+                Err(e) if is_resource_shortage(&e) && path.exists() => std::thread::sleep(ACCEPT_RETRY),
+                // A broken socket, or one whose file is gone, accepts nobody again
+                Err(_) => return None,
+            }
+        }))
+    }
+
+    fn is_resource_shortage(err: &io::Error) -> bool {
+        matches!(err.raw_os_error(), Some(libc::EMFILE | libc::ENFILE | libc::ENOBUFS | libc::ENOMEM))
+    }
+}
+
+#[cfg(windows)]
+mod platform {
+    use std::fs::File;
+    use std::io;
+    use std::os::windows::ffi::OsStrExt;
+    use std::os::windows::io::FromRawHandle;
+    use windows_sys::Win32::Foundation::{CloseHandle, GetLastError, ERROR_PIPE_CONNECTED, HANDLE, INVALID_HANDLE_VALUE};
+    use windows_sys::Win32::Storage::FileSystem::{FILE_FLAG_FIRST_PIPE_INSTANCE, PIPE_ACCESS_INBOUND};
+    use windows_sys::Win32::System::Pipes::{
+        ConnectNamedPipe, CreateNamedPipeW, PIPE_READMODE_BYTE, PIPE_REJECT_REMOTE_CLIENTS, PIPE_TYPE_BYTE, PIPE_UNLIMITED_INSTANCES, PIPE_WAIT,
+    };
+
+    fn pipe_name(name: &str) -> String {
+        format!(r"\\.\pipe\{}", name)
+    }
+
+    pub fn connect(name: &str) -> io::Result<File> {
+        std::fs::OpenOptions::new().write(true).open(pipe_name(name))
+    }
+
+    fn create_instance(wide_name: &[u16], first: bool) -> io::Result<HANDLE> {
+        let open_mode = PIPE_ACCESS_INBOUND | if first { FILE_FLAG_FIRST_PIPE_INSTANCE } else { 0 };
+        let pipe_mode = PIPE_TYPE_BYTE | PIPE_READMODE_BYTE | PIPE_WAIT | PIPE_REJECT_REMOTE_CLIENTS;
+        // SAFETY: `wide_name` is NUL-terminated and outlives the call
+        let handle = unsafe { CreateNamedPipeW(wide_name.as_ptr(), open_mode, pipe_mode, PIPE_UNLIMITED_INSTANCES, 0, 64 * 1024, 0, std::ptr::null()) };
+        if handle == INVALID_HANDLE_VALUE {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(handle)
+    }
+
+    pub fn bind(name: &str) -> io::Result<impl Iterator<Item = File>> {
+        let wide_name: Vec<u16> = std::ffi::OsStr::new(&pipe_name(name)).encode_wide().chain(Some(0)).collect();
+        // Fails while another instance owns the pipe
+        let mut next = Some(create_instance(&wide_name, true)?);
+        Ok(std::iter::from_fn(move || loop {
+            let handle = next.take().or_else(|| create_instance(&wide_name, false).ok())?;
+            // SAFETY: `handle` is a pipe instance we own; a null OVERLAPPED waits for a client
+            let connected = unsafe { ConnectNamedPipe(handle, std::ptr::null_mut()) } != 0 || unsafe { GetLastError() } == ERROR_PIPE_CONNECTED;
+            if connected {
+                // SAFETY: ownership of the handle moves into the File, which closes it
+                return Some(unsafe { File::from_raw_handle(handle as _) });
+            }
+            // SAFETY: `handle` is ours and not used again
+            unsafe { CloseHandle(handle) };
+        }))
+    }
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn forwards_requests_and_replaces_stale_sockets() {
+        let path = std::env::temp_dir().join(format!("typesafe-test-{}.sock", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        // A socket file nobody is listening on
+        drop(std::os::unix::net::UnixListener::bind(&path).unwrap());
+
+        let mut incoming = platform::bind_at(path.clone()).unwrap();
+        // Probes the live socket, leaving an empty connection behind
+        assert!(platform::bind_at(path.clone()).is_err());
+        incoming.next().unwrap();
+
+        let request = Request { cwd: PathBuf::from("/work"), args: vec!["main.tex:3".into()] };
+        let mut stream = std::os::unix::net::UnixStream::connect(&path).unwrap();
+        stream.write_all(&serde_json::to_vec(&request).unwrap()).unwrap();
+        drop(stream);
+
+        let mut body = Vec::new();
+        incoming.next().unwrap().read_to_end(&mut body).unwrap();
+        assert_eq!(serde_json::from_slice::<Request>(&body).unwrap(), request);
+
+        // A sender that never writes times out instead of blocking the listener
+        let _quiet = std::os::unix::net::UnixStream::connect(&path).unwrap();
+        let mut body = Vec::new();
+        assert!(incoming.next().unwrap().read_to_end(&mut body).is_err());
+        let _ = std::fs::remove_file(&path);
+    }
+
+    #[test]
+    fn leaves_files_that_are_not_sockets_alone() {
+        let path = std::env::temp_dir().join(format!("typesafe-test-{}.file", std::process::id()));
+        std::fs::write(&path, "keep").unwrap();
+        assert!(platform::bind_at(path.clone()).is_err());
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "keep");
+        let _ = std::fs::remove_file(&path);
+    }
+}
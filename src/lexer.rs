@@ -0,0 +1,473 @@
+// LaTeX Lexer
+//
+// Splits LaTeX source into semantic tokens for the editor's highlighting. It
+// works a line at a time and carries a small state between lines: whether the
+// text is in math mode, inside the body of a verbatim-like environment, or
+// inside arguments that follow a command. The editor colors tokens from the
+// active theme, and highlights minted and lstlisting bodies in the language
+// named on their \begin line.
+
+use std::ops::Range;
+
+// Environments whose contents are typeset in math mode
+const MATH_ENVIRONMENTS: &[&str] = &[
+    "math", "displaymath", "equation", "equation*", "align", "align*", "alignat", "alignat*", "flalign", "flalign*",
+    "gather", "gather*", "multline", "multline*", "eqnarray", "eqnarray*",
+];
+
+// Environments whose bodies are taken literally
+const VERBATIM_ENVIRONMENTS: &[&str] = &["verbatim", "verbatim*", "Verbatim", "lstlisting", "minted", "comment"];
+
+// Commands followed by a delimited literal argument, as in \verb|x|
+const INLINE_VERBATIM: &[&str] = &["verb", "verb*", "lstinline"];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TokenKind {
+    Text,
+    Command,
+    Environment,
+    Argument,
+    OptionalArgument,
+    MathDelimiter,
+    Math,
+    Comment,
+    Verbatim,
+}
+
+pub type Token = (Range<usize>, TokenKind);
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum MathEnd {
+    Dollar,
+    DoubleDollar,
+    Paren,
+    Bracket,
+    Environment(String),
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+enum Mode {
+    #[default]
+    Text,
+    Math(MathEnd),
+    // On the \begin line of a verbatim environment; the body starts on the next line
+    VerbatimNext { env: String, language: Option<String> },
+    Verbatim { env: String, language: Option<String> },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Group {
+    optional: bool,
+    // Opened right after a command, so another argument may follow it
+    after_command: bool,
+}
+
+// What the lexer knows at the start of a line
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct LexState {
+    mode: Mode,
+    groups: Vec<Group>,
+}
+
+impl LexState {
+    // Language of the code block the line is in, if it names one
+    pub fn embedded_language(&self) -> Option<&str> {
+        match &self.mode {
+            Mode::Verbatim { language, .. } => language.as_deref(),
+            _ => None,
+        }
+    }
+}
+
+struct Lexer<'a> {
+    line: &'a str,
+    pos: usize,
+    state: &'a mut LexState,
+    tokens: Vec<Token>,
+}
+
+impl<'a> Lexer<'a> {
+    fn peek(&self) -> Option<char> {
+        self.line[self.pos..].chars().next()
+    }
+
+    fn emit(&mut self, end: usize, kind: TokenKind) {
+        let start = self.pos;
+        self.pos = end;
+        if start == end {
+            return;
+        }
+        match self.tokens.last_mut() {
+            Some((range, last)) if *last == kind && range.end == start => range.end = end,
+            _ => self.tokens.push((start..end, kind)),
+        }
+    }
+
+    // End of the command starting at the backslash at `self.pos`, and its name
+    fn command(&self) -> (usize, &'a str) {
+        let start = self.pos + 1;
+        let rest = &self.line[start..];
+        let letters = rest.find(|c: char| !c.is_ascii_alphabetic() && c != '@').unwrap_or(rest.len());
+        let end = if letters > 0 {
+            start + letters + usize::from(rest[letters..].starts_with('*'))
+        } else {
+            start + rest.chars().next().map_or(0, char::len_utf8)
+        };
+        let line: &'a str = self.line;
+        (end, &line[start..end])
+    }
+
+    // Kind of plain text at the current nesting
+    fn text_kind(&self) -> TokenKind {
+        match self.state.groups.last() {
+            Some(g) if g.optional => TokenKind::OptionalArgument,
+            Some(_) => TokenKind::Argument,
+            None => TokenKind::Text,
+        }
+    }
+
+    // Opens an argument group if one starts right here
+    fn arguments(&mut self) {
+        let optional = match self.peek() {
+            Some('{') => false,
+            Some('[') => true,
+            _ => return,
+        };
+        self.state.groups.push(Group { optional, after_command: true });
+        let kind = if optional { TokenKind::OptionalArgument } else { TokenKind::Argument };
+        self.emit(self.pos + 1, kind);
+    }
+
+    // `{name}` after \begin or \end, returning the name
+    fn environment_name(&mut self) -> Option<String> {
+        if self.peek() != Some('{') {
+            return None;
+        }
+        let close = self.line[self.pos..].find('}')? + self.pos;
+        let name = self.line[self.pos + 1..close].to_string();
+        self.emit(self.pos + 1, TokenKind::Argument);
+        self.emit(close, TokenKind::Environment);
+        self.emit(close + 1, TokenKind::Argument);
+        Some(name)
+    }
+
+    fn comment(&mut self) {
+        self.emit(self.line.len(), TokenKind::Comment);
+    }
+
+    fn step_text(&mut self) {
+        let Some(c) = self.peek() else { return };
+        match c {
+            '%' => self.comment(),
+            '\\' => {
+                let (end, name) = self.command();
+                match name {
+                    "(" => {
+                        self.emit(end, TokenKind::MathDelimiter);
+                        self.state.mode = Mode::Math(MathEnd::Paren);
+                    }
+                    "[" => {
+                        self.emit(end, TokenKind::MathDelimiter);
+                        self.state.mode = Mode::Math(MathEnd::Bracket);
+                    }
+                    "begin" => {
+                        self.emit(end, TokenKind::Command);
+                        if let Some(env) = self.environment_name() {
+                            if MATH_ENVIRONMENTS.contains(&env.as_str()) {
+                                self.state.mode = Mode::Math(MathEnd::Environment(env));
+                            } else {
+                                if VERBATIM_ENVIRONMENTS.contains(&env.as_str()) {
+                                    let language = embedded_language(&env, &self.line[self.pos..]);
+                                    self.state.mode = Mode::VerbatimNext { env, language };
+                                }
+                                self.arguments();
+                            }
+                        }
+                    }
+                    "end" => {
+                        self.emit(end, TokenKind::Command);
+                        self.environment_name();
+                    }
+                    _ if INLINE_VERBATIM.contains(&name) => {
+                        self.emit(end, TokenKind::Command);
+                        self.inline_verbatim();
+                    }
+                    _ => {
+                        self.emit(end, TokenKind::Command);
+                        self.arguments();
+                    }
+                }
+            }
+            '$' => {
+                if self.line[self.pos..].starts_with("$$") {
+                    self.emit(self.pos + 2, TokenKind::MathDelimiter);
+                    self.state.mode = Mode::Math(MathEnd::DoubleDollar);
+                } else {
+                    self.emit(self.pos + 1, TokenKind::MathDelimiter);
+                    self.state.mode = Mode::Math(MathEnd::Dollar);
+                }
+            }
+            '{' if !self.state.groups.is_empty() => {
+                let optional = self.state.groups.last().is_some_and(|g| g.optional);
+                self.state.groups.push(Group { optional, after_command: false });
+                self.emit(self.pos + 1, self.text_kind());
+            }
+            '}' | ']' if self.state.groups.last().is_some_and(|g| g.optional == (c == ']')) => {
+                let kind = self.text_kind();
+                self.emit(self.pos + 1, kind);
+                if self.state.groups.pop().is_some_and(|g| g.after_command) {
+                    self.arguments();
+                }
+            }
+            _ => {
+                let len = self.line[self.pos..]
+                    .find(['%', '\\', '$', '{', '}', '[', ']'])
+                    .unwrap_or(self.line.len() - self.pos)
+                    .max(c.len_utf8());
+                self.emit(self.pos + len, self.text_kind());
+            }
+        }
+    }
+
+    // The delimited argument of \verb and friends
+    fn inline_verbatim(&mut self) {
+        let Some(open) = self.peek() else { return };
+        let close = if open == '{' { '}' } else { open };
+        let body = self.pos + open.len_utf8();
+        let end = self.line[body..].find(close).map_or(self.line.len(), |i| body + i + close.len_utf8());
+        self.emit(end, TokenKind::Verbatim);
+    }
+
+    fn step_math(&mut self, until: &MathEnd) {
+        let Some(c) = self.peek() else { return };
+        match c {
+            '%' => self.comment(),
+            '\\' => {
+                let (end, name) = self.command();
+                match (name, until) {
+                    (")", MathEnd::Paren) | ("]", MathEnd::Bracket) => {
+                        self.emit(end, TokenKind::MathDelimiter);
+                        self.state.mode = Mode::Text;
+                    }
+                    ("begin", _) | ("end", _) => {
+                        let closing = match until {
+                            MathEnd::Environment(env) if name == "end" => self.line[end..].starts_with(&format!("{{{}}}", env)),
+                            _ => false,
+                        };
+                        self.emit(end, TokenKind::Command);
+                        self.environment_name();
+                        if closing {
+                            self.state.mode = Mode::Text;
+                        }
+                    }
+                    _ => self.emit(end, TokenKind::Command),
+                }
+            }
+            '$' if *until == MathEnd::Dollar => {
+                self.emit(self.pos + 1, TokenKind::MathDelimiter);
+                self.state.mode = Mode::Text;
+            }
+            '$' if *until == MathEnd::DoubleDollar && self.line[self.pos..].starts_with("$$") => {
+                self.emit(self.pos + 2, TokenKind::MathDelimiter);
+                self.state.mode = Mode::Text;
+            }
+            _ => {
+                let len = self.line[self.pos + 1..].find(['%', '\\', '$']).map_or(self.line.len() - self.pos, |i| i + 1);
+                self.emit(self.pos + len, TokenKind::Math);
+            }
+        }
+    }
+
+    fn step_verbatim(&mut self, env: &str) {
+        let end = format!("\\end{{{}}}", env);
+        match self.line[self.pos..].find(&end) {
+            Some(i) => {
+                self.emit(self.pos + i, verbatim_kind(env));
+                self.state.mode = Mode::Text;
+            }
+            None => self.emit(self.line.len(), verbatim_kind(env)),
+        }
+    }
+}
+
+fn verbatim_kind(env: &str) -> TokenKind {
+    if env == "comment" {
+        TokenKind::Comment
+    } else {
+        TokenKind::Verbatim
+    }
+}
+
+// Tokens of one line (with or without its line break), advancing `state` past it
+pub fn lex_line(state: &mut LexState, line: &str) -> Vec<Token> {
+    // A paragraph break ends any arguments left open by unbalanced braces
+    if line.trim().is_empty() && state.mode == Mode::Text {
+        state.groups.clear();
+    }
+
+    let mut lexer = Lexer { line, pos: 0, state, tokens: Vec::new() };
+    while lexer.pos < line.len() {
+        match lexer.state.mode.clone() {
+            Mode::Text | Mode::VerbatimNext { .. } => lexer.step_text(),
+            Mode::Math(until) => lexer.step_math(&until),
+            Mode::Verbatim { env, .. } => lexer.step_verbatim(&env),
+        }
+    }
+    if let Mode::VerbatimNext { env, language } = &lexer.state.mode {
+        lexer.state.mode = Mode::Verbatim { env: env.clone(), language: language.clone() };
+    }
+    lexer.tokens
+}
+
+// Tokens of a whole document, with byte ranges into `text`
+pub fn lex(text: &str) -> Vec<Token> {
+    let mut state = LexState::default();
+    let mut offset = 0;
+    let mut tokens: Vec<Token> = Vec::new();
+    for line in text.split_inclusive('\n') {
+        for (range, kind) in lex_line(&mut state, line) {
+            let range = range.start + offset..range.end + offset;
+            match tokens.last_mut() {
+                Some((last, last_kind)) if *last_kind == kind && last.end == range.start => last.end = range.end,
+                _ => tokens.push((range, kind)),
+            }
+        }
+        offset += line.len();
+    }
+    tokens
+}
+
+// Byte ranges of verbatim text and code blocks, which the spelling and bracket checks leave alone
+pub fn verbatim_ranges(text: &str) -> Vec<Range<usize>> {
+    lex(text).into_iter().filter(|(_, kind)| *kind == TokenKind::Verbatim).map(|(range, _)| range).collect()
+}
+
+// Contents of the group opening `s` with `open`, and the text after it
+fn group(s: &str, open: char, close: char) -> Option<(&str, &str)> {
+    let inner = s.strip_prefix(open)?;
+    let mut depth = 0;
+    for (i, c) in inner.char_indices() {
+        if c == open {
+            depth += 1;
+        } else if c == close {
+            if depth == 0 {
+                return Some((&inner[..i], &inner[i + 1..]));
+            }
+            depth -= 1;
+        }
+    }
+    None
+}
+
+// Language given to a minted or lstlisting block by the rest of its \begin line:
+// `[options]{python}` for minted, `[language=Rust]` for lstlisting
+fn embedded_language(env: &str, rest: &str) -> Option<String> {
+    let (options, after) = group(rest, '[', ']').unwrap_or(("", rest));
+    let language = match env {
+        "minted" => group(after, '{', '}')?.0,
+        "lstlisting" => {
+            let value = options.split(',').find_map(|option| {
+                let (key, value) = option.split_once('=')?;
+                (key.trim() == "language").then_some(value.trim())
+            })?;
+            let value = value.trim_start_matches('{').trim_end_matches('}');
+            // Drop a dialect, as in [Sharp]C
+            group(value, '[', ']').map_or(value, |(_, name)| name)
+        }
+        _ => return None,
+    };
+    Some(language.trim().to_string()).filter(|l| !l.is_empty())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use TokenKind::*;
+
+    // The text of each token, with its kind
+    fn pieces(text: &str) -> Vec<(&str, TokenKind)> {
+        lex(text).into_iter().map(|(r, kind)| (&text[r], kind)).collect()
+    }
+
+    #[test]
+    fn commands_arguments_and_comments() {
+        assert_eq!(
+            pieces("\\section*[Short]{Größe} text % note\n"),
+            vec![
+                ("\\section*", Command),
+                ("[Short]", OptionalArgument),
+                ("{Größe}", Argument),
+                (" text ", Text),
+                ("% note\n", Comment),
+            ]
+        );
+        assert_eq!(
+            pieces("50\\% \\textbf{a \\emph{b} c}\\\\"),
+            vec![
+                ("50", Text),
+                ("\\%", Command),
+                (" ", Text),
+                ("\\textbf", Command),
+                ("{a ", Argument),
+                ("\\emph", Command),
+                ("{b} c}", Argument),
+                ("\\\\", Command),
+            ]
+        );
+    }
+
+    #[test]
+    fn math_modes() {
+        assert_eq!(
+            pieces("a $x^2 % c\n+ \\alpha$ b"),
+            vec![
+                ("a ", Text),
+                ("$", MathDelimiter),
+                ("x^2 ", Math),
+                ("% c\n", Comment),
+                ("+ ", Math),
+                ("\\alpha", Command),
+                ("$", MathDelimiter),
+                (" b", Text),
+            ]
+        );
+        assert_eq!(pieces("\\[ x \\]")[..2], [("\\[", MathDelimiter), (" x ", Math)]);
+
+        let env = pieces("\\begin{align}\nx &= 1 \\\\\n\\end{align}\ny");
+        assert_eq!(env[2], ("align", Environment));
+        assert_eq!(env[4], ("\nx &= 1 ", Math));
+        assert_eq!(env.last(), Some(&("\ny", Text)));
+    }
+
+    #[test]
+    fn verbatim_bodies_are_literal() {
+        let text = "\\begin{minted}[linenos]{python}\nx = {1: '$'} # \\end\n\\end{minted} \\verb|%$\\x| done";
+        let tokens = pieces(text);
+        assert!(tokens.contains(&("minted", Environment)));
+        assert!(tokens.contains(&("{python}", Argument)));
+        assert!(tokens.contains(&("x = {1: '$'} # \\end\n", Verbatim)));
+        assert!(tokens.contains(&("|%$\\x|", Verbatim)));
+        assert_eq!(tokens.last(), Some(&(" done", Text)));
+
+        let mut state = LexState::default();
+        lex_line(&mut state, "\\begin{lstlisting}[language=Rust]\n");
+        assert_eq!(state.embedded_language(), Some("Rust"));
+        assert_eq!(lex_line(&mut state, "\\end{lstlisting}\n")[0], (0..4, Command));
+        assert_eq!(state, LexState::default());
+
+        assert_eq!(verbatim_ranges(text), vec![32..52, 70..76]);
+    }
+
+    #[test]
+    fn code_block_languages() {
+        assert_eq!(embedded_language("minted", "{python}\n").as_deref(), Some("python"));
+        assert_eq!(embedded_language("minted", "[linenos, frame=lines]{rust}").as_deref(), Some("rust"));
+        assert_eq!(embedded_language("lstlisting", "[caption={A [b]}, language = {[Sharp]C}]").as_deref(), Some("C"));
+        assert_eq!(embedded_language("lstlisting", "[caption=x]\n"), None);
+        assert_eq!(embedded_language("verbatim", "{python}"), None);
+
+        let mut state = LexState::default();
+        lex_line(&mut state, "\\begin{verbatim}\n");
+        assert_eq!(state.embedded_language(), None);
+    }
+}
@@ -0,0 +1,92 @@
+// Magic Comments
+//
+// TeXShop/TeXstudio-style directives in the first lines of a file:
+//
+//     % !TEX root = ../thesis.tex
+//     % !TEX program = xelatex
+//     % !TEX spellcheck = en-GB
+//     % !BIB program = biber
+//
+// Keys are case-insensitive and `TS-program` is read as `program`.
+
+use crate::synctex::normalize;
+use std::path::{Path, PathBuf};
+
+// Directives further down than this are ignored, as in TeXShop
+const MAX_LINES: usize = 20;
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct MagicComments {
+    // As written; see `root_for`
+    pub root: Option<String>,
+    pub program: Option<String>,
+    pub spellcheck: Option<String>,
+    pub bib_program: Option<String>,
+}
+
+impl MagicComments {
+    pub fn parse(text: &str) -> Self {
+        let mut magic = Self::default();
+        for line in text.lines().take(MAX_LINES) {
+            let Some((kind, key, value)) = directive(line) else { continue };
+            let slot = match (kind.as_str(), key.as_str()) {
+                ("tex", "root") => &mut magic.root,
+                ("tex", "program" | "ts-program") => &mut magic.program,
+                ("tex", "spellcheck") => &mut magic.spellcheck,
+                ("bib", "program" | "ts-program") => &mut magic.bib_program,
+                _ => continue,
+            };
+            // The first occurrence wins
+            if slot.is_none() {
+                *slot = Some(value);
+            }
+        }
+        magic
+    }
+
+    // The root document named by a file at `file`, resolved against its folder
+    pub fn root_for(&self, file: &Path) -> Option<PathBuf> {
+        let root = self.root.as_ref()?;
+        let dir = file.parent().unwrap_or(Path::new(""));
+        Some(normalize(&dir.join(root)))
+    }
+}
+
+// Splits `% !TEX key = value` into lowercase ("tex", "key") and the value
+fn directive(line: &str) -> Option<(String, String, String)> {
+    let rest = line.trim_start().strip_prefix('%')?.trim_start_matches('%').trim_start();
+    let rest = rest.strip_prefix('!')?;
+    let (head, value) = rest.split_once('=')?;
+    let mut words = head.split_whitespace();
+    let kind = words.next()?.to_lowercase();
+    let key = words.next()?.to_lowercase();
+    let value = value.trim();
+    if words.next().is_some() || value.is_empty() || !matches!(kind.as_str(), "tex" | "bib") {
+        return None;
+    }
+    Some((kind, key, value.to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reads_directives_at_the_top() {
+        let text = "% !TEX root = ../thesis.tex\n%!TeX TS-program = XeLaTeX\n%% !tex spellcheck=de-DE\n% !BIB program = bibtex\n\\chapter{Intro}\n";
+        let magic = MagicComments::parse(text);
+        assert_eq!(magic.root.as_deref(), Some("../thesis.tex"));
+        assert_eq!(magic.program.as_deref(), Some("XeLaTeX"));
+        assert_eq!(magic.spellcheck.as_deref(), Some("de-DE"));
+        assert_eq!(magic.bib_program.as_deref(), Some("bibtex"));
+        assert_eq!(magic.root_for(Path::new("/work/chapters/intro.tex")), Some(PathBuf::from("/work/thesis.tex")));
+    }
+
+    #[test]
+    fn ignores_other_text() {
+        let mut text = String::from("% !TEX root =\n% TEX root = a.tex\n\\input{x} % !TEX program = lualatex\n% !TEX encoding = UTF-8\n");
+        text.push_str(&"\n".repeat(MAX_LINES));
+        text.push_str("% !TEX root = late.tex\n");
+        assert_eq!(MagicComments::parse(&text), MagicComments::default());
+    }
+}
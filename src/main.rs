@@ -1,6 +1,34 @@
 #![cfg_attr(not(debug_assertions), windows_subsystem = "windows")]
 
 // Typesafe Editor
+mod cli;
+mod compiler;
+mod desktop;
+mod document;
+mod folding;
+mod highlight;
+mod ipc;
+mod lexer;
+mod magic;
+mod project;
+mod recovery;
+mod render;
+mod session;
+mod synctex;
+mod texlog;
+
+use cli::LaunchArgs;
+use document::Document;
+use folding::Folds;
+use highlight::{Intervals, LineCache, LineState, SpanStyle};
+use lexer::TokenKind;
+use magic::MagicComments;
+use project::ProjectConfig;
+use recovery::{DiffKind, Swap, SwapStore, SwapWriter};
+use compiler::{BibTool, BuildManager, CompilationMsg, CompileJob, Diagnostic, Engine, EngineSettings, Severity};
+use render::{PageCache, RenderJob, RenderMsg, RenderWorker, SearchHit};
+use session::{OpenFile, ProjectSession, Session};
+use synctex::SyncTexIndex;
 use crossbeam_channel::{unbounded, Receiver, Sender};
 use serde::{Deserialize, Serialize};
 use image;
@@ -10,20 +38,19 @@ use egui::{
 use egui::epaint::Shadow;
 use egui::text::{CCursor, CCursorRange};
 use eframe::egui;
-use sha2::{Digest, Sha256};
 use pdfium_render::prelude::*;
 use std::path::PathBuf;
-use std::process::{Command, Stdio};
-use syntect::easy::HighlightLines;
-use syntect::highlighting::{ThemeSet, Style};
-use syntect::parsing::SyntaxSet;
-use syntect::util::LinesWithEndings;
-use flate2::read::GzDecoder;
-use std::io::Read;
+use syntect::highlighting::{HighlightIterator, HighlightState, Highlighter, ThemeSet};
+use syntect::parsing::{ParseState, ScopeStack, SyntaxSet};
 use std::io::Write;
 use unicode_segmentation::UnicodeSegmentation;
 
 const INDENT_UNIT: &str = "    ";
+const NEW_DOCUMENT: &str = "\\documentclass{article}\n\\begin{document}\n\n\\end{document}";
+// Seconds between session saves while running
+const SESSION_SAVE_INTERVAL: f64 = 60.0;
+// Seconds between journaling unsaved buffers to their swap files
+const SWAP_INTERVAL: f64 = 5.0;
 
 // Themes
 
@@ -111,6 +138,21 @@ impl ThemeColors {
         }
     }
 
+    // Editor color of a LaTeX token
+    pub fn token_color(&self, kind: TokenKind) -> Color32 {
+        match kind {
+            TokenKind::Text => self.text_primary,
+            TokenKind::Command => self.accent,
+            TokenKind::Environment => self.success,
+            TokenKind::Argument => mix(self.text_primary, self.accent_hover, 0.5),
+            TokenKind::OptionalArgument => self.text_secondary,
+            TokenKind::MathDelimiter => self.warning,
+            TokenKind::Math => mix(self.text_primary, self.warning, 0.6),
+            TokenKind::Comment => self.disabled,
+            TokenKind::Verbatim => mix(self.text_primary, self.success, 0.6),
+        }
+    }
+
     // Serendipity
     fn serendipity() -> Self {
         Self {
@@ -361,13 +403,60 @@ impl AccentColor {
 
 // Compilation Messages
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
 enum PdfFitMode {
     Normal,
     FitWidth,
     FitPage,
 }
 
+// The two scrolling PDF views; the embedded one also serves opened PDF files
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+enum PdfViewer {
+    Preview,
+    Popout,
+}
+
+// Forward-search target, flashed over the PDF and faded out
+#[derive(Clone, Debug)]
+struct SyncHighlight {
+    // (page, rect in PDF points, top-left origin)
+    area: Vec<(usize, egui::Rect)>,
+    started: f64,
+}
+
+enum RecoveryChoice {
+    Recover,
+    Discard,
+    Later,
+}
+
+// Asks what to do with a swap left for a file that is being opened, before its
+// disk copy is shown
+fn ask_to_recover(swap: &Swap) -> RecoveryChoice {
+    let disk = std::fs::read_to_string(&swap.path).unwrap_or_default();
+    let diff = recovery::diff_lines(&disk, &swap.content);
+    let added = diff.iter().filter(|(kind, _)| *kind == DiffKind::Added).count();
+    let removed = diff.iter().filter(|(kind, _)| *kind == DiffKind::Removed).count();
+    let name = std::path::Path::new(&swap.path).file_name().unwrap_or_default().to_string_lossy().to_string();
+    let buttons = ["Recover", "Discard", "Decide Later"];
+    let result = rfd::MessageDialog::new()
+        .set_title("Recover Unsaved Changes")
+        .set_description(format!(
+            "Typesafe closed with unsaved changes to {}: {} line(s) added and {} removed compared to the file on disk.\n\nRecover them as unsaved changes, discard them, or open the file as saved and decide at the next start?",
+            name, added, removed
+        ))
+        .set_buttons(rfd::MessageButtons::YesNoCancelCustom(buttons[0].into(), buttons[1].into(), buttons[2].into()))
+        .show();
+    match result {
+        rfd::MessageDialogResult::Yes => RecoveryChoice::Recover,
+        rfd::MessageDialogResult::No => RecoveryChoice::Discard,
+        rfd::MessageDialogResult::Custom(label) if label == buttons[0] => RecoveryChoice::Recover,
+        rfd::MessageDialogResult::Custom(label) if label == buttons[1] => RecoveryChoice::Discard,
+        _ => RecoveryChoice::Later,
+    }
+}
+
 #[derive(Clone, Copy, PartialEq)]
 enum CurrentFileType {
     Tex,
@@ -376,21 +465,55 @@ enum CurrentFileType {
     Other,
 }
 
-#[derive(Clone, Debug)]
-struct Diagnostic {
-    message: String,
-    line: usize,
-    #[allow(dead_code)]
-    file: String,
+// An open file shown as an editor tab. The active buffer is edited through the
+// editor fields on TypesafeApp, which hold its text until another tab is shown;
+// the rest of its entry here is refreshed on tab switches and session saves.
+struct Buffer {
+    path: String,
+    content: Document,
+    is_dirty: bool,
+    file_type: CurrentFileType,
+    // Cursor and undo history of the editor widget
+    edit_state: Option<egui::text_edit::TextEditState>,
+    scroll_offset: f32,
+    syntax_errors: Vec<std::ops::Range<usize>>,
+    spell_errors: Vec<std::ops::Range<usize>>,
+    // First lines of the folded regions
+    folds: Vec<usize>,
+    // Names the buffer's swap, which its path cannot for untitled buffers
+    swap_id: String,
 }
 
-enum CompilationMsg {
-    Start,
-    #[allow(dead_code)]
-    Log(String),
-    Diagnostics(Vec<Diagnostic>),
-    Success(PathBuf, String, String),
-    Error(String),
+impl Buffer {
+    fn new(path: String, content: String) -> Self {
+        let file_type = TypesafeApp::determine_file_type(&path);
+        Self {
+            path,
+            content: content.into(),
+            is_dirty: false,
+            file_type,
+            edit_state: None,
+            scroll_offset: 0.0,
+            syntax_errors: Vec::new(),
+            spell_errors: Vec::new(),
+            folds: Vec::new(),
+            swap_id: recovery::new_id(),
+        }
+    }
+
+    // A tab from the session, read from disk with its cursor, scroll position and folds
+    fn restore(file: &OpenFile) -> Self {
+        let content = if TypesafeApp::determine_file_type(&file.path) == CurrentFileType::Pdf {
+            String::new()
+        } else {
+            std::fs::read_to_string(&file.path).unwrap_or_default()
+        };
+        let mut buffer = Self::new(file.path.clone(), content);
+        buffer.edit_state = Some(cursor_state(file.cursor));
+        buffer.scroll_offset = file.scroll_offset;
+        buffer.folds = file.folds.clone();
+        buffer
+    }
 }
 
 // Settings and App State
@@ -423,10 +546,31 @@ struct Settings {
     pub autosave_on_compile: bool,
     #[serde(default = "default_true")]
     pub autosave_on_change: bool,
+    #[serde(default)]
+    pub build: EngineSettings,
+    // Per-project overrides, keyed by project folder
+    #[serde(default)]
+    pub project_builds: std::collections::HashMap<String, EngineSettings>,
+    // 0 disables the limit
+    #[serde(default = "default_compile_timeout")]
+    pub compile_timeout_secs: u64,
+    // "en", "de", ... picks dictionary.txt or dictionary-<lang>.txt; "off" disables
+    #[serde(default = "default_spelling_language")]
+    pub spelling_language: String,
+    // Build directory relative to the root document's folder, e.g. "build"; empty builds beside the sources
+    #[serde(default)]
+    pub output_dir: String,
+    // Project files whose build hooks may run: path to the SHA-256 they were trusted at
+    #[serde(default)]
+    pub trusted_hooks: std::collections::HashMap<String, String>,
 }
 
 fn default_true() -> bool { true }
 
+fn default_compile_timeout() -> u64 { 300 }
+
+fn default_spelling_language() -> String { "en".to_string() }
+
 impl Default for Settings {
     fn default() -> Self {
         Self {
@@ -439,6 +583,12 @@ impl Default for Settings {
             autosave_timer: true,
             autosave_on_compile: true,
             autosave_on_change: true,
+            build: EngineSettings::default(),
+            project_builds: std::collections::HashMap::new(),
+            compile_timeout_secs: default_compile_timeout(),
+            spelling_language: default_spelling_language(),
+            output_dir: String::new(),
+            trusted_hooks: std::collections::HashMap::new(),
         }
     }
 }
@@ -468,6 +618,27 @@ impl Settings {
         Self::default()
     }
 
+    // Engine settings of the closest project folder containing `path`, else the global ones
+    fn build_settings_for(&self, path: &std::path::Path) -> &EngineSettings {
+        path.ancestors()
+            .find_map(|dir| self.project_builds.get(dir.to_string_lossy().as_ref()))
+            .unwrap_or(&self.build)
+    }
+
+    // Whether `project`'s hooks were trusted with the file as it is now
+    fn hooks_trusted(&self, project: &ProjectConfig) -> bool {
+        self.trusted_hooks.get(project.path.to_string_lossy().as_ref()) == Some(&project.hash)
+    }
+
+    fn output_dir_for(&self, root: &std::path::Path) -> Option<PathBuf> {
+        let dir = self.output_dir.trim();
+        (!dir.is_empty()).then(|| root.parent().unwrap_or(std::path::Path::new(".")).join(dir))
+    }
+
+    fn compile_timeout(&self) -> Option<std::time::Duration> {
+        (self.compile_timeout_secs > 0).then(|| std::time::Duration::from_secs(self.compile_timeout_secs))
+    }
+
     fn save(&self) {
         let path = Self::path();
         if let Ok(content) = serde_json::to_string_pretty(self) {
@@ -512,10 +683,16 @@ struct LatexData {
 
 struct TypesafeApp {
     // Editor
-    editor_content: String,
+    editor_content: Document,
     file_path: String,
     current_dir: std::path::PathBuf,
+    // Folder last opened as a project; browsing the file panel moves current_dir but not this
+    project_dir: std::path::PathBuf,
     root_file: Option<String>,
+    // .typesafe.toml/.json of the project folder or the root document's folder
+    project: Option<ProjectConfig>,
+    // Project files whose hooks were turned down this session, so builds do not ask again
+    declined_hooks: std::collections::HashSet<PathBuf>,
     show_file_panel: bool,
     show_preview_panel: bool,
     last_window_width: f32,
@@ -526,6 +703,8 @@ struct TypesafeApp {
     context_menu_suggestions: Vec<String>,
     context_menu_replace_range: Option<std::ops::Range<usize>>,
     dictionary: std::collections::HashSet<String>,
+    // Language `dictionary` was loaded for
+    dictionary_language: String,
     user_dictionary: std::collections::HashSet<String>,
     ignored_words: std::collections::HashSet<String>,
     synonym_cache: std::collections::HashMap<String, Vec<String>>,
@@ -534,6 +713,12 @@ struct TypesafeApp {
     synonym_tx: Sender<(String, Vec<String>)>,
     is_dirty: bool,
 
+    // Tabs
+    buffers: Vec<Buffer>,
+    active_buffer: usize,
+    editor_scroll_offset: f32,
+    pending_editor_scroll: Option<f32>,
+
     // Debounced Diagnostics
     last_edit_time: f64,
     checks_dirty: bool,
@@ -548,13 +733,28 @@ struct TypesafeApp {
     // Syntax Highlighting
     syntax_set: SyntaxSet,
     theme_set: ThemeSet,
+    highlight_cache: LineCache<LineState, SpanStyle>,
+
+    // Code Folding
+    folds: Folds,
 
     // Preview
     preview_status: String,
-    pdf_textures: std::collections::HashMap<usize, egui::TextureHandle>,
-    preview_size: Option<[usize; 2]>,
+    page_cache: PageCache<egui::TextureHandle>,
     pdf_path: Option<PathBuf>,
-    pdfium: Option<Pdfium>,
+    pdfium: Option<std::sync::Arc<Pdfium>>,
+    pdf_renderer: Option<RenderWorker>,
+    render_rx: Receiver<RenderMsg>,
+    render_tx: Sender<RenderMsg>,
+    // Document the render worker has open (or is opening)
+    pdf_opened: Option<PathBuf>,
+    // Pages each viewer drew last frame and the zoom it drew them at
+    pdf_visible: std::collections::HashMap<PdfViewer, (Vec<usize>, f32)>,
+    // Jobs sent to the render worker that have not come back yet
+    pdf_sent_jobs: Vec<RenderJob>,
+    // Page and relative offset at the top of each viewer, restored after a rebuild
+    pdf_anchors: std::collections::HashMap<PdfViewer, (usize, f32)>,
+    pdf_restore: std::collections::HashSet<PdfViewer>,
     page_count: usize,
     current_page: usize,
     zoom: f32,
@@ -574,20 +774,41 @@ struct TypesafeApp {
 
     // SyncTeX
     page_sizes: std::collections::HashMap<usize, (f32, f32)>,
+    synctex: Option<SyncTexIndex>,
+    synctex_rx: Receiver<(PathBuf, SyncTexIndex)>,
+    synctex_tx: Sender<(PathBuf, SyncTexIndex)>,
+    // Folder the latest build ran in; SyncTeX input paths are relative to it
+    build_source_dir: PathBuf,
     pending_scroll_target: Option<(usize, f32)>,
     pending_cursor_scroll: Option<usize>,
+    sync_highlight: Option<SyncHighlight>,
 
     // Compilation
     compilation_log: String,
     show_log: bool,
     is_compiling: bool,
     compile_rx: Receiver<CompilationMsg>,
-    compile_tx: Sender<CompilationMsg>,
+    build_manager: BuildManager,
     pending_autocompile: bool,
+    // Files named on the command line, opened on the first frame
+    pending_launch: Option<LaunchArgs>,
+    // Launches forwarded by later `typesafe` invocations
+    launch_rx: Option<Receiver<LaunchArgs>>,
     diagnostics: Vec<Diagnostic>,
+    diagnostic_filter: std::collections::HashSet<Severity>,
 
     // UI state
     settings: Settings,
+    session: Session,
+    // The restored active tab is shown on the first frame, once there is a context
+    pending_session_restore: bool,
+    // Swaps of the tabs open at startup are asked about on the first frame, once there is a window
+    pending_buffer_recovery: bool,
+    // Preview position to return to when this PDF is next opened
+    pending_pdf_anchor: Option<(PathBuf, usize, f32)>,
+    last_session_save: f64,
+    // Result of the last file association change, shown under Permissions
+    integration_status: Option<String>,
 
     // Autocomplete state
     completion_suggestions: Vec<(String, String)>,
@@ -624,11 +845,23 @@ struct TypesafeApp {
     // PDF search
     pdf_search_query: String,
     show_pdf_search: bool,
+    pdf_search_results: Vec<SearchHit>,
+    pdf_search_index: usize,
 
     // Compilation Optimization
     last_bcf_hash: String,
     last_bib_hash: String,
 
+    // Crash Recovery
+    swap_writer: SwapWriter,
+    // Content hash of each buffer's swap as last written
+    swapped: std::collections::HashMap<String, u64>,
+    last_swap_time: f64,
+    // Swaps left by an earlier run, offered one at a time
+    recovery_queue: Vec<Swap>,
+    // Disk-to-swap diff of recovery_queue[0]
+    recovery_diff: Option<Vec<(DiffKind, String)>>,
+
     // File Management
     rename_dialog_open: bool,
     rename_target_path: std::path::PathBuf,
@@ -638,6 +871,8 @@ struct TypesafeApp {
 impl Default for TypesafeApp {
     fn default() -> Self {
         let (tx, rx) = unbounded();
+        let (synctex_tx, synctex_rx) = unbounded();
+        let (render_tx, render_rx) = unbounded();
         let (syn_tx, syn_rx) = unbounded();
 
         // Load syntax highlighting data
@@ -793,38 +1028,23 @@ impl Default for TypesafeApp {
             }
         }
 
+        // The last session's tabs win over last_file
+        let session = Session::load();
+        let (restored_files, restored_active) = session.last_project().map(ProjectSession::existing_files).unwrap_or_default();
+        if let Some(project) = session.last_project() {
+            current_dir = project.dir.clone();
+        }
+        if let Some(file) = restored_files.get(restored_active) {
+            default_file = file.path.clone();
+            found_file = true;
+        }
+
         let default_content = std::fs::read_to_string(&default_file).unwrap_or_else(|_| {
             "\\documentclass{article}\n\\begin{document}\nHello Typesafe!\n\\end{document}".to_string()
         });
 
-        // Initialize Dictionary
-        let mut dictionary = std::collections::HashSet::new();
-        let dict_path = std::path::Path::new("dictionary.txt");
-        let root_dict_path = std::path::Path::new("../../dictionary.txt");
-
-        if dict_path.exists() {
-            if let Ok(content) = std::fs::read_to_string(dict_path) {
-                for line in content.lines() {
-                    dictionary.insert(line.trim().to_lowercase());
-                }
-            }
-        } else if root_dict_path.exists() {
-            if let Ok(content) = std::fs::read_to_string(root_dict_path) {
-                for line in content.lines() {
-                    dictionary.insert(line.trim().to_lowercase());
-                }
-            }
-        } else {
-             // Attempt download in a separate thread
-             std::thread::spawn(|| {
-                 // Using atebits/Words for a better standard english dictionary
-                 if let Ok(resp) = reqwest::blocking::get("https://raw.githubusercontent.com/atebits/Words/master/Words/en.txt") {
-                     if let Ok(text) = resp.text() {
-                         let _ = std::fs::write("dictionary.txt", &text);
-                     }
-                 }
-             });
-        }
+        let dictionary = load_dictionary(&settings.spelling_language);
+        let dictionary_language = settings.spelling_language.clone();
 
         // Load User Dictionary
         let mut user_dictionary = std::collections::HashSet::new();
@@ -837,11 +1057,40 @@ impl Default for TypesafeApp {
             }
         }
 
+        let mut buffers = if restored_files.is_empty() {
+            vec![Buffer::new(default_file.clone(), default_content)]
+        } else {
+            restored_files.iter().map(Buffer::restore).collect()
+        };
+
+        // Swaps that still differ from their files are offered back
+        let swap_store = SwapStore::default_location();
+        let recovery_queue: Vec<Swap> = swap_store
+            .load_all()
+            .into_iter()
+            .filter(|swap| {
+                let recoverable = recovery::is_recoverable(swap);
+                if !recoverable {
+                    swap_store.remove(&swap.id);
+                }
+                recoverable
+            })
+            .collect();
+        let last = buffers.len() - 1;
+        let default_content = std::mem::take(&mut buffers[restored_active.min(last)].content);
+
         let mut app = Self {
+            buffers,
+            active_buffer: restored_active,
+            editor_scroll_offset: 0.0,
+            pending_editor_scroll: None,
             editor_content: default_content,
             file_path: default_file,
+            project_dir: current_dir.clone(),
             current_dir,
             root_file: None,
+            project: None,
+            declined_hooks: std::collections::HashSet::new(),
             show_file_panel: true,
             show_preview_panel: true,
             last_window_width: 1200.0,
@@ -852,6 +1101,7 @@ impl Default for TypesafeApp {
             context_menu_suggestions: Vec::new(),
             context_menu_replace_range: None,
             dictionary,
+            dictionary_language,
             user_dictionary,
             ignored_words: std::collections::HashSet::new(),
             synonym_cache: std::collections::HashMap::new(),
@@ -868,16 +1118,25 @@ impl Default for TypesafeApp {
             cmd_selected_index: 0,
             syntax_set,
             theme_set,
+            highlight_cache: LineCache::default(),
+            folds: Folds::default(),
             preview_status: "Ready to compile".to_string(),
-            pdf_textures: std::collections::HashMap::new(),
-            preview_size: None,
+            page_cache: PageCache::new(render::TEXTURE_BUDGET),
             pdf_path: None,
             pdfium: Pdfium::bind_to_library(Pdfium::pdfium_platform_library_name_at_path("."))
                 .or_else(|_| Pdfium::bind_to_library(Pdfium::pdfium_platform_library_name_at_path("deps")))
                 .or_else(|_| Pdfium::bind_to_library(Pdfium::pdfium_platform_library_name_at_path("../../deps")))
                 .or_else(|_| Pdfium::bind_to_system_library())
-                .map(Pdfium::new)
+                .map(|bindings| std::sync::Arc::new(Pdfium::new(bindings)))
                 .ok(),
+            pdf_renderer: None,
+            render_rx,
+            render_tx,
+            pdf_opened: None,
+            pdf_visible: std::collections::HashMap::new(),
+            pdf_sent_jobs: Vec::new(),
+            pdf_anchors: std::collections::HashMap::new(),
+            pdf_restore: std::collections::HashSet::new(),
             page_count: 0,
             current_page: 0,
             zoom: 1.0,
@@ -893,13 +1152,27 @@ impl Default for TypesafeApp {
             show_log: false,
             is_compiling: false,
             compile_rx: rx,
-            compile_tx: tx,
+            build_manager: BuildManager::new(tx),
             pending_autocompile: found_file,
+            integration_status: None,
+            pending_launch: None,
+            launch_rx: None,
             diagnostics: Vec::new(),
+            diagnostic_filter: Severity::all().iter().copied().collect(),
             page_sizes: std::collections::HashMap::new(),
+            synctex: None,
+            synctex_rx,
+            synctex_tx,
+            build_source_dir: PathBuf::new(),
             pending_scroll_target: None,
             pending_cursor_scroll: None,
+            sync_highlight: None,
             settings,
+            session: Session::default(),
+            pending_session_restore: false,
+            pending_buffer_recovery: true,
+            pending_pdf_anchor: None,
+            last_session_save: 0.0,
             completion_suggestions: Vec::new(),
             show_completions: false,
             completion_popup_pos: egui::Pos2::ZERO,
@@ -926,15 +1199,35 @@ impl Default for TypesafeApp {
             pdf_multi_page_view: false,
             pdf_search_query: String::new(),
             show_pdf_search: false,
+            pdf_search_results: Vec::new(),
+            pdf_search_index: 0,
 
             last_bcf_hash: String::new(),
             last_bib_hash: String::new(),
 
+            swap_writer: SwapWriter::spawn(swap_store),
+            swapped: std::collections::HashMap::new(),
+            last_swap_time: 0.0,
+            recovery_queue,
+            recovery_diff: None,
+
             rename_dialog_open: false,
             rename_target_path: std::path::PathBuf::new(),
             rename_new_name: String::new(),
         };
 
+        app.load_project_config();
+        app.pending_session_restore = !restored_files.is_empty();
+        app.apply_layout(&session.layout);
+        if let Some(project) = session.last_project() {
+            if let Some(root) = project.root_file.as_ref().filter(|r| std::path::Path::new(r).exists()) {
+                app.set_root_file(root.clone());
+            }
+            app.pending_pdf_anchor = project.pdf.clone().map(|pdf| (pdf, project.pdf_page, project.pdf_offset));
+        }
+        app.session = session;
+        // A restored PDF tab only builds through a root document
+        app.pending_autocompile = found_file && (app.root_file.is_some() || Self::determine_file_type(&app.file_path) == CurrentFileType::Tex);
         if !app.file_path.is_empty() && !app.file_path.ends_with("untitled.tex") {
             app.update_outline();
         }
@@ -1018,80 +1311,985 @@ impl TypesafeApp {
         ctx.set_style(style);
     }
 
+    // Hands the PDF to the render worker; pages arrive through render_rx.
+    // Textures from the previous build of the same file stay on screen until
+    // their pages are known to have changed.
     fn load_pdf_preview(&mut self, ctx: &egui::Context, pdf_path: &PathBuf) {
+        let Some(pdfium) = self.pdfium.clone() else {
+            self.preview_status = "Failed to load PDF: PDFium not available. Please check installation.".to_string();
+            return;
+        };
+        if self.pdf_opened.as_ref() != Some(pdf_path) {
+            self.page_cache.clear();
+            self.page_count = 0;
+            self.current_page = 0;
+            self.pdf_anchors.clear();
+            // Back to where the last session left this PDF
+            if let Some((_, page, offset)) = self.pending_pdf_anchor.take().filter(|(pdf, _, _)| pdf == pdf_path) {
+                self.current_page = page;
+                self.pdf_anchors.insert(PdfViewer::Preview, (page, offset));
+            }
+        }
         self.pdf_path = Some(pdf_path.clone());
+        self.pdf_opened = Some(pdf_path.clone());
         self.preview_status = "Loading PDF...".to_string();
 
-        let result = if let Some(pdfium) = &self.pdfium {
-            pdfium
-                .load_pdf_from_file(pdf_path, None)
-                .map(|doc| doc.pages().len())
-                .map_err(|e| e.to_string())
+        let worker = self.pdf_renderer.get_or_insert_with(|| {
+            let ctx = ctx.clone();
+            RenderWorker::spawn(pdfium, self.render_tx.clone(), move || ctx.request_repaint())
+        });
+        worker.open(pdf_path.clone());
+        self.pdf_sent_jobs.clear();
+    }
+
+    fn handle_render_msg(&mut self, ctx: &egui::Context, msg: RenderMsg) {
+        let Some(worker) = &self.pdf_renderer else { return };
+        let generation = worker.generation();
+        match msg {
+            RenderMsg::Opened { generation: g, sizes } if g == generation => {
+                self.page_count = sizes.len();
+                self.current_page = self.current_page.min(self.page_count.saturating_sub(1));
+                self.page_sizes = sizes.into_iter().enumerate().collect();
+                self.page_cache.truncate(self.page_count);
+                // Page sizes may have changed; put each viewer back on the same spot
+                self.pdf_anchors.retain(|_, (page, _)| *page < self.page_count);
+                self.pdf_restore = self.pdf_anchors.keys().copied().collect();
+                self.preview_status = format!("PDF loaded: {} pages", self.page_count);
+                // Refresh search hits against the new document
+                if !self.pdf_search_query.is_empty() {
+                    self.run_pdf_search();
+                }
+            }
+            RenderMsg::Failed { generation: g, error } if g == generation => {
+                self.preview_status = format!("Failed to load PDF: {}", error);
+                // Whatever is shown belongs to no open document; the next load starts over
+                self.pdf_opened = None;
+            }
+            // Older answers are dropped while typing
+            RenderMsg::Found { generation: g, query, hits } if g == generation && query == self.pdf_search_query.trim() => {
+                self.pdf_search_results = hits;
+                self.pdf_search_index = 0;
+                if !self.pdf_search_results.is_empty() {
+                    self.jump_to_pdf_search_hit(0);
+                }
+            }
+            RenderMsg::Page { generation: g, job, hash, image } if g == generation => {
+                self.pdf_sent_jobs.retain(|j| *j != job);
+                self.page_cache.insert(generation, job, hash, image, |image| {
+                    ctx.load_texture(format!("pdf_page_{}", job.page), image, egui::TextureOptions::LINEAR)
+                });
+            }
+            _ => {}
+        }
+    }
+
+    // Asks the render worker for the pages drawn last frame plus a couple on
+    // either side. Only sent when there is work the worker was not given yet.
+    // A page shown by both viewers is rendered for the larger of the two.
+    fn request_pdf_pages(&mut self, ctx: &egui::Context) {
+        let visible = std::mem::take(&mut self.pdf_visible);
+        let Some(worker) = &self.pdf_renderer else { return };
+
+        // Drawn pages first, then their neighbours
+        let mut wanted: Vec<(usize, f32)> = visible.values().flat_map(|(pages, zoom)| pages.iter().map(|&page| (page, *zoom))).collect();
+        for (pages, zoom) in visible.values() {
+            let (Some(&first), Some(&last)) = (pages.iter().min(), pages.iter().max()) else { continue };
+            wanted.extend((first.saturating_sub(2)..(last + 3).min(self.page_count)).map(|page| (page, *zoom)));
+        }
+        let pixels_per_point = ctx.pixels_per_point();
+        let mut targets: Vec<(usize, u32)> = Vec::new();
+        for (page, zoom) in wanted {
+            let Some((width, _)) = self.page_sizes.get(&page) else { continue };
+            let width = render::target_width(*width, zoom, pixels_per_point);
+            match targets.iter_mut().find(|(p, _)| *p == page) {
+                Some(target) => target.1 = target.1.max(width),
+                None => targets.push((page, width)),
+            }
+        }
+        if targets.is_empty() {
+            return;
+        }
+
+        let jobs = self.page_cache.jobs(worker.generation(), &targets);
+        if jobs.iter().any(|job| !self.pdf_sent_jobs.contains(job)) {
+            worker.render(jobs.clone());
+            self.pdf_sent_jobs = jobs;
+        }
+    }
+
+    // Lays out one page at `zoom` and draws its texture, or a blank sheet
+    // until the render worker delivers it. Also tracks where `viewer` is
+    // scrolled to, so a rebuild can bring it back there.
+    fn show_pdf_page(&mut self, ui: &mut egui::Ui, viewer: PdfViewer, page_idx: usize, zoom: f32) -> egui::Response {
+        let (pw, ph) = self.page_sizes.get(&page_idx).copied().unwrap_or((595.0, 842.0));
+        let (rect, response) = ui.allocate_exact_size(Vec2::new(pw * zoom, ph * zoom), egui::Sense::click());
+
+        let anchor = self.pdf_anchors.get(&viewer).copied();
+        match anchor {
+            Some((page, rel_y)) if page == page_idx && self.pdf_restore.remove(&viewer) => {
+                let top = egui::Rect::from_min_size(egui::pos2(rect.left(), rect.top() + rect.height() * rel_y), egui::vec2(rect.width(), 1.0));
+                ui.scroll_to_rect(top, Some(Align::Min));
+            }
+            _ if !self.pdf_restore.contains(&viewer) => {
+                let view_top = ui.clip_rect().top();
+                if rect.top() <= view_top && view_top < rect.bottom() {
+                    self.pdf_anchors.insert(viewer, (page_idx, (view_top - rect.top()) / rect.height()));
+                } else if page_idx == 0 && view_top < rect.top() {
+                    self.pdf_anchors.insert(viewer, (0, 0.0));
+                }
+            }
+            _ => {}
+        }
+
+        if ui.is_rect_visible(rect) {
+            let (pages, drawn_zoom) = self.pdf_visible.entry(viewer).or_insert_with(|| (Vec::new(), zoom));
+            pages.push(page_idx);
+            *drawn_zoom = zoom;
+            match self.page_cache.get(page_idx) {
+                Some(tex) => {
+                    egui::Image::new((tex.id(), rect.size())).paint_at(ui, rect);
+                }
+                None => {
+                    ui.painter().rect_filled(rect, 0.0, Color32::WHITE);
+                }
+            }
+        }
+        response
+    }
+
+    // Asks the render worker to search the open PDF; hits arrive as RenderMsg::Found
+    fn run_pdf_search(&mut self) {
+        let query = self.pdf_search_query.trim();
+        if query.is_empty() {
+            self.pdf_search_results.clear();
+            self.pdf_search_index = 0;
+            return;
+        }
+        if let Some(worker) = &self.pdf_renderer {
+            worker.search(query.to_string());
+        }
+    }
+
+    fn jump_to_pdf_search_hit(&mut self, index: usize) {
+        let Some(hit) = self.pdf_search_results.get(index) else { return };
+        self.pdf_search_index = index;
+
+        let mut rel_y = 0.5;
+        if let (Some(rect), Some((_, h))) = (hit.rects.first(), self.page_sizes.get(&hit.page)) {
+            if *h > 0.0 { rel_y = rect.center().y / *h; }
+        }
+        self.current_page = hit.page;
+        self.pending_scroll_target = Some((hit.page, rel_y));
+    }
+
+    fn step_pdf_search(&mut self, forward: bool) {
+        let count = self.pdf_search_results.len();
+        if count == 0 { return; }
+        let next = if forward {
+            (self.pdf_search_index + 1) % count
+        } else if self.pdf_search_index == 0 {
+            count - 1
         } else {
-            Err("PDFium not available. Please check installation.".to_string())
+            self.pdf_search_index - 1
         };
+        self.jump_to_pdf_search_hit(next);
+    }
+
+    fn show_pdf_search_bar(&mut self, ui: &mut egui::Ui, theme: &ThemeColors) {
+        ui.horizontal(|ui| {
+            ui.label("Search:");
+            let resp = ui.text_edit_singleline(&mut self.pdf_search_query);
+            if resp.changed() {
+                self.run_pdf_search();
+            }
+            if resp.lost_focus() && ui.input(|i| i.key_pressed(egui::Key::Enter)) {
+                self.step_pdf_search(!ui.input(|i| i.modifiers.shift));
+                resp.request_focus();
+            }
 
-        match result {
-            Ok(pages_len) => {
-                let pages: usize = pages_len.into();
-                self.page_count = pages;
-                self.current_page = 0;
-                self.pdf_textures.clear();
-                self.preview_status = format!("PDF loaded: {} pages", pages);
-                // Pre-render first page(s)
-                self.render_page(ctx, 0);
+            let match_count = self.pdf_search_results.len();
+            ui.label(
+                egui::RichText::new(if match_count > 0 {
+                    format!("{}/{}", self.pdf_search_index + 1, match_count)
+                } else {
+                    "0/0".to_string()
+                })
+                .color(theme.text_secondary),
+            );
+            if ui.button("◀").on_hover_text("Previous Match (Shift+Enter)").clicked() {
+                self.step_pdf_search(false);
             }
-            Err(e) => {
-                self.preview_status = format!("Failed to load PDF: {}", e);
+            if ui.button("▶").on_hover_text("Next Match (Enter)").clicked() {
+                self.step_pdf_search(true);
+            }
+            if ui.button("✖").clicked() {
+                self.show_pdf_search = false;
+                self.pdf_search_query.clear();
+                self.pdf_search_results.clear();
+            }
+        });
+
+        if !self.pdf_search_results.is_empty() {
+            let mut jump = None;
+            egui::ScrollArea::vertical()
+                .id_source("pdf_search_results")
+                .max_height(120.0)
+                .show(ui, |ui| {
+                    for (i, hit) in self.pdf_search_results.iter().enumerate() {
+                        let text = egui::RichText::new(format!("p. {}  {}", hit.page + 1, hit.snippet)).small();
+                        let label = ui.add(
+                            egui::SelectableLabel::new(i == self.pdf_search_index, text)
+                        );
+                        if label.clicked() {
+                            jump = Some(i);
+                        }
+                    }
+                });
+            if let Some(i) = jump {
+                self.jump_to_pdf_search_hit(i);
             }
         }
     }
 
-    fn render_page(&mut self, ctx: &egui::Context, page_idx: usize) {
-        if self.pdf_textures.contains_key(&page_idx) { return; }
+    fn paint_pdf_search_highlights(&self, ui: &egui::Ui, page_idx: usize, image_rect: egui::Rect, theme: &ThemeColors) {
+        if !self.show_pdf_search || self.pdf_search_results.is_empty() { return; }
+        let Some((pw, ph)) = self.page_sizes.get(&page_idx).copied() else { return };
+        if pw <= 0.0 || ph <= 0.0 { return; }
+
+        let scale = egui::vec2(image_rect.width() / pw, image_rect.height() / ph);
+        let painter = ui.painter_at(image_rect);
+
+        for (i, hit) in self.pdf_search_results.iter().enumerate() {
+            if hit.page != page_idx { continue; }
+            let is_current = i == self.pdf_search_index;
+            let fill = if is_current { theme.accent.linear_multiply(0.35) } else { theme.warning.linear_multiply(0.3) };
+
+            for r in &hit.rects {
+                let screen = egui::Rect::from_min_max(
+                    image_rect.min + r.min.to_vec2() * scale,
+                    image_rect.min + r.max.to_vec2() * scale,
+                ).expand(1.0);
+                painter.rect_filled(screen, 2.0, fill);
+                if is_current {
+                    painter.rect_stroke(screen, 2.0, Stroke::new(1.5, theme.accent));
+                }
+            }
+        }
+    }
 
-        let Some(pdfium) = &self.pdfium else { return };
-        let Some(path) = &self.pdf_path else { return };
+    fn paint_sync_highlight(&self, ui: &egui::Ui, page_idx: usize, image_rect: egui::Rect, theme: &ThemeColors) {
+        const FADE_SECS: f64 = 1.5;
+        let Some(highlight) = &self.sync_highlight else { return };
+        let elapsed = ui.input(|i| i.time) - highlight.started;
+        if elapsed >= FADE_SECS { return; }
+        let Some((pw, ph)) = self.page_sizes.get(&page_idx).copied() else { return };
+        if pw <= 0.0 || ph <= 0.0 { return; }
+
+        let scale = egui::vec2(image_rect.width() / pw, image_rect.height() / ph);
+        let painter = ui.painter_at(image_rect);
+        let fade = (1.0 - elapsed / FADE_SECS) as f32;
+
+        for (page, r) in &highlight.area {
+            if *page != page_idx { continue; }
+            let screen = egui::Rect::from_min_max(
+                image_rect.min + r.min.to_vec2() * scale,
+                image_rect.min + r.max.to_vec2() * scale,
+            ).expand(2.0);
+            painter.rect_filled(screen, 2.0, theme.accent.linear_multiply(0.3 * fade));
+            painter.rect_stroke(screen, 2.0, Stroke::new(1.5, theme.accent.linear_multiply(fade)));
+        }
+        ui.ctx().request_repaint();
+    }
 
-        match render_pdf_page_to_texture(
-            ctx,
-            pdfium,
-            path,
-            page_idx,
-            self.zoom,
-            &mut self.preview_size,
-            &mut self.page_sizes,
-        ) {
-            Ok(texture) => {
-                self.pdf_textures.insert(page_idx, texture);
+    fn determine_file_type(path: &str) -> CurrentFileType {
+        let path_lower = path.to_lowercase();
+        if path_lower.ends_with(".pdf") {
+            CurrentFileType::Pdf
+        } else if path_lower.ends_with(".md") {
+            CurrentFileType::Markdown
+        } else if path_lower.ends_with(".tex") || path_lower.ends_with(".bib") || path_lower.ends_with(".cls") || path_lower.ends_with(".sty") {
+            CurrentFileType::Tex
+        } else {
+            CurrentFileType::Other
+        }
+    }
+
+    // Character offset and length of `line` (1-based) in the editor
+    fn line_char_range(&self, line: usize) -> (usize, usize) {
+        let line = line.saturating_sub(1);
+        (self.editor_content.line_to_char(line), self.editor_content.line(line).chars().count())
+    }
+
+    // Opens `file` if it is not the current one and places the cursor at `line` (1-based).
+    // Returns false if the file does not exist.
+    fn jump_to_location(&mut self, ctx: &egui::Context, file: &str, line: usize, column: Option<usize>) -> bool {
+        if !file.is_empty() && std::path::Path::new(file) != std::path::Path::new(&self.file_path) {
+            if !std::path::Path::new(file).exists() {
+                return false;
             }
-            Err(e) => {
-                self.preview_status = format!("Render error on page {}: {}", page_idx, e);
+            self.open_file(ctx, file);
+        }
+
+        let (line_start, line_len) = self.line_char_range(line);
+        let char_idx = line_start + column.unwrap_or(0).min(line_len);
+
+        // No state yet on the first frame
+        let mut state = egui::TextEdit::load_state(ctx, egui::Id::new("main_editor")).unwrap_or_default();
+        state.cursor.set_char_range(Some(egui::text::CCursorRange::one(egui::text::CCursor::new(char_idx))));
+        state.store(ctx, egui::Id::new("main_editor"));
+        ctx.memory_mut(|m| m.request_focus(egui::Id::new("main_editor")));
+        self.pending_cursor_scroll = Some(char_idx);
+        true
+    }
+
+    // Applies command-line arguments, ours or forwarded: `--root` sets the root
+    // file, a folder becomes the project folder and a file opens in a tab at the
+    // given line
+    fn apply_launch_args(&mut self, ctx: &egui::Context, launch: LaunchArgs) {
+        let project_dir = match (&launch.root, &launch.path) {
+            (Some(root), _) => root.parent(),
+            (None, Some(path)) if path.is_dir() => Some(path.as_path()),
+            (None, Some(path)) => path.parent(),
+            (None, None) => None,
+        };
+        if let Some(dir) = project_dir {
+            self.open_project_dir(dir.to_path_buf());
+        }
+        // An explicit --root wins over the project file's
+        if let Some(root) = &launch.root {
+            self.set_root_file(root.to_string_lossy().to_string());
+        }
+
+        let Some(path) = launch.path else { return };
+        if path.is_dir() {
+            return;
+        }
+
+        let file = path.to_string_lossy().to_string();
+        let column = launch.column.map(|c| c.saturating_sub(1));
+        if std::path::Path::new(&file) != std::path::Path::new(&self.file_path) {
+            // Opening the file builds it; skip the startup build of the last file
+            self.pending_autocompile = false;
+        }
+        if !self.jump_to_location(ctx, &file, launch.line.unwrap_or(1), column) {
+            self.compilation_log = format!("Cannot open {}: file not found\n", file);
+            self.show_log = true;
+            return;
+        }
+        if let (true, Some(line)) = (launch.forward_search, launch.line) {
+            self.sync_forward_search(ctx, line);
+        }
+    }
+
+    // Inverse search: opens the source file behind `pos` (PDF points) on `page`
+    // and selects the line it came from
+    fn sync_inverse_search(&mut self, ctx: &egui::Context, page: usize, pos: egui::Pos2) -> bool {
+        let Some((file, line)) = self.synctex.as_ref().and_then(|index| index.inverse(page, pos)) else {
+            return false;
+        };
+        if line == 0 || !self.jump_to_location(ctx, &file.to_string_lossy(), line, None) {
+            return false;
+        }
+
+        let (line_start, line_len) = self.line_char_range(line);
+        if let Some(mut state) = egui::TextEdit::load_state(ctx, egui::Id::new("main_editor")) {
+            state.cursor.set_char_range(Some(egui::text::CCursorRange::two(
+                egui::text::CCursor::new(line_start),
+                egui::text::CCursor::new(line_start + line_len)
+            )));
+            state.store(ctx, egui::Id::new("main_editor"));
+        }
+        true
+    }
+
+    // Opens `dir` as the project, from Open Folder, Open File, the command line
+    // or Recent Projects. The project being left goes into the session history.
+    fn open_project_dir(&mut self, dir: PathBuf) {
+        if dir != self.project_dir {
+            let previous = self.project_session();
+            self.session.remember(previous);
+        }
+        self.set_project_dir(dir);
+    }
+
+    // Sets the root document. Without a project file in the project folder,
+    // one beside the root applies, though it cannot override this choice.
+    fn set_root_file(&mut self, root: String) {
+        self.root_file = Some(root.clone());
+        if self.project.is_none() {
+            self.load_project_config();
+            self.root_file = Some(root);
+        }
+    }
+
+    // Makes `dir` the project folder without touching the session history
+    fn set_project_dir(&mut self, dir: PathBuf) {
+        self.project_dir = dir.clone();
+        self.current_dir = dir;
+        self.load_project_config();
+    }
+
+    // The current project's session entry, from the tabs as last stashed
+    fn project_session(&self) -> ProjectSession {
+        let absolute = |path: &std::path::Path| std::path::absolute(path).unwrap_or_else(|_| path.to_path_buf());
+        let mut files = Vec::new();
+        let mut active = 0;
+        for (i, buffer) in self.buffers.iter().enumerate() {
+            if buffer.path.is_empty() || buffer.path.ends_with("untitled.tex") {
+                continue;
+            }
+            if i == self.active_buffer {
+                active = files.len();
+            }
+            let cursor = buffer.edit_state.as_ref().and_then(|s| s.cursor.char_range()).map_or(0, |r| r.primary.index);
+            files.push(OpenFile {
+                path: absolute(std::path::Path::new(&buffer.path)).to_string_lossy().to_string(),
+                cursor,
+                scroll_offset: buffer.scroll_offset,
+                folds: buffer.folds.clone(),
+            });
+        }
+        let (pdf_page, pdf_offset) = self.pdf_anchors.get(&PdfViewer::Preview).copied().unwrap_or((self.current_page, 0.0));
+        ProjectSession {
+            dir: absolute(&self.project_dir),
+            root_file: self.root_file.as_deref().map(|r| absolute(std::path::Path::new(r)).to_string_lossy().to_string()),
+            files,
+            active,
+            pdf: self.pdf_path.as_deref().map(absolute),
+            pdf_page,
+            pdf_offset,
+        }
+    }
+
+    fn layout(&self) -> session::Layout {
+        session::Layout {
+            show_file_panel: self.show_file_panel,
+            show_preview_panel: self.show_preview_panel,
+            show_log: self.show_log,
+            show_pdf_popup: self.show_pdf_popup,
+            zoom: self.zoom,
+            fit_mode: self.fit_mode,
+            multi_page_view: self.pdf_multi_page_view,
+            popout_zoom: self.popout_zoom,
+            popout_fit_mode: self.popout_fit_mode,
+            popout_multi_page_view: self.popout_multi_page_view,
+        }
+    }
+
+    fn apply_layout(&mut self, layout: &session::Layout) {
+        self.show_file_panel = layout.show_file_panel;
+        self.show_preview_panel = layout.show_preview_panel;
+        self.show_log = layout.show_log;
+        self.show_pdf_popup = layout.show_pdf_popup;
+        self.zoom = layout.zoom;
+        self.fit_mode = layout.fit_mode;
+        self.pdf_multi_page_view = layout.multi_page_view;
+        self.popout_zoom = layout.popout_zoom;
+        self.popout_fit_mode = layout.popout_fit_mode;
+        self.popout_multi_page_view = layout.popout_multi_page_view;
+    }
+
+    // Writes the open project and the layout to the session file
+    fn save_session(&mut self, ctx: &egui::Context) {
+        self.sync_active_buffer(ctx);
+        self.session.layout = self.layout();
+        let project = self.project_session();
+        self.session.remember(project);
+        self.session.save();
+    }
+
+    // Writes swaps for buffers whose unsaved content changed since the last
+    // pass, and drops the swaps of buffers that were saved or closed
+    fn journal_unsaved_buffers(&mut self) {
+        // (swap id, path, content)
+        let mut unsaved: Vec<(&String, &String, &str)> = Vec::new();
+        for (i, buffer) in self.buffers.iter().enumerate() {
+            if i == self.active_buffer {
+                if self.is_dirty {
+                    unsaved.push((&buffer.swap_id, &self.file_path, self.editor_content.as_str()));
+                }
+            } else if buffer.is_dirty {
+                unsaved.push((&buffer.swap_id, &buffer.path, buffer.content.as_str()));
+            }
+        }
+        unsaved.retain(|(_, path, _)| !path.is_empty());
+
+        for (id, path, content) in &unsaved {
+            let hash = content_hash(content);
+            if self.swapped.get(*id) != Some(&hash) {
+                self.swap_writer.write(id, path, content);
+                self.swapped.insert((*id).clone(), hash);
+            }
+        }
+        let stale: Vec<String> = self.swapped.keys().filter(|k| !unsaved.iter().any(|(id, _, _)| id == k)).cloned().collect();
+        for id in stale {
+            self.swap_writer.remove(&id);
+            self.swapped.remove(&id);
+        }
+    }
+
+    // Opens the swap's file in a tab with the recovered text as unsaved changes
+    fn recover_swap(&mut self, ctx: &egui::Context, swap: Swap) {
+        if std::path::Path::new(&swap.path).exists() {
+            self.open_file(ctx, &swap.path);
+        } else {
+            self.autosave_active_buffer();
+            self.stash_active_buffer(ctx);
+            self.buffers.push(Buffer::new(swap.path.clone(), String::new()));
+            self.restore_buffer(ctx, self.buffers.len() - 1);
+        }
+        // Undoing the recovery gets back the text from the disk
+        self.editor_content.replace_all(&swap.content);
+        self.is_dirty = true;
+        // The next journaling pass rewrites the swap from the buffer
+        self.buffers[self.active_buffer].swap_id = swap.id;
+        self.checks_dirty = true;
+        self.update_outline();
+    }
+
+    // Asks about the swaps of files already open in tabs, before they are edited
+    fn recover_open_buffers(&mut self) {
+        for index in 0..self.buffers.len() {
+            let path = std::path::Path::new(&self.buffers[index].path);
+            let Some(i) = self.recovery_queue.iter().position(|s| std::path::Path::new(&s.path) == path) else { continue };
+            let swap = self.recovery_queue.remove(i);
+            self.recovery_diff = None;
+            match ask_to_recover(&swap) {
+                RecoveryChoice::Recover => {
+                    // Undoing the recovery gets back the text from the disk
+                    if index == self.active_buffer {
+                        self.editor_content.replace_all(&swap.content);
+                        self.is_dirty = true;
+                        self.checks_dirty = true;
+                        self.update_outline();
+                    } else {
+                        self.buffers[index].content.replace_all(&swap.content);
+                        self.buffers[index].is_dirty = true;
+                    }
+                    self.buffers[index].swap_id = swap.id;
+                }
+                RecoveryChoice::Discard => self.swap_writer.remove(&swap.id),
+                RecoveryChoice::Later => {}
+            }
+        }
+    }
+
+    fn show_recovery_dialog(&mut self, ctx: &egui::Context) {
+        let Some(swap) = self.recovery_queue.first() else { return };
+        let diff = self.recovery_diff.get_or_insert_with(|| {
+            let disk = std::fs::read_to_string(&swap.path).unwrap_or_default();
+            recovery::diff_lines(&disk, &swap.content).into_iter().map(|(kind, line)| (kind, line.to_string())).collect()
+        });
+        let now = std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH).map_or(0, |d| d.as_secs());
+        let minutes = now.saturating_sub(swap.saved_at) / 60;
+        let theme = ThemeColors::from_preset(self.settings.theme);
+
+        let mut choice = None;
+        egui::Window::new("Recover Unsaved Changes")
+            .collapsible(false)
+            .default_size(egui::vec2(560.0, 420.0))
+            .anchor(egui::Align2::CENTER_CENTER, egui::vec2(0.0, 0.0))
+            .show(ctx, |ui| {
+                ui.label(format!("Typesafe closed with unsaved changes to {}.", swap.path));
+                ui.label(egui::RichText::new(format!("Last journaled {} minute(s) ago. Lines marked + are only in the unsaved copy.", minutes)).small());
+                ui.add_space(8.0);
+                egui::ScrollArea::both().max_height(300.0).auto_shrink([false, true]).show(ui, |ui| {
+                    for (kind, line) in diff.iter() {
+                        let (prefix, color) = match kind {
+                            DiffKind::Same => ("  ", theme.text_secondary),
+                            DiffKind::Removed => ("- ", theme.error),
+                            DiffKind::Added => ("+ ", theme.success),
+                        };
+                        ui.label(egui::RichText::new(format!("{}{}", prefix, line)).monospace().color(color));
+                    }
+                });
+                ui.add_space(8.0);
+                ui.horizontal(|ui| {
+                    if ui.button("Recover").clicked() {
+                        choice = Some(RecoveryChoice::Recover);
+                    }
+                    if ui.button("Discard").clicked() {
+                        choice = Some(RecoveryChoice::Discard);
+                    }
+                    if ui.button("Decide Later").on_hover_text("Keep the swap until the next start").clicked() {
+                        choice = Some(RecoveryChoice::Later);
+                    }
+                });
+            });
+
+        let Some(choice) = choice else { return };
+        let swap = self.recovery_queue.remove(0);
+        self.recovery_diff = None;
+        match choice {
+            RecoveryChoice::Recover => self.recover_swap(ctx, swap),
+            RecoveryChoice::Discard => self.swap_writer.remove(&swap.id),
+            RecoveryChoice::Later => {}
+        }
+    }
+
+    // Switches to a project from the session history, replacing the current
+    // project's tabs with its own
+    fn open_recent_project(&mut self, ctx: &egui::Context, dir: PathBuf) {
+        self.save_session(ctx);
+        let Some(project) = self.session.project(&dir).cloned() else { return };
+
+        // Cancelling a save prompt keeps the current project open
+        for index in (0..self.buffers.len()).rev() {
+            if !self.close_buffer(ctx, index) {
+                return;
+            }
+        }
+        // Already remembered by save_session, before its tabs were closed
+        self.set_project_dir(dir);
+        if let Some(root) = project.root_file.as_ref().filter(|r| std::path::Path::new(r).exists()) {
+            self.root_file = Some(root.clone());
+        }
+
+        let (files, active) = project.existing_files();
+        if !files.is_empty() {
+            self.buffers = files.iter().map(Buffer::restore).collect();
+            self.restore_buffer(ctx, active);
+            if self.current_file_type == CurrentFileType::Tex {
+                self.compile(ctx);
+            }
+        }
+        self.pending_pdf_anchor = project.pdf.map(|pdf| (pdf, project.pdf_page, project.pdf_offset));
+    }
+
+    // Loads the project file of the project folder, else of the root document's
+    // folder, if it is not the one already loaded. A newly loaded file sets the
+    // root document.
+    fn load_project_config(&mut self) {
+        let root_dir = self.root_file.as_deref().and_then(|r| std::path::Path::new(r).parent()).map(|d| d.to_path_buf());
+        let found = ProjectConfig::find(&self.project_dir).or_else(|| root_dir.as_deref().and_then(ProjectConfig::find));
+        if found.as_deref() == self.project.as_ref().map(|p| p.path.as_path()) {
+            return;
+        }
+
+        if let Some(old) = self.project.take() {
+            // Forget a root that only came from the old file
+            if old.root.map(|r| r.to_string_lossy().to_string()) == self.root_file {
+                self.root_file = None;
+            }
+        }
+        if let Some(path) = found {
+            match ProjectConfig::load(&path) {
+                Ok(config) => {
+                    if let Some(root) = config.root.as_ref().filter(|r| r.exists()) {
+                        self.root_file = Some(root.to_string_lossy().to_string());
+                    }
+                    self.project = Some(config);
+                }
+                Err(e) => {
+                    self.compilation_log = format!("{}\n", e);
+                    self.show_log = true;
+                }
+            }
+        }
+        self.refresh_dictionary();
+    }
+
+    // Asks once per project file before its hooks run, since they are arbitrary
+    // shell commands. A changed file is asked about again.
+    fn project_hooks_allowed(&mut self) -> bool {
+        let Some(project) = self.project.as_ref().filter(|p| p.has_hooks()) else { return false };
+        if self.settings.hooks_trusted(project) {
+            return true;
+        }
+        if self.declined_hooks.contains(&project.path) {
+            return false;
+        }
+        let mut commands = String::new();
+        for (stage, list) in [("Before build", &project.hooks.before_build), ("After build", &project.hooks.after_build)] {
+            for command in list.iter() {
+                commands.push_str(&format!("\n{}: {}", stage, command));
+            }
+        }
+        let trusted = rfd::MessageDialog::new()
+            .set_title("Run Build Hooks?")
+            .set_description(format!("{} runs these commands around every build:{}\n\nOnly allow this for projects you trust.", project.path.display(), commands))
+            .set_buttons(rfd::MessageButtons::YesNo)
+            .show()
+            == rfd::MessageDialogResult::Yes;
+        if trusted {
+            self.settings.trusted_hooks.insert(project.path.to_string_lossy().to_string(), project.hash.clone());
+            self.settings.save();
+        } else {
+            self.declined_hooks.insert(project.path.clone());
+        }
+        trusted
+    }
+
+    // `% !TEX spellcheck`, else the project file's language, else the global one
+    fn spelling_language(&self) -> String {
+        self.document_magic()
+            .spellcheck
+            .or_else(|| self.project.as_ref().and_then(|p| p.spelling_language.clone()))
+            .unwrap_or_else(|| self.settings.spelling_language.clone())
+    }
+
+    // Build directory for `root`: the project file's, else the global setting
+    fn output_dir_for(&self, root: &std::path::Path) -> Option<PathBuf> {
+        self.project.as_ref().and_then(|p| p.output_dir.clone()).or_else(|| self.settings.output_dir_for(root))
+    }
+
+    // Deletes the current document's auxiliary files, reporting in the log
+    fn clean_aux_files(&mut self) {
+        let root = PathBuf::from(self.root_document().unwrap_or_else(|| self.file_path.clone()));
+        let Some(source_dir) = root.parent().filter(|_| root.extension().is_some_and(|e| e == "tex")) else {
+            self.compilation_log = "Open a LaTeX document to clean its auxiliary files.\n".to_string();
+            self.show_log = true;
+            return;
+        };
+        let out_dir = self.output_dir_for(&root);
+        self.compilation_log = match compiler::clean_aux_files(source_dir, out_dir.as_deref()) {
+            Ok(count) => format!("Removed {} auxiliary file(s) from {}\n", count, out_dir.as_deref().unwrap_or(source_dir).display()),
+            Err(e) => format!("Cleaning failed: {}\n", e),
+        };
+        self.show_log = true;
+    }
+
+    // Magic comments of a file, from its tab if it is open
+    fn magic_comments(&self, path: &str) -> MagicComments {
+        if path == self.file_path {
+            return MagicComments::parse(&self.editor_content);
+        }
+        if let Some(buffer) = self.buffers.iter().find(|b| b.path == path) {
+            return MagicComments::parse(&buffer.content);
+        }
+        std::fs::read_to_string(path).map(|text| MagicComments::parse(&text)).unwrap_or_default()
+    }
+
+    // `% !TEX root` of the active file, if it names an existing file
+    fn magic_root(&self) -> Option<String> {
+        if self.current_file_type != CurrentFileType::Tex {
+            return None;
+        }
+        let root = MagicComments::parse(&self.editor_content).root_for(std::path::Path::new(&self.file_path))?;
+        root.exists().then(|| root.to_string_lossy().to_string())
+    }
+
+    // Document to build and outline: the active file's `% !TEX root`, else the
+    // root from the file panel, command line or project file
+    fn root_document(&self) -> Option<String> {
+        self.magic_root().or_else(|| self.root_file.clone())
+    }
+
+    // Magic comments of the active file, with the root document's filling the gaps
+    fn document_magic(&self) -> MagicComments {
+        let mut magic = if self.current_file_type == CurrentFileType::Tex {
+            MagicComments::parse(&self.editor_content)
+        } else {
+            MagicComments::default()
+        };
+        if let Some(root) = self.root_document().filter(|r| *r != self.file_path) {
+            let from_root = self.magic_comments(&root);
+            magic.program = magic.program.or(from_root.program);
+            magic.spellcheck = magic.spellcheck.or(from_root.spellcheck);
+            magic.bib_program = magic.bib_program.or(from_root.bib_program);
+        }
+        magic
+    }
+
+    // Reloads the spelling dictionary for the current language plus any
+    // word lists the project file adds
+    fn refresh_dictionary(&mut self) {
+        let language = self.spelling_language();
+        let mut dictionary = load_dictionary(&language);
+        if !dictionary.is_empty() {
+            for path in self.project.iter().flat_map(|p| &p.dictionaries) {
+                if let Ok(content) = std::fs::read_to_string(path) {
+                    dictionary.extend(content.lines().map(|l| l.trim().to_lowercase()).filter(|l| !l.is_empty()));
+                }
+            }
+        }
+        self.dictionary = dictionary;
+        self.dictionary_language = language;
+        self.checks_dirty = true;
+    }
+
+    // Writes the active buffer to disk before switching away, if autosave is on
+    fn autosave_active_buffer(&mut self) {
+        if self.settings.autosave_on_change && self.is_dirty && !self.file_path.is_empty() && !self.file_path.ends_with("untitled.tex") {
+            match std::fs::write(&self.file_path, self.editor_content.as_str()) {
+                Ok(_) => self.is_dirty = false,
+                Err(e) => self.compilation_log = format!("Error autosaving: {}\n", e),
+            }
+        }
+    }
+
+    // Copies the editor state, all but the text and checks, into the active
+    // buffer's entry
+    fn sync_active_buffer(&mut self, ctx: &egui::Context) {
+        let edit_state = egui::TextEdit::load_state(ctx, egui::Id::new("main_editor"));
+        if let Some(buffer) = self.buffers.get_mut(self.active_buffer) {
+            buffer.path = self.file_path.clone();
+            buffer.is_dirty = self.is_dirty;
+            buffer.file_type = self.current_file_type;
+            buffer.edit_state = edit_state;
+            buffer.scroll_offset = self.editor_scroll_offset;
+            buffer.folds = self.folds.folded_lines();
+        }
+    }
+
+    // Moves the editor state into the active buffer's entry, leaving the
+    // editor empty for restore_buffer to fill
+    fn stash_active_buffer(&mut self, ctx: &egui::Context) {
+        self.sync_active_buffer(ctx);
+        if let Some(buffer) = self.buffers.get_mut(self.active_buffer) {
+            buffer.content = std::mem::take(&mut self.editor_content);
+            buffer.syntax_errors = std::mem::take(&mut self.cached_syntax_errors);
+            buffer.spell_errors = std::mem::take(&mut self.cached_spell_errors);
+        }
+    }
+
+    // Shows buffers[index] in the editor, without stashing the current one.
+    // The buffer's text moves into the editor until it is stashed again.
+    fn restore_buffer(&mut self, ctx: &egui::Context, index: usize) {
+        let Some(buffer) = self.buffers.get_mut(index) else { return };
+        self.active_buffer = index;
+        self.file_path = buffer.path.clone();
+        self.editor_content = std::mem::take(&mut buffer.content);
+        self.is_dirty = buffer.is_dirty;
+        self.current_file_type = buffer.file_type;
+        self.cached_syntax_errors = std::mem::take(&mut buffer.syntax_errors);
+        self.cached_spell_errors = std::mem::take(&mut buffer.spell_errors);
+        self.folds = Folds::new(buffer.folds.clone());
+        self.checks_dirty = false;
+        self.pending_editor_scroll = Some(buffer.scroll_offset);
+        egui::TextEdit::store_state(ctx, egui::Id::new("main_editor"), buffer.edit_state.clone().unwrap_or_default());
+
+        self.show_completions = false;
+        self.search_matches.clear();
+        if self.current_file_type == CurrentFileType::Pdf {
+            self.pdf_path = Some(PathBuf::from(&self.file_path));
+        }
+        self.settings.last_file = Some(self.file_path.clone());
+        self.settings.save();
+        if self.current_file_type == CurrentFileType::Tex {
+            self.update_outline();
+        }
+    }
+
+    fn switch_to_buffer(&mut self, ctx: &egui::Context, index: usize) {
+        if index == self.active_buffer || index >= self.buffers.len() {
+            return;
+        }
+        self.autosave_active_buffer();
+        self.stash_active_buffer(ctx);
+        self.restore_buffer(ctx, index);
+    }
+
+    // Opens `path` in a new tab, or selects its tab if it is already open
+    fn open_file(&mut self, ctx: &egui::Context, path: &str) {
+        if let Some(index) = self.buffers.iter().position(|b| std::path::Path::new(&b.path) == std::path::Path::new(path)) {
+            self.switch_to_buffer(ctx, index);
+            return;
+        }
+        self.autosave_active_buffer();
+        self.stash_active_buffer(ctx);
+
+        self.buffers.push(Buffer::new(path.to_string(), String::new()));
+        self.active_buffer = self.buffers.len() - 1;
+        self.file_path = path.to_string();
+        self.cached_syntax_errors.clear();
+        self.cached_spell_errors.clear();
+        self.folds = Folds::default();
+        self.checks_dirty = true;
+        self.pending_editor_scroll = Some(0.0);
+        egui::TextEdit::store_state(ctx, egui::Id::new("main_editor"), Default::default());
+        self.load_file(ctx, path);
+
+        self.settings.last_file = Some(self.file_path.clone());
+        self.settings.save();
+        if self.current_file_type == CurrentFileType::Tex {
+            self.update_outline();
+        }
+    }
+
+    fn new_buffer(&mut self, ctx: &egui::Context) {
+        self.autosave_active_buffer();
+        self.stash_active_buffer(ctx);
+        let path = self.current_dir.join("untitled.tex").to_string_lossy().to_string();
+        self.buffers.push(Buffer::new(path, NEW_DOCUMENT.to_string()));
+        self.restore_buffer(ctx, self.buffers.len() - 1);
+        self.settings.last_file = None;
+        self.settings.save();
+    }
+
+    // Closes a tab, asking about unsaved changes first. Returns false if the user cancelled.
+    fn close_buffer(&mut self, ctx: &egui::Context, index: usize) -> bool {
+        if index >= self.buffers.len() {
+            return false;
+        }
+        if index == self.active_buffer {
+            self.sync_active_buffer(ctx);
+        }
+
+        let buffer = &self.buffers[index];
+        // The active buffer's text is with the editor
+        let content = if index == self.active_buffer { &self.editor_content } else { &buffer.content };
+        if buffer.is_dirty {
+            let untitled = buffer.path.is_empty() || buffer.path.ends_with("untitled.tex");
+            let save = if self.settings.autosave_on_change && !untitled {
+                true
+            } else {
+                let name = std::path::Path::new(&buffer.path).file_name().unwrap_or_default().to_string_lossy().to_string();
+                match rfd::MessageDialog::new()
+                    .set_title("Unsaved Changes")
+                    .set_description(format!("Do you want to save changes to {}?", name))
+                    .set_buttons(rfd::MessageButtons::YesNoCancel)
+                    .show()
+                {
+                    rfd::MessageDialogResult::Yes => true,
+                    rfd::MessageDialogResult::No => false,
+                    _ => return false,
+                }
+            };
+            if save {
+                if let Err(e) = std::fs::write(&buffer.path, content.as_str()) {
+                    self.compilation_log = format!("Error saving file: {}\n", e);
+                    self.show_log = true;
+                    return false;
+                }
             }
         }
-    }
 
-    fn determine_file_type(path: &str) -> CurrentFileType {
-        let path_lower = path.to_lowercase();
-        if path_lower.ends_with(".pdf") {
-            CurrentFileType::Pdf
-        } else if path_lower.ends_with(".md") {
-            CurrentFileType::Markdown
-        } else if path_lower.ends_with(".tex") || path_lower.ends_with(".bib") || path_lower.ends_with(".cls") || path_lower.ends_with(".sty") {
-            CurrentFileType::Tex
-        } else {
-            CurrentFileType::Other
+        self.buffers.remove(index);
+        if self.buffers.is_empty() {
+            self.active_buffer = 0;
+            let path = self.current_dir.join("untitled.tex").to_string_lossy().to_string();
+            self.buffers.push(Buffer::new(path, NEW_DOCUMENT.to_string()));
+            self.restore_buffer(ctx, 0);
+        } else if index == self.active_buffer {
+            self.restore_buffer(ctx, index.min(self.buffers.len() - 1));
+        } else if index < self.active_buffer {
+            self.active_buffer -= 1;
         }
+        true
     }
 
     fn load_file(&mut self, ctx: &egui::Context, path: &str) {
         self.current_file_type = Self::determine_file_type(path);
+        // A swap left by a crash is settled before the disk copy is relied on
+        let mut recovered = None;
+        if let Some(i) = self.recovery_queue.iter().position(|s| std::path::Path::new(&s.path) == std::path::Path::new(path)) {
+            let swap = self.recovery_queue.remove(i);
+            if i == 0 {
+                self.recovery_diff = None;
+            }
+            match ask_to_recover(&swap) {
+                RecoveryChoice::Recover => recovered = Some(swap),
+                RecoveryChoice::Discard => self.swap_writer.remove(&swap.id),
+                RecoveryChoice::Later => {}
+            }
+        }
 
         match self.current_file_type {
             CurrentFileType::Pdf => {
                 // Load PDF file directly
                 self.pdf_path = Some(std::path::PathBuf::from(path));
-                self.editor_content = "".to_string();
+                self.editor_content = Document::default();
                 self.is_dirty = false;
                 // PDF will be loaded on next render when pdfium is available
             }
@@ -1099,11 +2297,11 @@ impl TypesafeApp {
                 // Load text files normally
                 match std::fs::read_to_string(path) {
                     Ok(contents) => {
-                        self.editor_content = contents;
+                        self.editor_content = Document::from(contents);
                         self.is_dirty = false;
                     }
                     Err(e) => {
-                        self.editor_content = format!("Error loading file: {}", e);
+                        self.editor_content = Document::from(format!("Error loading file: {}", e));
                     }
                 }
             }
@@ -1111,22 +2309,31 @@ impl TypesafeApp {
                 // Load LaTeX files and compile
                 match std::fs::read_to_string(path) {
                     Ok(contents) => {
-                        self.editor_content = contents;
+                        self.editor_content = Document::from(contents);
                         self.is_dirty = false;
                         self.compile(ctx);
                     }
                     Err(e) => {
-                        self.editor_content = format!("Error loading file: {}", e);
+                        self.editor_content = Document::from(format!("Error loading file: {}", e));
                     }
                 }
             }
         }
+
+        if let Some(swap) = recovered {
+            // Undoing the recovery gets back the text from the disk
+            self.editor_content.replace_all(&swap.content);
+            self.is_dirty = true;
+            self.buffers[self.active_buffer].swap_id = swap.id;
+        }
     }
 
     fn save_file(&mut self, ctx: &egui::Context, trigger_compile: bool) {
-        match std::fs::write(&self.file_path, &self.editor_content) {
+        match std::fs::write(&self.file_path, self.editor_content.as_str()) {
             Ok(_) => {
                 self.is_dirty = false;
+                // Typing after a save is a separate undo step
+                self.editor_content.break_group();
                 self.compilation_log = "File saved successfully\n".to_string();
                 self.update_outline();
                 if self.settings.auto_compile && trigger_compile {
@@ -1163,258 +2370,69 @@ impl TypesafeApp {
     fn compile(&mut self, ctx: &egui::Context) {
         if self.settings.autosave_on_compile && self.is_dirty {
             if !self.file_path.is_empty() && self.file_path != "untitled.tex" && !self.file_path.ends_with("untitled.tex") {
-                 let _ = std::fs::write(&self.file_path, &self.editor_content);
+                 let _ = std::fs::write(&self.file_path, self.editor_content.as_str());
                  self.is_dirty = false;
                  self.update_outline();
             }
         }
-        let tx = self.compile_tx.clone();
-        let ctx = ctx.clone();
-        let content = self.editor_content.clone();
-        let file_path = self.file_path.clone();
-        let root_file = self.root_file.clone();
-        let last_bcf_hash = self.last_bcf_hash.clone();
-        let last_bib_hash = self.last_bib_hash.clone();
-
-        std::thread::spawn(move || {
-            let _ = tx.send(CompilationMsg::Start);
-            ctx.request_repaint();
-
-            // Determine what to compile
-            let target_path = if let Some(root) = root_file {
-                root
-            } else if !file_path.is_empty() {
-                file_path.clone()
-            } else {
-                "temp.tex".to_string()
-            };
-
-            // Save current file content to disk
-            let save_path = if file_path.is_empty() { "temp.tex".to_string() } else { file_path.clone() };
-            // Strip BOM if present to prevent "Environment document undefined" errors
-            let clean_content = content.trim_start_matches('\u{feff}');
-            if let Err(e) = std::fs::write(&save_path, clean_content) {
-                let _ = tx.send(CompilationMsg::Error(format!("Write error: {}", e)));
-                ctx.request_repaint();
-                return;
-            }
-
-            // Locate tectonic binary
-            let tectonic_name = if cfg!(windows) { "tectonic.exe" } else { "tectonic" };
-            let mut tectonic_path = std::path::PathBuf::from(tectonic_name);
-
-            if let Ok(current_exe) = std::env::current_exe() {
-                if let Some(parent) = current_exe.parent() {
-                    let candidate = parent.join(tectonic_name);
-                    if candidate.exists() {
-                        tectonic_path = candidate;
-                    } else {
-                        let candidate_deps = parent.join("deps").join(tectonic_name);
-                        if candidate_deps.exists() {
-                            tectonic_path = candidate_deps;
-                        } else if let Some(target_dir) = parent.parent() {
-                            if let Some(project_root) = target_dir.parent() {
-                                let dev_candidate = project_root.join("deps").join(tectonic_name);
-                                if dev_candidate.exists() {
-                                    tectonic_path = dev_candidate;
-                                }
-                            }
-                        }
-                    }
-                }
+        // Unsaved changes in other tabs go into the build too
+        let mut other_sources = Vec::new();
+        for (i, buffer) in self.buffers.iter_mut().enumerate() {
+            if i == self.active_buffer || !buffer.is_dirty || buffer.file_type != CurrentFileType::Tex || buffer.path.ends_with("untitled.tex") {
+                continue;
             }
-
-            // Locate biber binary
-            let biber_name = if cfg!(windows) { "biber.exe" } else { "biber" };
-            let mut biber_path = std::path::PathBuf::from(biber_name);
-
-            if let Ok(current_exe) = std::env::current_exe() {
-                if let Some(parent) = current_exe.parent() {
-                    let candidate = parent.join(biber_name);
-                    if candidate.exists() {
-                        biber_path = candidate;
-                    } else {
-                        let candidate_deps = parent.join("deps").join(biber_name);
-                        if candidate_deps.exists() {
-                            biber_path = candidate_deps;
-                        } else if let Some(target_dir) = parent.parent() {
-                            if let Some(project_root) = target_dir.parent() {
-                                let dev_candidate = project_root.join("deps").join(biber_name);
-                                if dev_candidate.exists() {
-                                    biber_path = dev_candidate;
-                                }
-                            }
-                        }
-                    }
-                }
+            if self.settings.autosave_on_compile && std::fs::write(&buffer.path, buffer.content.as_str()).is_ok() {
+                buffer.is_dirty = false;
+            } else {
+                other_sources.push((PathBuf::from(&buffer.path), buffer.content.trim_start_matches('\u{feff}').to_string()));
             }
+        }
+        let content = &self.editor_content;
+        let file_path = &self.file_path;
 
-            // Determine output dir
-            let parent_dir = std::path::Path::new(&target_path).parent().unwrap_or(std::path::Path::new("."));
-            let file_stem = std::path::Path::new(&target_path).file_stem().unwrap_or_default().to_string_lossy();
-
-            let run_tectonic = || {
-                let mut cmd = Command::new(&tectonic_path);
-                cmd.current_dir(parent_dir)
-                    .arg(&target_path)
-                    .arg("--synctex")
-                    .arg("--keep-intermediates")
-                    .stdout(Stdio::piped())
-                    .stderr(Stdio::piped());
-
-                #[cfg(windows)]
-                {
-                    use std::os::windows::process::CommandExt;
-                    const CREATE_NO_WINDOW: u32 = 0x08000000;
-                    cmd.creation_flags(CREATE_NO_WINDOW);
-                }
-                cmd.output()
-            };
-
-            let mut output;
-            let has_biblatex = content.contains("biblatex");
-
-            // Hashing helper
-            let hash_file = |path: &std::path::Path| -> String {
-                if let Ok(mut file) = std::fs::File::open(path) {
-                    let mut hasher = Sha256::new();
-                    if let Ok(_) = std::io::copy(&mut file, &mut hasher) {
-                        return format!("{:x}", hasher.finalize());
-                    }
-                }
-                String::new()
-            };
-
-            let mut current_bcf_hash = String::new();
-            let mut current_bib_hash = String::new();
-
-            // Optimistic First Pass (Full Convergence)
-            // We run Tectonic fully. Most of the time, this is all we need.
-            // If we detect that Biber was needed (citations changed), we run it and then re-run Tectonic.
-            let _ = tx.send(CompilationMsg::Log("Compiling document...".to_string()));
-            ctx.request_repaint();
-            output = run_tectonic();
-
-            if has_biblatex {
-                let bcf_path = parent_dir.join(format!("{}.bcf", file_stem));
-                if output.as_ref().map(|o| o.status.success()).unwrap_or(false) && bcf_path.exists() {
-                    // Calculate hashes
-                    current_bcf_hash = hash_file(&bcf_path);
-
-                    let mut bib_hasher = Sha256::new();
-                    if let Ok(entries) = std::fs::read_dir(parent_dir) {
-                        let mut paths: Vec<_> = entries.filter_map(|e| e.ok()).map(|e| e.path()).filter(|p| p.extension().map_or(false, |e| e == "bib")).collect();
-                        paths.sort();
-                        for p in paths {
-                            if let Ok(bytes) = std::fs::read(&p) {
-                                bib_hasher.update(&bytes);
-                            }
-                        }
-                    }
-                    current_bib_hash = format!("{:x}", bib_hasher.finalize());
-
-                    let mut run_biber = true;
-                    // If hashes match previous run, we assume bibliography is stable.
-                    if current_bcf_hash == last_bcf_hash && current_bib_hash == last_bib_hash && !current_bcf_hash.is_empty() {
-                         let _ = tx.send(CompilationMsg::Log("Citations unchanged.".to_string()));
-                         ctx.request_repaint();
-                         run_biber = false;
-                    }
-
-                    if run_biber {
-                        let _ = tx.send(CompilationMsg::Log("Citations changed. Processing bibliography with Biber...".to_string()));
-                        ctx.request_repaint();
-
-                        let mut biber_cmd = Command::new(&biber_path);
-                        biber_cmd.current_dir(parent_dir)
-                            .arg(&*file_stem)
-                            .stdout(Stdio::piped())
-                            .stderr(Stdio::piped());
-
-                        #[cfg(windows)]
-                        {
-                            use std::os::windows::process::CommandExt;
-                            const CREATE_NO_WINDOW: u32 = 0x08000000;
-                            biber_cmd.creation_flags(CREATE_NO_WINDOW);
-                        }
-
-                        if let Ok(biber_out) = biber_cmd.output() {
-                            if !biber_out.status.success() {
-                                 let err = String::from_utf8_lossy(&biber_out.stderr);
-                                 let _ = tx.send(CompilationMsg::Log(format!("Biber warning/error: {}", err)));
-                                 ctx.request_repaint();
-                            }
-
-                            // Run Tectonic again to incorporate bibliography
-                            let _ = tx.send(CompilationMsg::Log("Re-compiling document to link citations...".to_string()));
-                            ctx.request_repaint();
-                            output = run_tectonic();
-                        } else {
-                             let _ = tx.send(CompilationMsg::Log("Failed to execute Biber.".to_string()));
-                             ctx.request_repaint();
-                        }
-                    }
-                }
-            }
+        // Determine what to compile
+        let root = self.root_document();
+        let target_path = if let Some(root) = &root {
+            root.clone()
+        } else if !file_path.is_empty() {
+            file_path.clone()
+        } else {
+            "temp.tex".to_string()
+        };
+        let mut engine_settings = if let Some(project_engine) = self.project.as_ref().and_then(ProjectConfig::engine_settings) {
+            project_engine
+        } else if root.is_none() && file_path.is_empty() {
+            self.settings.build_settings_for(&self.current_dir).clone()
+        } else {
+            self.settings.build_settings_for(std::path::Path::new(&target_path)).clone()
+        };
 
-            if let Ok(out) = &output {
-                let stdout = String::from_utf8_lossy(&out.stdout);
-                let stderr = String::from_utf8_lossy(&out.stderr);
-                let mut diagnostics = Vec::new();
-                // Tectonic output parsing
-                let error_regex = regex::Regex::new(r"error: .+:(\d+): (.*)").unwrap();
-
-                for line in stdout.lines().chain(stderr.lines()) {
-                    if let Some(caps) = error_regex.captures(line) {
-                        if let Ok(line_num) = caps[1].parse::<usize>() {
-                            diagnostics.push(Diagnostic {
-                                line: line_num,
-                                message: caps[2].to_string(),
-                                file: target_path.clone(),
-                            });
-                        }
-                    }
-                }
-                if !diagnostics.is_empty() {
-                     let _ = tx.send(CompilationMsg::Diagnostics(diagnostics));
-                     ctx.request_repaint();
-                }
-            }
+        // Save current file content to disk
+        let save_path = if file_path.is_empty() { "temp.tex".to_string() } else { file_path.clone() };
+        // Strip BOM if present to prevent "Environment document undefined" errors
+        let clean_content = content.trim_start_matches('\u{feff}').to_string();
 
-            match output {
-                Ok(output) if output.status.success() => {
-                    let stem = std::path::Path::new(&target_path).file_stem().unwrap_or_default();
-                    let pdf_name = format!("{}.pdf", stem.to_string_lossy());
-                    let pdf_path = parent_dir.join(&pdf_name);
+        let magic = self.document_magic();
+        if let Some(engine) = magic.program.as_deref().and_then(Engine::from_name) {
+            engine_settings.engine = engine;
+        }
 
-                    if pdf_path.exists() {
-                        let _ = tx.send(CompilationMsg::Success(pdf_path, current_bcf_hash, current_bib_hash));
-                        ctx.request_repaint();
-                    } else {
-                        let _ = tx.send(CompilationMsg::Error(
-                            "PDF file not found after compilation".to_string(),
-                        ));
-                        ctx.request_repaint();
-                    }
-                }
-                Ok(output) => {
-                    let stdout = String::from_utf8_lossy(&output.stdout);
-                    let stderr = String::from_utf8_lossy(&output.stderr);
-                    let _ = tx.send(CompilationMsg::Error(format!(
-                        "Compilation failed with code: {}\n\nSTDOUT:\n{}\n\nSTDERR:\n{}",
-                        output.status.code().unwrap_or(-1),
-                        stdout,
-                        stderr
-                    )));
-                    ctx.request_repaint();
-                }
-                Err(e) => {
-                    let _ = tx.send(CompilationMsg::Error(format!("Failed to run tectonic: {}", e)));
-                    ctx.request_repaint();
-                }
-            }
-        });
+        let mut job = CompileJob::new(target_path).with_engine(&engine_settings);
+        job.bib_tool = magic.bib_program.as_deref().and_then(BibTool::from_name);
+        if self.project_hooks_allowed() {
+            job.hooks = self.project.as_ref().map(|p| p.hooks.clone()).unwrap_or_default();
+        }
+        job.out_dir = self.output_dir_for(&job.target);
+        self.build_source_dir = job.source_dir().to_path_buf();
+        job.last_bcf_hash = self.last_bcf_hash.clone();
+        job.last_bib_hash = self.last_bib_hash.clone();
+        job.sources.push((PathBuf::from(save_path), clean_content));
+        job.sources.extend(other_sources);
+        job.has_biblatex = job.sources.iter().any(|(_, content)| content.contains("biblatex"));
+        job.timeout = self.settings.compile_timeout();
 
+        let ctx = ctx.clone();
+        self.build_manager.request(job, move || ctx.request_repaint());
         self.is_compiling = true;
     }
 
@@ -1437,11 +2455,7 @@ impl TypesafeApp {
         self.labels.clear();
         self.bib_items.clear();
 
-        let entry_file = if let Some(root) = &self.root_file {
-            root.clone()
-        } else {
-            self.file_path.clone()
-        };
+        let entry_file = self.root_document().unwrap_or_else(|| self.file_path.clone());
 
         if entry_file.is_empty() { return; }
 
@@ -1515,9 +2529,7 @@ impl TypesafeApp {
                 if let Some(caps) = re_section.captures(clean_line) {
                     let type_str = caps.get(1).map_or("", |m| m.as_str());
                     let title = caps.get(2).map_or("", |m| m.as_str());
-                    let level = match type_str {
-                        "part" => 0, "chapter" => 0, "section" => 1, "subsection" => 2, "subsubsection" => 3, _ => 4,
-                    };
+                    let level = folding::section_level(type_str).unwrap_or(4);
 
                     if type_str == "chapter" {
                         counters.chapter += 1;
@@ -1657,17 +2669,21 @@ impl TypesafeApp {
         self.labels = all_labels;
 
         // Scan bibliography using biblatex parser
-        if let Ok(entries) = std::fs::read_dir(&self.current_dir) {
-            for entry in entries.flatten() {
-                 if entry.path().extension().map_or(false, |e| e == "bib") {
-                    if let Ok(c) = std::fs::read_to_string(entry.path()) {
-                        if let Ok(bibliography) = biblatex::Bibliography::parse(&c) {
-                            for entry in bibliography {
-                                self.bib_items.push(entry.key);
-                            }
-                        }
+        let mut bib_files: Vec<PathBuf> = std::fs::read_dir(&self.current_dir)
+            .map(|entries| entries.flatten().map(|e| e.path()).filter(|p| p.extension().is_some_and(|e| e == "bib")).collect())
+            .unwrap_or_default();
+        for path in self.project.iter().flat_map(|p| &p.bib_files) {
+            if !bib_files.iter().any(|known| known == path) {
+                bib_files.push(path.clone());
+            }
+        }
+        for path in bib_files {
+            if let Ok(c) = std::fs::read_to_string(&path) {
+                if let Ok(bibliography) = biblatex::Bibliography::parse(&c) {
+                    for entry in bibliography {
+                        self.bib_items.push(entry.key);
                     }
-                 }
+                }
             }
         }
     }
@@ -1676,9 +2692,14 @@ impl TypesafeApp {
         let mut errors = Vec::new();
         let mut stack = Vec::new();
         let mut escaped = false;
+        // Code and verbatim text are not LaTeX
+        let verbatim = Intervals::new(lexer::verbatim_ranges(text));
 
         // Bracket balance
         for (i, c) in text.char_indices() {
+            if verbatim.contains(i) {
+                continue;
+            }
             if escaped {
                 escaped = false;
                 continue;
@@ -1746,6 +2767,9 @@ impl TypesafeApp {
             "theorem",
             "lemma",
             "proof",
+            "verbatim",
+            "lstlisting",
+            "minted",
         ];
 
         let mut env_stack: Vec<(String, usize, usize)> = Vec::new();
@@ -1767,6 +2791,10 @@ impl TypesafeApp {
             };
 
             let start = pos + idx;
+            if verbatim.contains(start) {
+                pos = start + 1;
+                continue;
+            }
             let name_start = start
                 + if kind == "begin" {
                     "\\begin{".len()
@@ -1815,9 +2843,10 @@ impl TypesafeApp {
              return errors;
         }
 
+        let verbatim = Intervals::new(lexer::verbatim_ranges(text));
         for (start, word) in text.unicode_word_indices() {
-             // Filter out non-alphabetic words
-             if !word.chars().any(|c| c.is_alphabetic()) {
+             // Filter out non-alphabetic words and code
+             if !word.chars().any(|c| c.is_alphabetic()) || verbatim.contains(start) {
                  continue;
              }
 
@@ -1871,39 +2900,92 @@ impl TypesafeApp {
         // Find $1
         if let Some(idx) = final_text.find("$1") {
             final_text.replace_range(idx..idx+2, "");
-            selection_range = Some(idx);
+            selection_range = Some(final_text[..idx].chars().count());
         }
 
+        let end = self.editor_content.len_chars();
         if let Some(mut state) = egui::TextEdit::load_state(ctx, egui::Id::new("main_editor")) {
             if let Some(range) = state.cursor.char_range() {
-                let cursor = range.primary.index;
-                self.editor_content.insert_str(cursor, &final_text);
+                let cursor = range.primary.index.min(end);
+                self.editor_content.transact(|tx| tx.insert(cursor, &final_text));
 
                 // Calculate new cursor position
                 let new_cursor_idx = if let Some(rel_idx) = selection_range {
                     cursor + rel_idx
                 } else {
-                    cursor + final_text.len()
+                    cursor + final_text.chars().count()
                 };
 
                 state.cursor.set_char_range(Some(CCursorRange::one(CCursor::new(new_cursor_idx))));
                 state.store(ctx, egui::Id::new("main_editor"));
             } else {
-                self.editor_content.push_str(&final_text);
+                self.editor_content.transact(|tx| tx.insert(end, &final_text));
             }
         } else {
-            self.editor_content.push_str(&final_text);
+            self.editor_content.transact(|tx| tx.insert(end, &final_text));
+        }
+        self.is_dirty = true;
+        ctx.request_repaint();
+    }
+
+    // Reverts the last undo step in the editor
+    fn undo(&mut self, ctx: &egui::Context) {
+        if let Some(cursor) = self.editor_content.undo() {
+            self.after_history_step(ctx, cursor);
         }
+    }
+
+    fn redo(&mut self, ctx: &egui::Context) {
+        if let Some(cursor) = self.editor_content.redo() {
+            self.after_history_step(ctx, cursor);
+        }
+    }
+
+    fn after_history_step(&mut self, ctx: &egui::Context, cursor: usize) {
+        let editor_id = egui::Id::new("main_editor");
+        let mut state = egui::TextEdit::load_state(ctx, editor_id).unwrap_or_default();
+        state.cursor.set_char_range(Some(CCursorRange::one(CCursor::new(cursor))));
+        state.store(ctx, editor_id);
+        self.pending_cursor_scroll = Some(cursor);
         self.is_dirty = true;
+        self.checks_dirty = true;
+        self.update_outline();
         ctx.request_repaint();
     }
 
-    fn apply_completion(&mut self, ctx: &egui::Context, text: &mut String, completion: &str) {
+    // Folds or unfolds the region starting on 0-based `line`
+    fn toggle_fold(&mut self, ctx: &egui::Context, line: usize) {
+        self.folds.toggle(line);
+        self.keep_cursor_visible(ctx);
+    }
+
+    fn fold_all(&mut self, ctx: &egui::Context) {
+        self.folds.fold_all();
+        self.keep_cursor_visible(ctx);
+    }
+
+    // A cursor left inside a folded region would unfold it again, so it moves
+    // to the end of the region's first line
+    fn keep_cursor_visible(&mut self, ctx: &egui::Context) {
+        let editor_id = egui::Id::new("main_editor");
+        let Some(mut state) = egui::TextEdit::load_state(ctx, editor_id) else { return };
+        let Some(range) = state.cursor.char_range() else { return };
+        let line = self.editor_content.char_to_line(range.primary.index);
+        if let Some(hidden) = self.folds.hidden_lines().into_iter().find(|lines| lines.contains(&line)) {
+            let heading = hidden.start - 1;
+            let cursor = self.editor_content.line_to_char(heading) + self.editor_content.line(heading).chars().count();
+            state.cursor.set_char_range(Some(CCursorRange::one(CCursor::new(cursor))));
+            state.store(ctx, editor_id);
+        }
+    }
+
+    fn apply_completion(&mut self, ctx: &egui::Context, completion: &str) {
         if let Some(mut state) = egui::TextEdit::load_state(ctx, egui::Id::new("main_editor")) {
             if let Some(range) = state.cursor.char_range() {
-                let cursor_idx = range.primary.index;
+                let text = &self.editor_content;
+                let cursor_idx = range.primary.index.min(text.len_chars());
                 let mut word_start = cursor_idx;
-                let chars: Vec<char> = text.chars().collect();
+                let chars: Vec<char> = text.slice(0..cursor_idx).chars().collect();
 
                 while word_start > 0 {
                     let prev_char = chars[word_start - 1];
@@ -1914,8 +2996,8 @@ impl TypesafeApp {
                 }
 
                 // Detect indentation
-                let line_start = text[..word_start].rfind('\n').map(|i| i + 1).unwrap_or(0);
-                let base_indent = text[line_start..word_start].chars().take_while(|c| c.is_whitespace()).collect::<String>();
+                let line_start = text.line_to_char(text.char_to_line(word_start));
+                let base_indent = text.slice(line_start..word_start).chars().take_while(|c| c.is_whitespace()).collect::<String>();
 
                 // Normalize tabs to our indent unit and re-indent following lines
                 let normalized = completion.replace("\t", INDENT_UNIT);
@@ -1929,22 +3011,22 @@ impl TypesafeApp {
 
                 // Avoid double closing braces/brackets when one already exists at the cursor
                 let mut final_completion = indented_completion.clone();
-                if let Some(next) = text[cursor_idx..].chars().next() {
+                if let Some(next) = text.slice(cursor_idx..text.len_chars()).chars().next() {
                     if (final_completion.ends_with('}') && next == '}') || (final_completion.ends_with(']') && next == ']') {
                         final_completion.pop();
                     }
                 }
 
-                text.drain(word_start..cursor_idx);
-                text.insert_str(word_start, &final_completion);
+                self.editor_content.transact(|tx| tx.replace(word_start..cursor_idx, &final_completion));
 
-                let mut new_cursor_idx = word_start + final_completion.len();
+                let chars_before = |pos: usize| final_completion[..pos].chars().count();
+                let mut new_cursor_idx = word_start + final_completion.chars().count();
                 if let Some(pos) = final_completion.find("{}") {
-                    new_cursor_idx = word_start + pos + 1;
+                    new_cursor_idx = word_start + chars_before(pos) + 1;
                 } else if let Some(pos) = final_completion.find("[]") {
-                    new_cursor_idx = word_start + pos + 1;
+                    new_cursor_idx = word_start + chars_before(pos) + 1;
                 } else if let Some(pos) = final_completion.find("}{") {
-                    new_cursor_idx = word_start + pos + 1;
+                    new_cursor_idx = word_start + chars_before(pos) + 1;
                 }
 
                 state.cursor.set_char_range(Some(egui::text::CCursorRange::one(egui::text::CCursor::new(new_cursor_idx))));
@@ -1957,66 +3039,43 @@ impl TypesafeApp {
         ctx.request_repaint();
     }
 
-    fn sync_forward_search(&mut self, line_num: usize) {
-        let mut found = false;
-        if let Some(pdf_path) = &self.pdf_path {
-            let stem = pdf_path.file_stem().unwrap_or_default();
-            let parent = pdf_path.parent().unwrap_or(std::path::Path::new("."));
-            let synctex_path = parent.join(format!("{}.synctex.gz", stem.to_string_lossy()));
-
-            if synctex_path.exists() {
-                 if let Ok(file) = std::fs::File::open(&synctex_path) {
-                    let mut decoder = GzDecoder::new(file);
-                    let mut content = String::new();
-                    if decoder.read_to_string(&mut content).is_ok() {
-                        let file_name = std::path::Path::new(&self.file_path).file_name().unwrap_or_default().to_string_lossy();
-
-                        // 1. Find File ID
-                        let mut file_id = None;
-                        for line in content.lines() {
-                            if line.starts_with("Input:") {
-                                let parts: Vec<&str> = line.splitn(3, ':').collect();
-                                if parts.len() == 3 {
-                                    if parts[2].contains(&*file_name) {
-                                        file_id = parts[1].parse::<usize>().ok();
-                                        break;
-                                    }
-                                }
-                            }
-                        }
+    // Character ranges of the search query in the editor, honouring the case and whole-word options
+    fn find_matches(&self) -> Vec<(usize, usize)> {
+        search_text(&self.editor_content, &self.search_query, self.search_case_sensitive, self.search_whole_word)
+            .into_iter()
+            .map(|r| (self.editor_content.byte_to_char(r.start), self.editor_content.byte_to_char(r.end)))
+            .collect()
+    }
 
-                        if let Some(fid) = file_id {
-                            let mut current_page = 1;
-                            let prefix = format!("{},{}:", fid, line_num);
-
-                            for line in content.lines() {
-                                if line.starts_with('{') {
-                                    if let Ok(p) = line[1..].parse::<usize>() { current_page = p; }
-                                } else if line.len() > 1 && line[1..].starts_with(&prefix) {
-                                     let parts: Vec<&str> = line.split(':').collect();
-                                     if parts.len() >= 2 {
-                                         let coords: Vec<&str> = parts[1].split(',').collect();
-                                         if coords.len() >= 2 {
-                                             if let Ok(v_sp) = coords[1].parse::<f32>() {
-                                                 let v_pt = v_sp / 65536.0;
-                                                 let page_idx = current_page.saturating_sub(1);
-
-                                                 let mut rel_y = 0.5;
-                                                 if let Some((_, h)) = self.page_sizes.get(&page_idx) {
-                                                     if *h > 0.0 { rel_y = v_pt / *h; }
-                                                 }
-                                                 self.current_page = page_idx;
-                                                 self.pending_scroll_target = Some((page_idx, rel_y));
-                                                 found = true;
-                                                 break;
-                                             }
-                                         }
-                                     }
-                                }
-                            }
-                        }
-                    }
-                 }
+    // Parses the PDF's SyncTeX data off the UI thread; the result arrives through synctex_rx.
+    // `source_dir` is where the engine ran, which relative input paths are based on.
+    fn build_synctex_index(&mut self, ctx: &egui::Context, pdf_path: &std::path::Path, source_dir: PathBuf) {
+        self.synctex = None;
+        let Some(synctex_path) = SyncTexIndex::for_pdf(pdf_path) else { return };
+        let tx = self.synctex_tx.clone();
+        let pdf_path = pdf_path.to_path_buf();
+        let ctx = ctx.clone();
+        std::thread::spawn(move || {
+            if let Ok(index) = SyncTexIndex::load(&synctex_path, &source_dir) {
+                let _ = tx.send((pdf_path, index));
+                ctx.request_repaint();
+            }
+        });
+    }
+
+    fn sync_forward_search(&mut self, ctx: &egui::Context, line_num: usize) {
+        let mut found = false;
+        if let Some(index) = &self.synctex {
+            let area = index.forward_area(std::path::Path::new(&self.file_path), line_num);
+            if let Some((page, rect)) = area.first().copied() {
+                let mut rel_y = 0.5;
+                if let Some((_, h)) = self.page_sizes.get(&page) {
+                    if *h > 0.0 { rel_y = rect.center().y / *h; }
+                }
+                self.current_page = page;
+                self.pending_scroll_target = Some((page, rel_y));
+                self.sync_highlight = Some(SyncHighlight { area, started: ctx.input(|i| i.time) });
+                found = true;
             }
         }
 
@@ -2030,11 +3089,13 @@ impl TypesafeApp {
         }
     }
 
-    fn syntax_highlighting(&self, theme: &ThemeColors, text: &str) -> egui::text::LayoutJob {
-        let syntax = self
-            .syntax_set
-            .find_syntax_by_extension("tex")
-            .unwrap_or_else(|| self.syntax_set.find_syntax_plain_text());
+    fn syntax_highlighting(&mut self, theme: &ThemeColors, text: &str) -> egui::text::LayoutJob {
+        // Non-LaTeX files are highlighted by syntect when it knows the extension
+        let extension = std::path::Path::new(&self.file_path).extension().and_then(|e| e.to_str()).unwrap_or("");
+        let syntax = match self.current_file_type {
+            CurrentFileType::Tex => None,
+            _ => self.syntax_set.find_syntax_by_extension(extension),
+        };
 
         // Select syntect theme based on our app theme brightness
         let bg_brightness = (theme.bg.r() as u32 + theme.bg.g() as u32 + theme.bg.b() as u32) / 3;
@@ -2046,110 +3107,117 @@ impl TypesafeApp {
         };
 
         let syntect_theme = &self.theme_set.themes[theme_name];
-        let mut highlighter = HighlightLines::new(syntax, syntect_theme);
+        let highlighter = Highlighter::new(syntect_theme);
+        let (key, initial) = match syntax {
+            Some(syntax) => (
+                format!("{} {}", syntax.name, theme_name),
+                LineState::Syntect(ParseState::new(syntax), HighlightState::new(&highlighter, ScopeStack::new())),
+            ),
+            None => (format!("LaTeX {}", theme_name), LineState::Latex(Default::default(), None)),
+        };
+        let syntax_set = &self.syntax_set;
+        let edit = self.highlight_cache.update(text, &key, initial, |state, line| match state {
+            LineState::Latex(lex_state, embedded) => {
+                let code_syntax = lex_state.embedded_language().and_then(|l| syntax_set.find_syntax_by_token(l));
+                let mut tokens = lexer::lex_line(lex_state, line);
+                let mut spans = Vec::new();
+                if let Some(syntax) = code_syntax {
+                    // The code runs up to the block's \end, if that is on this line
+                    let code_end = match tokens.first() {
+                        Some((range, TokenKind::Verbatim)) if range.start == 0 => range.end,
+                        _ => 0,
+                    };
+                    let (parse_state, highlight_state) = embedded
+                        .get_or_insert_with(|| (ParseState::new(syntax), HighlightState::new(&highlighter, ScopeStack::new())));
+                    spans = syntect_spans(parse_state, highlight_state, &highlighter, syntax_set, &line[..code_end])
+                        .into_iter()
+                        .map(|(range, color)| (range, SpanStyle::Color(color)))
+                        .collect();
+                    tokens.retain(|(range, _)| range.start >= code_end);
+                }
+                if lex_state.embedded_language().is_none() {
+                    *embedded = None;
+                }
+                spans.extend(tokens.into_iter().map(|(range, kind)| (range, SpanStyle::Token(kind))));
+                spans
+            }
+            LineState::Syntect(parse_state, highlight_state) => syntect_spans(parse_state, highlight_state, &highlighter, syntax_set, line)
+                .into_iter()
+                .map(|(range, color)| (range, SpanStyle::Color(color)))
+                .collect(),
+        });
 
         // Use cached errors to prevent flashing while typing
-        let errors = &self.cached_syntax_errors;
-        let spell_errors = &self.cached_spell_errors;
+        let errors = Intervals::new(self.cached_syntax_errors.clone());
+        let spell_errors = Intervals::new(self.cached_spell_errors.clone());
 
         // Run search check
-        let mut search_matches_bytes = Vec::new();
-        if self.show_search && !self.search_query.is_empty() {
-             let query = &self.search_query;
-             let raw_matches = if self.search_case_sensitive {
-                 text.match_indices(query).map(|(i, s)| (i, i + s.len())).collect::<Vec<_>>()
-             } else {
-                 text.to_lowercase().match_indices(&query.to_lowercase()).map(|(i, s)| (i, i + s.len())).collect::<Vec<_>>()
-             };
-
-             for (s, e) in raw_matches {
-                 let mut valid = true;
-                 if self.search_whole_word {
-                     let is_start_ok = s == 0 || !text[..s].chars().last().unwrap_or(' ').is_alphanumeric();
-                     let is_end_ok = e == text.len() || !text[e..].chars().next().unwrap_or(' ').is_alphanumeric();
-                     if !is_start_ok || !is_end_ok { valid = false; }
-                 }
-                 if valid { search_matches_bytes.push((s, e)); }
-             }
-        }
-
-        let mut job = egui::text::LayoutJob::default();
-        let mut current_byte_idx = 0;
-        let mut line_num = 1;
+        let search_matches = if self.show_search && !self.search_query.is_empty() {
+            Intervals::new(search_text(text, &self.search_query, self.search_case_sensitive, self.search_whole_word))
+        } else {
+            Intervals::default()
+        };
 
-        for line in LinesWithEndings::from(text) {
-            let ranges: Vec<(Style, &str)> = highlighter
-                .highlight_line(line, &self.syntax_set)
-                .unwrap_or_default();
+        // Compiler errors reported against the open file
+        let error_lines: std::collections::HashSet<usize> = self.diagnostics.iter()
+            .filter(|d| d.severity == Severity::Error && std::path::Path::new(&d.file) == std::path::Path::new(&self.file_path))
+            .map(|d| d.line)
+            .collect();
 
-            let has_compiler_error = self.diagnostics.iter().any(|d| d.line == line_num);
+        // Lines inside folded regions are laid out with no height
+        let hidden = if self.current_file_type == CurrentFileType::Tex {
+            self.folds.update(self.highlight_cache.lines(), edit);
+            Intervals::new(self.folds.hidden_lines())
+        } else {
+            Intervals::default()
+        };
 
-            for (style, range_text) in ranges {
-                let range_len = range_text.len();
-                let range_start = current_byte_idx;
-                let range_end = range_start + range_len;
+        let font_id = TextStyle::Monospace.resolve(&egui::Style::default());
+        let mut job = egui::text::LayoutJob::default();
+        let mut line_start = 0;
+        let mut split_points = Vec::new();
+
+        for (line_idx, line) in self.highlight_cache.lines().iter().enumerate() {
+            if hidden.contains(line_idx) {
+                let format = egui::TextFormat { font_id: font_id.clone(), color: Color32::TRANSPARENT, line_height: Some(0.0), ..Default::default() };
+                job.append(&line.text, 0.0, format);
+                line_start += line.text.len();
+                continue;
+            }
+            let has_compiler_error = error_lines.contains(&(line_idx + 1));
 
-                let fg = style.foreground;
-                let text_color = Color32::from_rgb(fg.r, fg.g, fg.b);
-                let font_id = TextStyle::Monospace.resolve(&egui::Style::default());
+            for (range, style) in &line.spans {
+                let text_color = match *style {
+                    SpanStyle::Token(kind) => theme.token_color(kind),
+                    SpanStyle::Color(color) => color,
+                };
+                let range_start = line_start + range.start;
+                let range_end = line_start + range.end;
 
                 // Split tokens to precisely highlight errors and search matches
-                let mut split_points = vec![0, range_len];
-
-                for e in errors {
-                    if e.start > range_start && e.start < range_end {
-                        split_points.push(e.start - range_start);
-                    }
-                    if e.end > range_start && e.end < range_end {
-                        split_points.push(e.end - range_start);
-                    }
-                }
-
-                for e in spell_errors {
-                    if e.start > range_start && e.start < range_end {
-                        split_points.push(e.start - range_start);
-                    }
-                    if e.end > range_start && e.end < range_end {
-                        split_points.push(e.end - range_start);
-                    }
-                }
-
-                for (s, e) in &search_matches_bytes {
-                    if *s > range_start && *s < range_end {
-                        split_points.push(*s - range_start);
-                    }
-                    if *e > range_start && *e < range_end {
-                        split_points.push(*e - range_start);
-                    }
-                }
-
+                split_points.clear();
+                split_points.extend([range_start, range_end]);
+                errors.boundaries_in(range_start..range_end, &mut split_points);
+                spell_errors.boundaries_in(range_start..range_end, &mut split_points);
+                search_matches.boundaries_in(range_start..range_end, &mut split_points);
                 split_points.sort_unstable();
                 split_points.dedup();
 
-                for i in 0..split_points.len() - 1 {
-                    let local_start = split_points[i];
-                    let local_end = split_points[i + 1];
-                    let abs_start = range_start + local_start;
-                    let abs_end = range_start + local_end;
-
-                    let sub_text = &range_text[local_start..local_end];
+                for piece in split_points.windows(2) {
+                    let (abs_start, abs_end) = (piece[0], piece[1]);
+                    let sub_text = &line.text[abs_start - line_start..abs_end - line_start];
 
-                    let has_error = errors.iter().any(|e| e.start <= abs_start && e.end >= abs_end);
-                    let is_spell_error = spell_errors.iter().any(|e| e.start <= abs_start && e.end >= abs_end);
-                    let is_search_match =
-                        search_matches_bytes.iter().any(|(s, e)| *s <= abs_start && *e >= abs_end);
-
-                    let stroke = if has_error {
+                    let stroke = if errors.contains(abs_start) {
                         Stroke::new(2.0, theme.error)
                     } else if has_compiler_error {
                         Stroke::new(1.5, theme.error)
-                    } else if is_spell_error {
+                    } else if spell_errors.contains(abs_start) {
                         Stroke::new(1.0, theme.warning)
                     } else {
                         Stroke::NONE
                     };
 
-                    let background = if is_search_match {
+                    let background = if search_matches.contains(abs_start) {
                         theme.warning.linear_multiply(0.3)
                     } else {
                         Color32::TRANSPARENT
@@ -2167,10 +3235,8 @@ impl TypesafeApp {
                         },
                     );
                 }
-
-                current_byte_idx += range_len;
             }
-            line_num += 1;
+            line_start += line.text.len();
         }
 
         job
@@ -2180,6 +3246,25 @@ impl TypesafeApp {
 impl eframe::App for TypesafeApp {
     #[allow(deprecated)]
     fn update(&mut self, ctx: &egui::Context, _frame: &mut eframe::Frame) {
+        if std::mem::take(&mut self.pending_session_restore) {
+            // The editor has held the active tab's text since startup
+            self.buffers[self.active_buffer].content = std::mem::take(&mut self.editor_content);
+            self.restore_buffer(ctx, self.active_buffer);
+        }
+        if std::mem::take(&mut self.pending_buffer_recovery) {
+            self.recover_open_buffers();
+        }
+        if let Some(launch) = self.pending_launch.take() {
+            self.apply_launch_args(ctx, launch);
+        }
+        let forwarded: Vec<LaunchArgs> = self.launch_rx.as_ref().map(|rx| rx.try_iter().collect()).unwrap_or_default();
+        for launch in forwarded {
+            self.apply_launch_args(ctx, launch);
+            // Even a launch without arguments brings the window back up
+            ctx.send_viewport_cmd(egui::ViewportCommand::Minimized(false));
+            ctx.send_viewport_cmd(egui::ViewportCommand::Focus);
+        }
+
         // Handle pending compilation
         if self.pending_autocompile {
             self.pending_autocompile = false;
@@ -2188,13 +3273,19 @@ impl eframe::App for TypesafeApp {
 
         // Load PDF if needed
         if self.current_file_type == CurrentFileType::Pdf && self.pdf_path.is_some() && self.pdfium.is_some() {
-            if self.page_count == 0 {
+            if self.pdf_opened != self.pdf_path {
                 if let Some(pdf_path) = &self.pdf_path.clone() {
                     self.load_pdf_preview(ctx, pdf_path);
                 }
             }
         }
 
+        // Rendered pages
+        while let Ok(msg) = self.render_rx.try_recv() {
+            self.handle_render_msg(ctx, msg);
+        }
+        self.request_pdf_pages(ctx);
+
         let now = ctx.input(|i| i.time);
         if self.settings.autosave_timer && self.is_dirty {
             if now - self.last_save_time > 30.0 {
@@ -2205,6 +3296,14 @@ impl eframe::App for TypesafeApp {
                  self.last_save_time = now;
             }
         }
+        if now - self.last_swap_time > SWAP_INTERVAL {
+            self.journal_unsaved_buffers();
+            self.last_swap_time = now;
+        }
+        if ctx.input(|i| i.viewport().close_requested()) || now - self.last_session_save > SESSION_SAVE_INTERVAL {
+            self.save_session(ctx);
+            self.last_session_save = now;
+        }
 
 
 
@@ -2227,7 +3326,6 @@ impl eframe::App for TypesafeApp {
                  // Force PDF to re-fit width when window shrinks to prevent cutoff
                  if self.show_preview_panel {
                      self.fit_mode = PdfFitMode::FitWidth;
-                     self.pdf_textures.clear();
                  }
 
                  if remaining < min_editor_width {
@@ -2251,6 +3349,12 @@ impl eframe::App for TypesafeApp {
             self.synonym_cache.insert(word, synonyms);
         }
 
+        while let Ok((pdf_path, index)) = self.synctex_rx.try_recv() {
+            if self.pdf_path.as_ref() == Some(&pdf_path) {
+                self.synctex = Some(index);
+            }
+        }
+
         // Poll compilation messages
         while let Ok(msg) = self.compile_rx.try_recv() {
             match msg {
@@ -2274,12 +3378,18 @@ impl eframe::App for TypesafeApp {
                     self.last_bib_hash = bib_hash;
                     self.compilation_log.push_str("\nDone!");
                     self.load_pdf_preview(ctx, &pdf_path);
+                    self.build_synctex_index(ctx, &pdf_path, self.build_source_dir.clone());
                 }
                 CompilationMsg::Error(err) => {
                     self.is_compiling = false;
                     self.compilation_log.push_str(&format!("\nERROR: {}", err));
                     self.show_log = true;
                 }
+                CompilationMsg::Cancelled => {
+                    self.is_compiling = false;
+                    self.preview_status = "Compilation stopped".to_string();
+                    self.compilation_log.push_str("\nStopped.");
+                }
             }
         }
 
@@ -2292,6 +3402,17 @@ impl eframe::App for TypesafeApp {
             self.save_file(ctx, true);
         }
 
+        // Tabs (Ctrl+W, Ctrl+PageUp/PageDown)
+        if ctx.input_mut(|i| i.consume_key(egui::Modifiers::COMMAND, egui::Key::W)) {
+            self.close_buffer(ctx, self.active_buffer);
+        }
+        if ctx.input_mut(|i| i.consume_key(egui::Modifiers::COMMAND, egui::Key::PageDown)) {
+            self.switch_to_buffer(ctx, (self.active_buffer + 1) % self.buffers.len());
+        }
+        if ctx.input_mut(|i| i.consume_key(egui::Modifiers::COMMAND, egui::Key::PageUp)) {
+            self.switch_to_buffer(ctx, (self.active_buffer + self.buffers.len() - 1) % self.buffers.len());
+        }
+
         // Block Commenting (Ctrl+/)
         if ctx.input(|i| i.key_pressed(egui::Key::Slash) && (i.modifiers.ctrl || i.modifiers.command)) {
             if let Some(state) = egui::TextEdit::load_state(ctx, egui::Id::new("main_editor")) {
@@ -2299,53 +3420,35 @@ impl eframe::App for TypesafeApp {
                     let start = range.primary.index.min(range.secondary.index);
                     let end = range.primary.index.max(range.secondary.index);
 
-                    let mut current_pos = 0;
-                    let mut start_line_idx = 0;
-                    let mut end_line_idx = 0;
-
-                    let mut lines: Vec<String> = self.editor_content.lines().map(|s| s.to_string()).collect();
-                    // Handle trailing newline case which lines() swallows
-                    if self.editor_content.ends_with('\n') {
-                        lines.push(String::new());
-                    }
-
-                    for (i, line) in lines.iter().enumerate() {
-                        let line_len = line.chars().count() + 1;
-
-                        if current_pos <= start {
-                            start_line_idx = i;
-                        }
-                        if current_pos < end {
-                            end_line_idx = i;
-                        }
-                        current_pos += line_len;
+                    let doc = &mut self.editor_content;
+                    let start_line = doc.char_to_line(start);
+                    let mut end_line = doc.char_to_line(end);
+                    // A selection ending at the start of a line leaves that line alone
+                    if end > start && end_line > start_line && doc.line_to_char(end_line) == end {
+                        end_line -= 1;
                     }
 
-                    if start_line_idx < lines.len() {
-                        let target_end = end_line_idx.min(lines.len() - 1);
-                        let target_lines = &lines[start_line_idx..=target_end];
-                        let all_commented = target_lines.iter().all(|l| l.trim_start().starts_with('%'));
-
-                        for i in start_line_idx..=target_end {
+                    let all_commented = (start_line..=end_line).all(|l| doc.line(l).trim_start().starts_with('%'));
+                    doc.transact(|tx| {
+                        for l in start_line..=end_line {
+                            let line_start = tx.doc().line_to_char(l);
                             if all_commented {
-                                if let Some(idx) = lines[i].find('%') {
-                                    lines[i].remove(idx);
+                                if let Some(idx) = tx.doc().line(l).chars().position(|c| c == '%') {
+                                    tx.delete(line_start + idx..line_start + idx + 1);
                                 }
                             } else {
-                                lines[i].insert(0, '%');
+                                tx.insert(line_start, "%");
                             }
                         }
-
-                        self.editor_content = lines.join("\n");
-                        self.is_dirty = true;
-                    }
+                    });
+                    self.is_dirty = true;
                 }
             }
         }
         if ctx.input(|i| {
             i.key_pressed(egui::Key::B) && (i.modifiers.ctrl || i.modifiers.command)
         }) {
-            if self.current_file_type == CurrentFileType::Tex && !self.is_compiling {
+            if self.current_file_type == CurrentFileType::Tex {
                 self.compile(ctx);
             }
         }
@@ -2384,72 +3487,43 @@ impl eframe::App for TypesafeApp {
                 // File Menu
                 ui.menu_button("File", |ui| {
                     if ui.button("New").clicked() {
-                        let mut proceed = true;
-                        if self.is_dirty {
-                            if self.settings.autosave_on_change {
-                                self.save_file(ctx, false);
-                            } else {
-                                let confirmed = rfd::MessageDialog::new()
-                                    .set_title("Unsaved Changes")
-                                    .set_description("Do you want to save changes to the current file?")
-                                    .set_buttons(rfd::MessageButtons::YesNoCancel)
-                                    .show();
-                                match confirmed {
-                                    rfd::MessageDialogResult::Yes => self.save_file(ctx, true),
-                                    rfd::MessageDialogResult::No => {},
-                                    rfd::MessageDialogResult::Cancel => proceed = false,
-                                    _ => {},
-                                }
-                            }
-                        }
-                        if proceed {
-                            self.editor_content = "\\documentclass{article}\n\\begin{document}\n\n\\end{document}".to_string();
-                            self.file_path = self.current_dir.join("untitled.tex").to_string_lossy().to_string();
-                            self.settings.last_file = None;
-                            self.settings.save();
-                            ui.close_menu();
-                        }
+                        self.new_buffer(ctx);
+                        ui.close_menu();
                     }
                     ui.separator();
                     if ui.button("Open File...").clicked() {
-                        let mut proceed = true;
-                        if self.is_dirty {
-                             if self.settings.autosave_on_change {
-                                 self.save_file(ctx, false);
-                             } else {
-                                 let confirmed = rfd::MessageDialog::new()
-                                     .set_title("Unsaved Changes")
-                                     .set_description("Do you want to save changes to the current file?")
-                                     .set_buttons(rfd::MessageButtons::YesNoCancel)
-                                     .show();
-                                 match confirmed {
-                                     rfd::MessageDialogResult::Yes => self.save_file(ctx, true),
-                                     rfd::MessageDialogResult::No => {},
-                                     rfd::MessageDialogResult::Cancel => proceed = false,
-                                     _ => {},
-                                 }
-                             }
-                        }
-                        if proceed {
-                            if let Some(path) = rfd::FileDialog::new().add_filter("LaTeX", &["tex"]).pick_file() {
-                                self.file_path = path.to_string_lossy().to_string();
-                                let path_str = self.file_path.clone();
-                                self.load_file(ctx, &path_str);
-                                self.settings.last_file = Some(self.file_path.clone());
-                                self.settings.save();
-                                if let Some(parent) = path.parent() {
-                                    self.current_dir = parent.to_path_buf();
-                                }
-                                ui.close_menu();
+                        if let Some(path) = rfd::FileDialog::new().add_filter("LaTeX", &["tex"]).pick_file() {
+                            if let Some(parent) = path.parent() {
+                                self.open_project_dir(parent.to_path_buf());
                             }
+                            self.open_file(ctx, &path.to_string_lossy());
+                            ui.close_menu();
                         }
                     }
                     if ui.button("Open Folder...").clicked() {
                         if let Some(folder) = rfd::FileDialog::new().pick_folder() {
-                            self.current_dir = folder;
+                            self.open_project_dir(folder);
                             ui.close_menu();
                         }
                     }
+                    ui.menu_button("Recent Projects", |ui| {
+                        let current = std::path::absolute(&self.project_dir).unwrap_or_else(|_| self.project_dir.clone());
+                        let recent: Vec<PathBuf> = self.session.projects.iter().map(|p| p.dir.clone()).filter(|d| *d != current && d.is_dir()).collect();
+                        if recent.is_empty() {
+                            ui.label("No recent projects");
+                        }
+                        for dir in recent {
+                            let name = dir.file_name().unwrap_or(dir.as_os_str()).to_string_lossy().to_string();
+                            if ui.button(name).on_hover_text(dir.display().to_string()).clicked() {
+                                self.open_recent_project(ctx, dir);
+                                ui.close_menu();
+                            }
+                        }
+                    });
+                    if ui.add(egui::Button::new("Close Tab").shortcut_text("Ctrl+W")).clicked() {
+                        self.close_buffer(ctx, self.active_buffer);
+                        ui.close_menu();
+                    }
                     ui.separator();
                     if ui.add(egui::Button::new("Save").shortcut_text("Ctrl+S")).clicked() {
                         self.save_file(ctx, true);
@@ -2526,12 +3600,12 @@ impl eframe::App for TypesafeApp {
 
                 // Edit Menu
                 ui.menu_button("Edit", |ui| {
-                     if ui.add(egui::Button::new("Undo").shortcut_text("Ctrl+Z")).clicked() {
-                         // Handled by TextEdit
+                     if ui.add_enabled(self.editor_content.can_undo(), egui::Button::new("Undo").shortcut_text("Ctrl+Z")).clicked() {
+                         self.undo(ctx);
                          ui.close_menu();
                      }
-                     if ui.add(egui::Button::new("Redo").shortcut_text("Ctrl+Y")).clicked() {
-                         // Handled by TextEdit
+                     if ui.add_enabled(self.editor_content.can_redo(), egui::Button::new("Redo").shortcut_text("Ctrl+Y")).clicked() {
+                         self.redo(ctx);
                          ui.close_menu();
                      }
                      ui.separator();
@@ -2552,6 +3626,15 @@ impl eframe::App for TypesafeApp {
                          self.show_search = !self.show_search;
                          ui.close_menu();
                      }
+                     ui.separator();
+                     if ui.button("Fold All").clicked() {
+                         self.fold_all(ctx);
+                         ui.close_menu();
+                     }
+                     if ui.button("Unfold All").clicked() {
+                         self.folds.unfold_all();
+                         ui.close_menu();
+                     }
                 });
 
                 // View Menu
@@ -2574,7 +3657,6 @@ impl eframe::App for TypesafeApp {
                         ui.separator();
                         if ui.add(egui::Button::new("Pop-out PDF Viewer").shortcut_text("Ctrl+Alt+P")).clicked() {
                             self.show_pdf_popup = true;
-                            self.pdf_textures.clear();
                             ui.close_menu();
                         }
                     }
@@ -2582,6 +3664,11 @@ impl eframe::App for TypesafeApp {
 
                 // Tools Menu
                 ui.menu_button("Tools", |ui| {
+                    if ui.button("Clean Auxiliary Files").clicked() {
+                        self.clean_aux_files();
+                        ui.close_menu();
+                    }
+                    ui.separator();
                      ui.menu_button("LaTeX", |ui| {
                         ui.menu_button("Structure", |ui| {
                             if ui.button("Part").clicked() { self.insert_command(ctx, "\\part{}"); ui.close_menu(); }
@@ -2655,6 +3742,18 @@ impl eframe::App for TypesafeApp {
                                 .color(theme.accent),
                         );
                     }
+                    if let Some(root) = self.root_document() {
+                        let source = if self.magic_root().is_some() {
+                            "% !TEX root in this file"
+                        } else if self.project.as_ref().and_then(|p| p.root.as_ref()).is_some_and(|r| r.to_string_lossy() == root) {
+                            "the project file"
+                        } else {
+                            "Set as Root File"
+                        };
+                        let name = std::path::Path::new(&root).file_name().unwrap_or_default().to_string_lossy().to_string();
+                        ui.label(egui::RichText::new(format!("⌂ {}", name)).color(theme.text_secondary))
+                            .on_hover_text(format!("Root document: {}\nFrom {}", root, source));
+                    }
                 });
             });
             ui.add_space(4.0);
@@ -2728,36 +3827,49 @@ impl eframe::App for TypesafeApp {
                             if ui.checkbox(&mut self.settings.autosave_on_change, "Autosave on File Switch").changed() {
                                 self.settings.save();
                             }
+
+                            ui.add_space(12.0);
+                            ui.heading("Spelling");
+                            ui.add_space(4.0);
+                            ui.horizontal(|ui| {
+                                ui.label("Language");
+                                let response = ui.add(egui::TextEdit::singleline(&mut self.settings.spelling_language).desired_width(80.0));
+                                if response.lost_focus() {
+                                    self.settings.save();
+                                    self.refresh_dictionary();
+                                }
+                                ui.label(egui::RichText::new("(en, de, ... or off)").small());
+                            });
+                            if let Some(language) = self.project.as_ref().and_then(|p| p.spelling_language.as_ref()) {
+                                ui.label(egui::RichText::new(format!("This project uses \"{}\" (set in its project file).", language)).small());
+                            }
                         },
                         SettingsTab::Permissions => {
                             ui.label("System Integration");
                             ui.add_space(8.0);
 
-                            #[cfg(windows)]
-                            if ui.button("Set as Default for .tex files").clicked() {
-                                if let Ok(exe_path) = std::env::current_exe() {
-                                    let exe = exe_path.to_string_lossy();
-                                    let hkcu = winreg::RegKey::predef(winreg::enums::HKEY_CURRENT_USER);
-                                    let path = std::path::Path::new("Software\\Classes\\.tex");
-                                    if let Ok((key, _)) = hkcu.create_subkey(&path) {
-                                        let _ = key.set_value("", &"Typesafe.Document");
-                                    }
-
-                                    let path = std::path::Path::new("Software\\Classes\\Typesafe.Document");
-                                    if let Ok((key, _)) = hkcu.create_subkey(&path) {
-                                        let _ = key.set_value("", &"LaTeX Document");
-                                    }
-
-                                    let path = std::path::Path::new("Software\\Classes\\Typesafe.Document\\DefaultIcon");
-                                    if let Ok((key, _)) = hkcu.create_subkey(&path) {
-                                        let _ = key.set_value("", &format!("{},0", exe));
+                            if desktop::is_supported() {
+                                let extensions = desktop::EXTENSIONS.iter().map(|e| format!(".{}", e)).collect::<Vec<_>>().join(", ");
+                                ui.horizontal(|ui| {
+                                    if ui.button("Set as Default for .tex files").on_hover_text(&extensions).clicked() {
+                                        self.integration_status = Some(match desktop::install() {
+                                            Ok(()) => format!("Typesafe now opens {} files.", extensions),
+                                            Err(e) => e,
+                                        });
                                     }
-
-                                    let path = std::path::Path::new("Software\\Classes\\Typesafe.Document\\shell\\open\\command");
-                                    if let Ok((key, _)) = hkcu.create_subkey(&path) {
-                                        let _ = key.set_value("", &format!("\"{}\" \"%1\"", exe));
+                                    if ui.button("Remove File Associations").clicked() {
+                                        self.integration_status = Some(match desktop::uninstall() {
+                                            Ok(()) => "File associations removed.".to_string(),
+                                            Err(e) => e,
+                                        });
                                     }
+                                });
+                                if let Some(status) = &self.integration_status {
+                                    ui.add_space(4.0);
+                                    ui.label(status);
                                 }
+                            } else {
+                                ui.label("File associations are not available on this platform.");
                             }
                         },
                         SettingsTab::APIs => {
@@ -2766,6 +3878,101 @@ impl eframe::App for TypesafeApp {
                             if ui.checkbox(&mut self.settings.auto_compile, "Auto-compile on Save").changed() {
                                 self.settings.save();
                             }
+                            ui.horizontal(|ui| {
+                                ui.label("Stop builds after");
+                                if ui.add(egui::DragValue::new(&mut self.settings.compile_timeout_secs).clamp_range(0..=3600).suffix(" s")).changed() {
+                                    self.settings.save();
+                                }
+                                ui.label(egui::RichText::new("(0 = never)").small());
+                            });
+                            ui.horizontal(|ui| {
+                                ui.label("Build directory");
+                                if ui.add(egui::TextEdit::singleline(&mut self.settings.output_dir).hint_text("build").desired_width(120.0)).changed() {
+                                    self.settings.save();
+                                }
+                                ui.label(egui::RichText::new("(relative to the root document; empty = next to it)").small());
+                            });
+                            if let Some(dir) = self.project.as_ref().and_then(|p| p.output_dir.as_ref()) {
+                                ui.label(egui::RichText::new(format!("This project builds into {} (set in its project file)", dir.display())).small());
+                            }
+
+                            ui.add_space(12.0);
+                            ui.heading("Engine");
+                            ui.add_space(4.0);
+
+                            let project_key = self.current_dir.to_string_lossy().to_string();
+                            let project_name = self.current_dir.file_name().unwrap_or_default().to_string_lossy().to_string();
+                            let mut per_project = self.settings.project_builds.contains_key(&project_key);
+                            if ui.checkbox(&mut per_project, format!("Use separate settings for this project ({})", project_name)).changed() {
+                                if per_project {
+                                    let global = self.settings.build.clone();
+                                    self.settings.project_builds.insert(project_key.clone(), global);
+                                } else {
+                                    self.settings.project_builds.remove(&project_key);
+                                }
+                                self.settings.save();
+                            }
+
+                            let build = match self.settings.project_builds.get_mut(&project_key) {
+                                Some(project_build) => project_build,
+                                None => &mut self.settings.build,
+                            };
+                            let mut changed = false;
+                            egui::ComboBox::from_id_source("engine_selector")
+                                .selected_text(build.engine.name())
+                                .width(200.0)
+                                .show_ui(ui, |ui| {
+                                    for &engine in Engine::all() {
+                                        changed |= ui.selectable_value(&mut build.engine, engine, engine.name()).changed();
+                                    }
+                                });
+
+                            ui.add_space(4.0);
+                            ui.label(format!("{} arguments (separated by spaces):", build.engine.name()));
+                            let engine = build.engine;
+                            let mut args = build.args.get(&engine).cloned().unwrap_or_else(|| engine.default_args().join(" "));
+                            ui.horizontal(|ui| {
+                                if ui.add(egui::TextEdit::singleline(&mut args).desired_width(360.0)).changed() {
+                                    build.args.insert(engine, args.clone());
+                                    changed = true;
+                                }
+                                if ui.button("Reset").clicked() {
+                                    build.args.remove(&engine);
+                                    changed = true;
+                                }
+                            });
+                            if changed {
+                                self.settings.save();
+                            }
+
+                            if let Some(project) = &self.project {
+                                ui.add_space(12.0);
+                                ui.heading("Project File");
+                                ui.add_space(4.0);
+                                ui.label(egui::RichText::new(project.path.to_string_lossy()).small());
+                                ui.label(egui::RichText::new("Values set here take precedence over the settings above.").small());
+                                ui.add_space(4.0);
+                                let relative = |p: &std::path::Path| p.strip_prefix(project.dir()).unwrap_or(p).to_string_lossy().to_string();
+                                let list = |paths: &[PathBuf]| paths.iter().map(|p| relative(p)).collect::<Vec<_>>().join(", ");
+                                egui::Grid::new("project_config_grid").num_columns(2).spacing([16.0, 4.0]).show(ui, |ui| {
+                                    let mut row = |key: &str, value: String| {
+                                        if !value.is_empty() {
+                                            ui.label(key);
+                                            ui.label(value);
+                                            ui.end_row();
+                                        }
+                                    };
+                                    row("Root", project.root.as_deref().map(relative).unwrap_or_default());
+                                    row("Engine", project.engine.map(|e| e.name().to_string()).unwrap_or_default());
+                                    row("Engine arguments", project.engine_args.clone().unwrap_or_default());
+                                    row("Output directory", project.output_dir.as_deref().map(relative).unwrap_or_default());
+                                    row("Spelling language", project.spelling_language.clone().unwrap_or_default());
+                                    row("Dictionaries", list(&project.dictionaries));
+                                    row("Bibliographies", list(&project.bib_files));
+                                    row("Before build", project.hooks.before_build.join("; "));
+                                    row("After build", project.hooks.after_build.join("; "));
+                                });
+                            }
                         },
                         SettingsTab::About => {
                             egui::ScrollArea::vertical().show(ui, |ui| {
@@ -2822,6 +4029,9 @@ impl eframe::App for TypesafeApp {
                                  if self.file_path == old_path_str {
                                      self.file_path = new_path_str.clone();
                                  }
+                                 for buffer in self.buffers.iter_mut().filter(|b| b.path == old_path_str) {
+                                     buffer.path = new_path_str.clone();
+                                 }
                                  // If we renamed the root file, update root_file
                                  if let Some(root) = &self.root_file {
                                      if root == &old_path_str {
@@ -2838,6 +4048,8 @@ impl eframe::App for TypesafeApp {
             }
         }
 
+        self.show_recovery_dialog(ctx);
+
         // ====== POP-OUT PDF VIEWER WINDOW ======
         if self.show_pdf_popup && self.pdf_path.is_some() {
             let viewport_id = egui::ViewportId::from_hash_of("pdf_viewer_viewport");
@@ -2898,28 +4110,22 @@ impl eframe::App for TypesafeApp {
 
                         if (zoom - self.popout_zoom).abs() > 0.001 {
                             self.popout_zoom = zoom;
-                            self.pdf_textures.clear();
                         }
 
                         if ui.button("⟳").on_hover_text("Reset Zoom").clicked() {
                             self.popout_zoom = 1.0;
                             self.popout_fit_mode = PdfFitMode::Normal;
-                            self.pdf_textures.clear();
                         }
 
                         ui.separator();
 
                         if ui.button("↔").on_hover_text("Fit Width").clicked() {
                             self.popout_fit_mode = PdfFitMode::FitWidth;
-                            self.pdf_textures.clear();
                         }
                         if ui.button("⬚").on_hover_text("Fit Page").clicked() {
                             self.popout_fit_mode = PdfFitMode::FitPage;
-                            self.pdf_textures.clear();
-                        }
-                        if ui.toggle_value(&mut self.popout_multi_page_view, "::").on_hover_text("Grid View").clicked() {
-                             self.pdf_textures.clear();
                         }
+                        ui.toggle_value(&mut self.popout_multi_page_view, "::").on_hover_text("Grid View");
 
                         ui.separator();
 
@@ -2929,14 +4135,7 @@ impl eframe::App for TypesafeApp {
                     });
 
                     if self.show_pdf_search {
-                        ui.horizontal(|ui| {
-                            ui.label("Search:");
-                            ui.text_edit_singleline(&mut self.pdf_search_query);
-                            if ui.button("✖").clicked() {
-                                self.show_pdf_search = false;
-                                self.pdf_search_query.clear();
-                            }
-                        });
+                        self.show_pdf_search_bar(ui, &theme);
                     }
 
                     ui.separator();
@@ -2969,7 +4168,6 @@ impl eframe::App for TypesafeApp {
                             new_zoom *= pinch_zoom;
                         }
                         self.popout_zoom = new_zoom.clamp(0.3, 4.0);
-                        self.pdf_textures.clear();
                     }
 
                     egui::ScrollArea::both()
@@ -2981,36 +4179,19 @@ impl eframe::App for TypesafeApp {
                                          ui.spacing_mut().item_spacing = egui::vec2(10.0, 10.0);
 
                                          for page_idx in 0..self.page_count {
-                                             self.render_page(ctx, page_idx);
-                                             if let Some(tex) = self.pdf_textures.get(&page_idx) {
-                                                 let (pw, ph) = self.page_sizes.get(&page_idx).copied().unwrap_or((595.0, 842.0));
-                                                 let aspect = pw / ph;
-                                                 let display_width = pw * self.popout_zoom;
-                                                 let display_height = display_width / aspect;
-
-                                                 ui.add(egui::Image::new((
-                                                     tex.id(),
-                                                     Vec2::new(display_width, display_height),
-                                                 )).sense(egui::Sense::click()));
-                                             }
+                                             let img_resp = self.show_pdf_page(ui, PdfViewer::Popout, page_idx, self.popout_zoom);
+                                             self.paint_pdf_search_highlights(ui, page_idx, img_resp.rect, &theme);
+                                             self.paint_sync_highlight(ui, page_idx, img_resp.rect, &theme);
+                                             scroll_to_pdf_target(ui, &mut self.pending_scroll_target, page_idx, img_resp.rect);
                                          }
                                      });
                                 } else {
                                     ui.vertical_centered(|ui| {
                                         for page_idx in 0..self.page_count {
-                                            self.render_page(ctx, page_idx);
-
-                                            if let Some(tex) = self.pdf_textures.get(&page_idx) {
-                                                let (pw, ph) = self.page_sizes.get(&page_idx).copied().unwrap_or((595.0, 842.0));
-                                                let aspect = pw / ph;
-                                                let display_width = pw * self.popout_zoom;
-                                                let display_height = display_width / aspect;
-
-                                                ui.add(egui::Image::new((
-                                                    tex.id(),
-                                                    Vec2::new(display_width, display_height),
-                                                )).sense(egui::Sense::click()));
-                                            }
+                                            let img_resp = self.show_pdf_page(ui, PdfViewer::Popout, page_idx, self.popout_zoom);
+                                            self.paint_pdf_search_highlights(ui, page_idx, img_resp.rect, &theme);
+                                            self.paint_sync_highlight(ui, page_idx, img_resp.rect, &theme);
+                                            scroll_to_pdf_target(ui, &mut self.pending_scroll_target, page_idx, img_resp.rect);
                                         }
                                     });
                                 }
@@ -3024,7 +4205,6 @@ impl eframe::App for TypesafeApp {
 
             if !show_popup {
                 self.show_pdf_popup = false;
-                self.pdf_textures.clear();
             }
         }
 
@@ -3068,30 +4248,7 @@ impl eframe::App for TypesafeApp {
                                     ctx.request_repaint();
                                 }
                                 if ui.button("➕").on_hover_text("New File").clicked() {
-                                    let mut proceed = true;
-                                    if self.is_dirty {
-                                        if self.settings.autosave_on_change {
-                                            self.save_file(ctx, false);
-                                        } else {
-                                            let confirmed = rfd::MessageDialog::new()
-                                                .set_title("Unsaved Changes")
-                                                .set_description("Do you want to save changes to the current file?")
-                                                .set_buttons(rfd::MessageButtons::YesNoCancel)
-                                                .show();
-                                            match confirmed {
-                                                rfd::MessageDialogResult::Yes => self.save_file(ctx, true),
-                                                rfd::MessageDialogResult::No => {},
-                                                rfd::MessageDialogResult::Cancel => proceed = false,
-                                                _ => {},
-                                            }
-                                        }
-                                    }
-                                    if proceed {
-                                        self.editor_content = "\\documentclass{article}\n\\begin{document}\n\n\\end{document}".to_string();
-                                        self.file_path = self.current_dir.join("untitled.tex").to_string_lossy().to_string();
-                                        self.settings.last_file = None;
-                                        self.settings.save();
-                                    }
+                                    self.new_buffer(ctx);
                                 }
                                 ui.label(
                                     egui::RichText::new(self.current_dir.file_name().unwrap_or_default().to_string_lossy())
@@ -3101,6 +4258,7 @@ impl eframe::App for TypesafeApp {
                             });
                             ui.separator();
 
+                            let root_document = self.root_document();
                             if let Ok(entries) = std::fs::read_dir(&self.current_dir) {
                                 let mut entries: Vec<_> = entries.filter_map(|e| e.ok()).collect();
                                 entries.sort_by_key(|e| {
@@ -3126,7 +4284,7 @@ impl eframe::App for TypesafeApp {
 
                                     let is_selected = path.to_string_lossy() == self.file_path;
 
-                                    let is_root = self.root_file.as_ref().map_or(false, |r| r == &path.to_string_lossy().to_string());
+                                    let is_root = root_document.as_ref().is_some_and(|r| std::path::Path::new(r) == path);
                                     let label_text = if is_root {
                                         format!("{} {} [ROOT]", icon, name)
                                     } else {
@@ -3144,7 +4302,7 @@ impl eframe::App for TypesafeApp {
                                     btn.context_menu(|ui| {
                                         if !is_dir {
                                             if ui.button("Set as Root File").clicked() {
-                                                self.root_file = Some(path.to_string_lossy().to_string());
+                                                self.set_root_file(path.to_string_lossy().to_string());
                                                 ui.close_menu();
                                             }
                                             if ui.button("✏ Rename").clicked() {
@@ -3174,21 +4332,7 @@ impl eframe::App for TypesafeApp {
                                             self.current_dir = path;
                                         } else if is_allowed {
                                             if ["tex", "bib", "cls", "sty", "md", "txt", "pdf"].contains(&ext.as_str()) {
-                                                // Autosave previous file
-                                                if self.settings.autosave_on_change && self.is_dirty && !self.file_path.is_empty() && self.file_path != "untitled.tex" {
-                                                    if let Err(e) = std::fs::write(&self.file_path, &self.editor_content) {
-                                                        self.compilation_log = format!("Error autosaving: {}\n", e);
-                                                    }
-                                                }
-
-                                                self.file_path = path.to_string_lossy().to_string();
-                                                let path_str = self.file_path.clone();
-                                                self.load_file(ctx, &path_str);
-                                                self.settings.last_file = Some(self.file_path.clone());
-                                                self.settings.save();
-                                                if self.current_file_type == CurrentFileType::Tex {
-                                                    self.update_outline();
-                                                }
+                                                self.open_file(ctx, &path.to_string_lossy());
                                             }
                                         }
                                     }
@@ -3269,16 +4413,12 @@ impl eframe::App for TypesafeApp {
                                     };
 
                                     if path_to_load.exists() {
-                                         self.file_path = path_to_load.to_string_lossy().to_string();
-                                         let p = self.file_path.clone();
-                                         self.load_file(ctx, &p);
-                                         self.settings.last_file = Some(self.file_path.clone());
-                                         self.settings.save();
+                                         self.open_file(ctx, &path_to_load.to_string_lossy());
                                     }
                                 }
 
                                 if let Some(mut state) = egui::TextEdit::load_state(ctx, egui::Id::new("main_editor")) {
-                                    let char_idx = self.editor_content.lines().take(line).map(|l| l.len() + 1).sum::<usize>();
+                                    let char_idx = self.editor_content.line_to_char(line);
                                     state.cursor.set_char_range(Some(egui::text::CCursorRange::one(egui::text::CCursor::new(char_idx))));
                                     state.store(ctx, egui::Id::new("main_editor"));
                                     ctx.memory_mut(|m| m.request_focus(egui::Id::new("main_editor")));
@@ -3384,13 +4524,11 @@ impl eframe::App for TypesafeApp {
 
                     if (zoom - self.zoom).abs() > 0.001 {
                         self.zoom = zoom;
-                        self.pdf_textures.clear();
                     }
 
                     if ui.button("⟳").on_hover_text("Reset Zoom").clicked() {
                         self.zoom = 1.0;
                         self.fit_mode = PdfFitMode::Normal;
-                        self.pdf_textures.clear();
                     }
 
                     ui.separator();
@@ -3398,15 +4536,11 @@ impl eframe::App for TypesafeApp {
                     // View Mode Icons
                     if ui.button("↔").on_hover_text("Fit Width").clicked() {
                         self.fit_mode = PdfFitMode::FitWidth;
-                        self.pdf_textures.clear();
                     }
                     if ui.button("⬚").on_hover_text("Fit Page").clicked() {
                         self.fit_mode = PdfFitMode::FitPage;
-                        self.pdf_textures.clear();
-                    }
-                    if ui.toggle_value(&mut self.pdf_multi_page_view, "::").on_hover_text("Grid View").clicked() {
-                         self.pdf_textures.clear();
                     }
+                    ui.toggle_value(&mut self.pdf_multi_page_view, "::").on_hover_text("Grid View");
 
                     ui.separator();
 
@@ -3417,7 +4551,6 @@ impl eframe::App for TypesafeApp {
                 let scroll_zoom = ctx.input(|i| if i.modifiers.ctrl { i.raw_scroll_delta.y } else { 0.0 });
                 if scroll_zoom.abs() > 0.0 {
                     self.zoom = (self.zoom + scroll_zoom * 0.0015).clamp(0.3, 4.0);
-                    self.pdf_textures.clear();
                 }
 
                 // Top-down layout with manual height calculation for preview vs log
@@ -3447,7 +4580,6 @@ impl eframe::App for TypesafeApp {
 
                         if (target_zoom - self.zoom).abs() > 0.005 {
                              self.zoom = target_zoom;
-                             self.pdf_textures.clear();
                              ctx.request_repaint();
                         }
                     }
@@ -3460,184 +4592,79 @@ impl eframe::App for TypesafeApp {
                     .show(ui, |ui| {
                         ui.vertical_centered(|ui| {
                             if self.page_count > 0 {
-                                // Lazily render all pages
                                 for page_idx in 0..self.page_count {
-                                    self.render_page(ctx, page_idx);
-
-                                    if let Some(tex) = self.pdf_textures.get(&page_idx) {
-                                        if let Some([w, h]) = self.preview_size {
-                                        let aspect = w as f32 / h as f32;
-                                        // Use the container width captured outside the scroll area to avoid progressive scaling loop
-                                        let available_w = (preview_container_width - 20.0).max(100.0);
-                                        // With auto-zoom logic above, self.zoom should be correct for Fit modes
-                                        let display_width = if let Some((pw, _)) = self.page_sizes.get(&page_idx) {
-                                            pw * self.zoom
-                                        } else {
-                                            available_w * self.zoom
-                                        };
-                                        let display_height = display_width / aspect;
-
-                                        let img_resp = ui.add(egui::Image::new((
-                                            tex.id(),
-                                            Vec2::new(display_width, display_height),
-                                        )).sense(egui::Sense::click()));
-
-                                        if let Some((p, rel_y)) = self.pending_scroll_target {
-                                            if p == page_idx {
-                                                let y_pos = img_resp.rect.min.y + img_resp.rect.height() * rel_y;
-                                                let target_rect = egui::Rect::from_min_size(
-                                                    egui::pos2(img_resp.rect.min.x, y_pos),
-                                                    egui::vec2(img_resp.rect.width(), 1.0)
-                                                );
-                                                ui.scroll_to_rect(target_rect, Some(egui::Align::Center));
-                                                self.pending_scroll_target = None;
+                                    let img_resp = self.show_pdf_page(ui, PdfViewer::Preview, page_idx, self.zoom);
+                                    let (display_width, display_height) = (img_resp.rect.width(), img_resp.rect.height());
+                                    let aspect = display_width / display_height.max(1.0);
+
+                                    self.paint_pdf_search_highlights(ui, page_idx, img_resp.rect, &theme);
+                                    self.paint_sync_highlight(ui, page_idx, img_resp.rect, &theme);
+                                    scroll_to_pdf_target(ui, &mut self.pending_scroll_target, page_idx, img_resp.rect);
+
+                                    if self.page_count > 0 && img_resp.double_clicked() {
+                                        let mut jumped = false;
+
+                                        // Try SyncTeX inverse search
+                                        if let Some(pos) = img_resp.interact_pointer_pos() {
+                                            if let Some(&(page_w, page_h)) = self.page_sizes.get(&page_idx) {
+                                                let rel = (pos - img_resp.rect.min) / img_resp.rect.size();
+                                                jumped = self.sync_inverse_search(ctx, page_idx, egui::pos2(rel.x * page_w, rel.y * page_h));
                                             }
                                         }
 
-                                        if self.page_count > 0 {
-                                            if img_resp.double_clicked() {
-                                                let mut jumped = false;
-
-                                                // Try SyncTeX inverse search
-                                                if let Some(pos) = img_resp.interact_pointer_pos() {
-                                                    if let Some((_, page_h)) = self.page_sizes.get(&page_idx) {
-                                                        let rel_y = (pos.y - img_resp.rect.min.y) / img_resp.rect.height();
-                                                        let y_pt = rel_y * page_h;
-
-                                                        if let Some(pdf_path) = &self.pdf_path {
-                                                            let stem = pdf_path.file_stem().unwrap_or_default();
-                                                            let parent = pdf_path.parent().unwrap_or(std::path::Path::new("."));
-                                                            let synctex_path = parent.join(format!("{}.synctex.gz", stem.to_string_lossy()));
-
-                                                            if synctex_path.exists() {
-                                                                if let Ok(file) = std::fs::File::open(&synctex_path) {
-                                                                    let mut decoder = GzDecoder::new(file);
-                                                                    let mut content = String::new();
-                                                                    if decoder.read_to_string(&mut content).is_ok() {
-                                                                        // Manual Inverse Search
-                                                                        let target_page = page_idx + 1;
-                                                                        let mut current_page = 0;
-                                                                        let mut best_dist = f32::MAX;
-                                                                        let mut best_line = 0;
-                                                                        let target_y_sp = y_pt * 65536.0;
-
-                                                                        for line in content.lines() {
-                                                                            if line.starts_with('{') {
-                                                                                if let Ok(p) = line[1..].parse::<usize>() { current_page = p; }
-                                                                            } else if current_page == target_page {
-                                                                                let first = line.chars().next().unwrap_or(' ');
-                                                                                if "xkgvh".contains(first) {
-                                                                                    if let Some(colon) = line.find(':') {
-                                                                                        let parts: Vec<&str> = line[1..colon].split(',').collect();
-                                                                                        if parts.len() >= 2 {
-                                                                                            if let Ok(rec_line) = parts[1].parse::<usize>() {
-                                                                                                let coords: Vec<&str> = line[colon+1..].split(',').collect();
-                                                                                                if coords.len() >= 2 {
-                                                                                                    if let Ok(v_sp) = coords[1].parse::<f32>() {
-                                                                                                        let dist = (v_sp - target_y_sp).abs();
-                                                                                                        if dist < best_dist && dist < 65536.0 * 50.0 { // Increased tolerance
-                                                                                                            best_dist = dist;
-                                                                                                            best_line = rec_line;
-                                                                                                        }
-                                                                                                    }
-                                                                                                }
-                                                                                            }
-                                                                                        }
-                                                                                    }
-                                                                                }
-                                                                            }
-
-                                                                            if best_line > 0 {
-                                                                                let line = best_line - 1;
-                                                                                // Calculate accurate char index (handling UTF-8 and mixed newlines)
-                                                                                let char_idx = if line == 0 { 0 } else {
-                                                                                    self.editor_content.chars()
-                                                                                        .enumerate()
-                                                                                        .filter(|&(_, c)| c == '\n')
-                                                                                        .nth(line - 1)
-                                                                                        .map(|(i, _)| i + 1)
-                                                                                        .unwrap_or(0)
-                                                                                };
-
-                                                                                if let Some(mut state) = egui::TextEdit::load_state(ctx, egui::Id::new("main_editor")) {
-                                                                                        // Calculate line length for selection
-                                                                                        let line_len = self.editor_content.chars().skip(char_idx).take_while(|&c| c != '\n').count();
-
-                                                                                        state.cursor.set_char_range(Some(egui::text::CCursorRange::two(
-                                                                                            egui::text::CCursor::new(char_idx),
-                                                                                            egui::text::CCursor::new(char_idx + line_len)
-                                                                                        )));
-                                                                                        state.store(ctx, egui::Id::new("main_editor"));
-
-                                                                                        // Force focus to editor so it scrolls and shows cursor
-                                                                                        ctx.memory_mut(|m| m.request_focus(egui::Id::new("main_editor")));
-                                                                                        self.pending_cursor_scroll = Some(char_idx);
-                                                                                        jumped = true;
-                                                                                }
-                                                                            }
-                                                                        }
-                                                                    }
-                                                                }
-                                                            }
-                                                        }
-                                                    }
-                                                }
-
-                                                // Fallback to naive estimation
-                                                if !jumped {
-                                                    let total_lines = self.editor_content.lines().count().max(1);
-                                                    let rel_in_page = if let Some(pos) = img_resp.interact_pointer_pos() {
-                                                        ((pos.y - img_resp.rect.min.y) / img_resp.rect.height().max(1.0)).clamp(0.0, 1.0)
-                                                    } else {
-                                                        0.5
-                                                    };
-                                                    let rel = (page_idx as f32 + rel_in_page) / (self.page_count as f32);
-                                                    let target_line = ((rel * total_lines as f32).round() as usize).min(total_lines.saturating_sub(1));
-                                                    if let Some(mut state) = egui::TextEdit::load_state(ctx, egui::Id::new("main_editor")) {
-                                                        let char_idx = self.editor_content.lines().take(target_line).map(|l| l.len() + 1).sum::<usize>();
-                                                        let line_len = self.editor_content.lines().nth(target_line).map(|l| l.len()).unwrap_or(0);
-                                                        state.cursor.set_char_range(Some(egui::text::CCursorRange::two(egui::text::CCursor::new(char_idx), egui::text::CCursor::new(char_idx + line_len))));
-                                                        state.store(ctx, egui::Id::new("main_editor"));
-                                                    }
-                                                }
+                                        // Fallback to naive estimation
+                                        if !jumped {
+                                            let total_lines = self.editor_content.lines().count().max(1);
+                                            let rel_in_page = if let Some(pos) = img_resp.interact_pointer_pos() {
+                                                ((pos.y - img_resp.rect.min.y) / img_resp.rect.height().max(1.0)).clamp(0.0, 1.0)
+                                            } else {
+                                                0.5
+                                            };
+                                            let rel = (page_idx as f32 + rel_in_page) / (self.page_count as f32);
+                                            let target_line = ((rel * total_lines as f32).round() as usize).min(total_lines.saturating_sub(1));
+                                            if let Some(mut state) = egui::TextEdit::load_state(ctx, egui::Id::new("main_editor")) {
+                                                let char_idx = self.editor_content.line_to_char(target_line);
+                                                let line_len = self.editor_content.line(target_line).chars().count();
+                                                state.cursor.set_char_range(Some(egui::text::CCursorRange::two(egui::text::CCursor::new(char_idx), egui::text::CCursor::new(char_idx + line_len))));
+                                                state.store(ctx, egui::Id::new("main_editor"));
                                             }
                                         }
+                                    }
 
-                                        // Hover magnifier (full-page lens)
-                                        if self.magnifier_enabled && img_resp.hovered() {
-                                            if let Some(pos) = ctx.input(|i| i.pointer.hover_pos()) {
-                                                let lens = Vec2::new(self.magnifier_size, self.magnifier_size / aspect.max(0.1));
-                                                let rect = img_resp.rect;
-                                                let rel = (pos - rect.min) / rect.size();
-                                                let half_u = (lens.x / (display_width * self.magnifier_zoom)).min(0.5);
-                                                let half_v = (lens.y / (display_height * self.magnifier_zoom)).min(0.5);
-                                                let center_u = rel.x.clamp(0.0, 1.0);
-                                                let center_v = rel.y.clamp(0.0, 1.0);
-                                                let uv_min = egui::pos2((center_u - half_u).clamp(0.0, 1.0), (center_v - half_v).clamp(0.0, 1.0));
-                                                let uv_max = egui::pos2((center_u + half_u).clamp(0.0, 1.0), (center_v + half_v).clamp(0.0, 1.0));
-                                                egui::Area::new(egui::Id::new(format!("magnifier_{}", page_idx)))
-                                                    .order(egui::Order::Tooltip)
-                                                    .fixed_pos(pos + Vec2::new(12.0, 12.0))
-                                                    .show(ctx, |ui| {
-                                                        ui.set_min_size(lens);
-                                                        ui.set_max_size(lens);
-                                                        ui.add(egui::Image::new((tex.id(), lens * self.magnifier_zoom)).uv(egui::Rect::from_min_max(uv_min, uv_max)));
-                                                    });
-                                            }
+                                    // Hover magnifier (full-page lens)
+                                    if self.magnifier_enabled && img_resp.hovered() {
+                                        let tex_id = self.page_cache.get(page_idx).map(|tex| tex.id());
+                                        if let (Some(pos), Some(tex_id)) = (ctx.input(|i| i.pointer.hover_pos()), tex_id) {
+                                            let lens = Vec2::new(self.magnifier_size, self.magnifier_size / aspect.max(0.1));
+                                            let rect = img_resp.rect;
+                                            let rel = (pos - rect.min) / rect.size();
+                                            let half_u = (lens.x / (display_width * self.magnifier_zoom)).min(0.5);
+                                            let half_v = (lens.y / (display_height * self.magnifier_zoom)).min(0.5);
+                                            let center_u = rel.x.clamp(0.0, 1.0);
+                                            let center_v = rel.y.clamp(0.0, 1.0);
+                                            let uv_min = egui::pos2((center_u - half_u).clamp(0.0, 1.0), (center_v - half_v).clamp(0.0, 1.0));
+                                            let uv_max = egui::pos2((center_u + half_u).clamp(0.0, 1.0), (center_v + half_v).clamp(0.0, 1.0));
+                                            egui::Area::new(egui::Id::new(format!("magnifier_{}", page_idx)))
+                                                .order(egui::Order::Tooltip)
+                                                .fixed_pos(pos + Vec2::new(12.0, 12.0))
+                                                .show(ctx, |ui| {
+                                                    ui.set_min_size(lens);
+                                                    ui.set_max_size(lens);
+                                                    ui.add(egui::Image::new((tex_id, lens * self.magnifier_zoom)).uv(egui::Rect::from_min_max(uv_min, uv_max)));
+                                                });
                                         }
-
-                                        ui.add_space(10.0);
                                     }
+
+                                    ui.add_space(10.0);
                                 }
+                            } else {
+                                ui.label(
+                                    egui::RichText::new(&self.preview_status)
+                                        .color(theme.text_secondary),
+                                );
                             }
-                        } else {
-                            ui.label(
-                                egui::RichText::new(&self.preview_status)
-                                    .color(theme.text_secondary),
-                            );
-                        }
+                        });
                     });
-                });
 
                 ui.separator();
 
@@ -3662,15 +4689,33 @@ impl eframe::App for TypesafeApp {
                         .stick_to_bottom(true)
                         .show(ui, |ui| {
                             if !self.diagnostics.is_empty() {
-                                    ui.label(egui::RichText::new("Diagnostics (Click to Jump):").strong().color(egui::Color32::from_rgb(255, 100, 100)));
-                                    for diag in &self.diagnostics {
-                                        if ui.link(format!("Line {}: {}", diag.line, diag.message)).clicked() {
-                                            let char_idx = self.editor_content.lines().take(diag.line.saturating_sub(1)).map(|l| l.len() + 1).sum::<usize>();
-                                            if let Some(mut state) = egui::TextEdit::load_state(ctx, egui::Id::new("main_editor")) {
-                                                state.cursor.set_char_range(Some(egui::text::CCursorRange::one(egui::text::CCursor::new(char_idx))));
-                                                state.store(ctx, egui::Id::new("main_editor"));
+                                    ui.horizontal(|ui| {
+                                        ui.label(egui::RichText::new("Diagnostics (Click to Jump):").strong().color(egui::Color32::from_rgb(255, 100, 100)));
+                                        for &severity in Severity::all() {
+                                            let count = self.diagnostics.iter().filter(|d| d.severity == severity).count();
+                                            let mut shown = self.diagnostic_filter.contains(&severity);
+                                            if ui.toggle_value(&mut shown, format!("{} {}", count, severity_title(severity))).changed() {
+                                                if shown {
+                                                    self.diagnostic_filter.insert(severity);
+                                                } else {
+                                                    self.diagnostic_filter.remove(&severity);
+                                                }
                                             }
                                         }
+                                    });
+
+                                    let mut jump = None;
+                                    for diag in self.diagnostics.iter().filter(|d| self.diagnostic_filter.contains(&d.severity)) {
+                                        let file_name = std::path::Path::new(&diag.file).file_name().unwrap_or_default().to_string_lossy();
+                                        let location = if diag.line > 0 { format!("{}:{}", file_name, diag.line) } else { file_name.to_string() };
+                                        let text = egui::RichText::new(format!("{} {}: {}", severity_icon(diag.severity), location, diag.message))
+                                            .color(severity_color(diag.severity));
+                                        if ui.link(text).on_hover_text(&diag.file).clicked() {
+                                            jump = Some((diag.file.clone(), diag.line, diag.column));
+                                        }
+                                    }
+                                    if let Some((file, line, column)) = jump {
+                                        self.jump_to_location(ctx, &file, line, column);
                                     }
                                     ui.separator();
                             }
@@ -3699,6 +4744,10 @@ impl eframe::App for TypesafeApp {
         // Run debounced checks
         let now = ctx.input(|i| i.time);
         if self.checks_dirty && now - self.last_edit_time > 0.5 {
+            // A `% !TEX spellcheck` line may have changed, or this tab uses another language
+            if self.spelling_language() != self.dictionary_language {
+                self.refresh_dictionary();
+            }
             self.cached_syntax_errors = self.check_syntax(&self.editor_content);
             self.cached_spell_errors = self.check_spelling(&self.editor_content);
             self.checks_dirty = false;
@@ -3708,6 +4757,41 @@ impl eframe::App for TypesafeApp {
 
         // ====== CENTRAL PANEL (EDITOR OR PDF VIEWER) ======
         egui::CentralPanel::default().show(ctx, |ui| {
+            // Tab bar
+            let mut select_tab = None;
+            let mut close_tab = None;
+            egui::ScrollArea::horizontal().id_source("editor_tabs").show(ui, |ui| {
+                ui.horizontal(|ui| {
+                    ui.spacing_mut().item_spacing.x = 2.0;
+                    for (i, buffer) in self.buffers.iter().enumerate() {
+                        let active = i == self.active_buffer;
+                        let (path, dirty) = if active { (&self.file_path, self.is_dirty) } else { (&buffer.path, buffer.is_dirty) };
+                        let name = std::path::Path::new(path).file_name().unwrap_or_default().to_string_lossy().to_string();
+                        let title = if dirty { format!("{} ●", name) } else { name };
+                        let color = if active { theme.text_primary } else { theme.text_secondary };
+
+                        let resp = ui.selectable_label(active, egui::RichText::new(title).color(color)).on_hover_text(path.as_str());
+                        if resp.clicked() {
+                            select_tab = Some(i);
+                        }
+                        if resp.middle_clicked() {
+                            close_tab = Some(i);
+                        }
+                        if ui.add(egui::Button::new(egui::RichText::new("×").color(theme.text_secondary)).frame(false)).on_hover_text("Close").clicked() {
+                            close_tab = Some(i);
+                        }
+                        ui.add_space(6.0);
+                    }
+                });
+            });
+            if let Some(i) = select_tab {
+                self.switch_to_buffer(ctx, i);
+            }
+            if let Some(i) = close_tab {
+                self.close_buffer(ctx, i);
+            }
+            ui.separator();
+
             // Show different content based on file type
             match self.current_file_type {
                 CurrentFileType::Pdf => {
@@ -3715,7 +4799,6 @@ impl eframe::App for TypesafeApp {
                     ui.horizontal(|ui| {
                         if ui.add(egui::Button::new("📤").frame(false)).on_hover_text("Pop-out (Ctrl+Shift+P)").clicked() {
                             self.show_pdf_popup = true;
-                            self.pdf_textures.clear();
                         }
                         ui.label(
                             egui::RichText::new("PDF VIEWER")
@@ -3733,7 +4816,6 @@ impl eframe::App for TypesafeApp {
                             );
                             if ui.button("Return to Main Window").clicked() {
                                 self.show_pdf_popup = false;
-                                self.pdf_textures.clear();
                             }
                         });
                         return;
@@ -3782,28 +4864,22 @@ impl eframe::App for TypesafeApp {
 
                         if (zoom - self.zoom).abs() > 0.001 {
                             self.zoom = zoom;
-                            self.pdf_textures.clear();
                         }
 
                         if ui.button("⟳").on_hover_text("Reset Zoom").clicked() {
                             self.zoom = 1.0;
                             self.fit_mode = PdfFitMode::Normal;
-                            self.pdf_textures.clear();
                         }
 
                         ui.separator();
 
                         if ui.button("↔").on_hover_text("Fit Width").clicked() {
                             self.fit_mode = PdfFitMode::FitWidth;
-                            self.pdf_textures.clear();
                         }
                         if ui.button("⬚").on_hover_text("Fit Page").clicked() {
                             self.fit_mode = PdfFitMode::FitPage;
-                            self.pdf_textures.clear();
-                        }
-                        if ui.toggle_value(&mut self.pdf_multi_page_view, "::").on_hover_text("Grid View").clicked() {
-                             self.pdf_textures.clear();
                         }
+                        ui.toggle_value(&mut self.pdf_multi_page_view, "::").on_hover_text("Grid View");
 
                         ui.separator();
 
@@ -3813,14 +4889,7 @@ impl eframe::App for TypesafeApp {
                     });
 
                     if self.show_pdf_search {
-                        ui.horizontal(|ui| {
-                            ui.label("Search:");
-                            ui.text_edit_singleline(&mut self.pdf_search_query);
-                            if ui.button("✖").clicked() {
-                                self.show_pdf_search = false;
-                                self.pdf_search_query.clear();
-                            }
-                        });
+                        self.show_pdf_search_bar(ui, &theme);
                         ui.separator();
                     }
 
@@ -3837,7 +4906,6 @@ impl eframe::App for TypesafeApp {
                             new_zoom *= pinch_zoom;
                         }
                         self.zoom = new_zoom.clamp(0.3, 4.0);
-                        self.pdf_textures.clear();
                     }
 
                     egui::ScrollArea::both()
@@ -3849,35 +4917,19 @@ impl eframe::App for TypesafeApp {
                                          ui.spacing_mut().item_spacing = egui::vec2(10.0, 10.0);
 
                                          for page_idx in 0..self.page_count {
-                                             self.render_page(ctx, page_idx);
-                                             if let Some(tex) = self.pdf_textures.get(&page_idx) {
-                                                 let (pw, ph) = self.page_sizes.get(&page_idx).copied().unwrap_or((595.0, 842.0));
-                                                 let aspect = pw / ph;
-                                                 let display_width = pw * self.zoom;
-                                                 let display_height = display_width / aspect;
-
-                                                 ui.add(egui::Image::new((
-                                                     tex.id(),
-                                                     Vec2::new(display_width, display_height),
-                                                 )).sense(egui::Sense::click()));
-                                             }
+                                             let img_resp = self.show_pdf_page(ui, PdfViewer::Preview, page_idx, self.zoom);
+                                             self.paint_pdf_search_highlights(ui, page_idx, img_resp.rect, &theme);
+                                             self.paint_sync_highlight(ui, page_idx, img_resp.rect, &theme);
+                                             scroll_to_pdf_target(ui, &mut self.pending_scroll_target, page_idx, img_resp.rect);
                                          }
                                      });
                                  } else {
                                      ui.vertical_centered(|ui| {
                                          for page_idx in 0..self.page_count {
-                                             self.render_page(ctx, page_idx);
-                                             if let Some(tex) = self.pdf_textures.get(&page_idx) {
-                                                 let (pw, ph) = self.page_sizes.get(&page_idx).copied().unwrap_or((595.0, 842.0));
-                                                 let aspect = pw / ph;
-                                                 let display_width = pw * self.zoom;
-                                                 let display_height = display_width / aspect;
-
-                                                 ui.add(egui::Image::new((
-                                                     tex.id(),
-                                                     Vec2::new(display_width, display_height),
-                                                 )).sense(egui::Sense::click()));
-                                             }
+                                             let img_resp = self.show_pdf_page(ui, PdfViewer::Preview, page_idx, self.zoom);
+                                             self.paint_pdf_search_highlights(ui, page_idx, img_resp.rect, &theme);
+                                             self.paint_sync_highlight(ui, page_idx, img_resp.rect, &theme);
+                                             scroll_to_pdf_target(ui, &mut self.pending_scroll_target, page_idx, img_resp.rect);
                                          }
                                      });
                                  }
@@ -3921,10 +4973,13 @@ impl eframe::App for TypesafeApp {
                     if ui.button("▶ Build").clicked() {
                         self.compile(ctx);
                     }
+                    if self.build_manager.is_running() && ui.button("■ Stop").on_hover_text("Stop the running build").clicked() {
+                        self.build_manager.cancel();
+                    }
                     ui.add_space(8.0);
                     if ui.button("💾 Save").clicked() {
                          if !self.file_path.is_empty() && self.file_path != "untitled.tex" {
-                            if let Err(e) = std::fs::write(&self.file_path, &self.editor_content) {
+                            if let Err(e) = std::fs::write(&self.file_path, self.editor_content.as_str()) {
                                 self.compilation_log = format!("Error saving: {}\n", e);
                             } else {
                                 self.is_dirty = false;
@@ -3972,37 +5027,8 @@ impl eframe::App for TypesafeApp {
                 self.search_match_index = 0;
                 self.checks_dirty = true;
                 // Perform search
-                self.search_matches.clear();
-                        if !self.search_query.is_empty() {
-                            let text = &self.editor_content;
-                            let query = &self.search_query;
-                            let matches = if self.search_case_sensitive {
-                                text.match_indices(query).map(|(i, _)| i).collect::<Vec<_>>()
-                            } else {
-                                text.to_lowercase().match_indices(&query.to_lowercase()).map(|(i, _)| i).collect::<Vec<_>>()
-                            };
-
-                            for start_byte in matches {
-                                // Filter whole word if needed
-                                let mut valid = true;
-                                if self.search_whole_word {
-                                    let is_start_ok = start_byte == 0 || !text[..start_byte].chars().last().unwrap_or(' ').is_alphanumeric();
-                                    let end_byte = start_byte + query.len();
-                                    let is_end_ok = end_byte == text.len() || !text[end_byte..].chars().next().unwrap_or(' ').is_alphanumeric();
-                                    if !is_start_ok || !is_end_ok {
-                                        valid = false;
-                                    }
-                                }
-
-                                if valid {
-                                    // Convert byte index to char index for TextEdit
-                                    let char_idx = text[..start_byte].chars().count();
-                                    let len_chars = text[start_byte..start_byte + query.len()].chars().count();
-                                    self.search_matches.push((char_idx, char_idx + len_chars));
-                                }
-                            }
-                        }
-                    }
+                self.search_matches = self.find_matches();
+            }
 
                     // Replace Input
                     ui.add(egui::TextEdit::singleline(&mut self.replace_query).desired_width(120.0).hint_text("Replace..."));
@@ -4055,12 +5081,8 @@ impl eframe::App for TypesafeApp {
                     // Replace Actions
                     if ui.button("Replace").clicked() && match_count > 0 {
                         if let Some(&(start_char, end_char)) = self.search_matches.get(self.search_match_index) {
-                             // Convert chars to bytes
-                             let start_byte = self.editor_content.chars().take(start_char).map(|c| c.len_utf8()).sum::<usize>();
-                             let end_byte = self.editor_content.chars().take(end_char).map(|c| c.len_utf8()).sum::<usize>();
-
-                             if start_byte < self.editor_content.len() && end_byte <= self.editor_content.len() {
-                                 self.editor_content.replace_range(start_byte..end_byte, &self.replace_query);
+                             if end_char <= self.editor_content.len_chars() {
+                                 self.editor_content.transact(|tx| tx.replace(start_char..end_char, &self.replace_query));
                                  self.is_dirty = true;
                                  self.search_matches.clear(); // Clear to force refresh
                              }
@@ -4068,40 +5090,13 @@ impl eframe::App for TypesafeApp {
                     }
 
                     if ui.button("All").on_hover_text("Replace All").clicked() && match_count > 0 {
-                         let from = &self.search_query;
-                         let to = &self.replace_query;
-
-                         if !self.search_whole_word && self.search_case_sensitive {
-                             self.editor_content = self.editor_content.replace(from, to);
-                         } else if !self.search_whole_word && !self.search_case_sensitive {
-                             let lower_content = self.editor_content.to_lowercase();
-                             let lower_from = from.to_lowercase();
-                             let mut result = String::new();
-                             let mut last_end = 0;
-                             for (start, part) in lower_content.match_indices(&lower_from) {
-                                 result.push_str(&self.editor_content[last_end..start]);
-                                 result.push_str(to);
-                                 last_end = start + part.len();
-                             }
-                             result.push_str(&self.editor_content[last_end..]);
-                             self.editor_content = result;
-                         } else {
-                             // Whole word replace all strategy
-                             let mut byte_matches = Vec::new();
-                             let mut char_indices = self.editor_content.char_indices().map(|(b, _)| b).collect::<Vec<_>>();
-                             char_indices.push(self.editor_content.len());
-
-                             for &(start_char, end_char) in &self.search_matches {
-                                 if let (Some(&start_byte), Some(&end_byte)) = (char_indices.get(start_char), char_indices.get(end_char)) {
-                                     byte_matches.push((start_byte, end_byte));
-                                 }
-                             }
-
-                             byte_matches.reverse();
-                             for (start, end) in byte_matches {
-                                 self.editor_content.replace_range(start..end, to);
+                         // Matches are looked up afresh and replaced from the end, so earlier positions stay valid
+                         let matches = self.find_matches();
+                         self.editor_content.transact(|tx| {
+                             for &(start, end) in matches.iter().rev() {
+                                 tx.replace(start..end, &self.replace_query);
                              }
-                         }
+                         });
 
                          self.is_dirty = true;
                          self.search_matches.clear();
@@ -4115,22 +5110,36 @@ impl eframe::App for TypesafeApp {
                 ui.separator();
             }
 
-            let mut text = self.editor_content.clone();
             let theme_clone = theme;
             let editor_id = egui::Id::new("main_editor");
 
+            // Undo and redo go to the document's history rather than the TextEdit's own
+            if ctx.memory(|m| m.has_focus(editor_id)) {
+                let (undo, redo) = ctx.input_mut(|i| {
+                    let redo = i.consume_key(egui::Modifiers::SHIFT | egui::Modifiers::COMMAND, egui::Key::Z)
+                        || i.consume_key(egui::Modifiers::COMMAND, egui::Key::Y);
+                    (i.consume_key(egui::Modifiers::COMMAND, egui::Key::Z), redo)
+                });
+                if redo {
+                    self.redo(ctx);
+                } else if undo {
+                    self.undo(ctx);
+                }
+            }
+
             // Smart Indentation (Pre-process)
             if ctx.memory(|m| m.has_focus(editor_id)) && ctx.input(|i| i.key_pressed(egui::Key::Enter) && i.modifiers.is_none()) {
                 ctx.input_mut(|i| i.consume_key(egui::Modifiers::NONE, egui::Key::Enter));
 
                 if let Some(mut state) = egui::TextEdit::load_state(ctx, editor_id) {
                     if let Some(range) = state.cursor.char_range() {
-                        let idx = range.primary.index;
+                        let end = self.editor_content.len_chars();
+                        let selection = range.primary.index.min(range.secondary.index).min(end)..range.primary.index.max(range.secondary.index).min(end);
+                        let idx = selection.start;
 
                         // Identify indentation of current line
-                        let text_before = &self.editor_content[..idx];
-                        let line_start = text_before.rfind('\n').map(|i| i + 1).unwrap_or(0);
-                        let current_line_prefix = &self.editor_content[line_start..idx];
+                        let line_start = self.editor_content.line_to_char(self.editor_content.char_to_line(idx));
+                        let current_line_prefix = self.editor_content.slice(line_start..idx);
                         let indent: String = current_line_prefix.chars().take_while(|c| c.is_whitespace()).collect();
 
                         let mut next_indent = indent.clone();
@@ -4144,10 +5153,9 @@ impl eframe::App for TypesafeApp {
                         }
 
                         let to_insert = format!("\n{}", next_indent);
-                        self.editor_content.insert_str(idx, &to_insert);
-                        text = self.editor_content.clone(); // Update local text for editor
+                        self.editor_content.transact(|tx| tx.replace(selection, &to_insert));
 
-                        let new_cursor = idx + to_insert.len();
+                        let new_cursor = idx + to_insert.chars().count();
                         state.cursor.set_char_range(Some(CCursorRange::one(CCursor::new(new_cursor))));
                         state.store(ctx, editor_id);
                         self.is_dirty = true;
@@ -4159,10 +5167,9 @@ impl eframe::App for TypesafeApp {
             if ctx.input(|i| i.key_pressed(egui::Key::J) && i.modifiers.ctrl) {
                 if let Some(state) = egui::TextEdit::load_state(ctx, egui::Id::new("main_editor")) {
                     if let Some(range) = state.cursor.char_range() {
-                        let idx = range.primary.index;
-                        let line_num = self.editor_content[..idx].chars().filter(|&c| c == '\n').count() + 1;
+                        let line_num = self.editor_content.char_to_line(range.primary.index) + 1;
 
-                        self.sync_forward_search(line_num);
+                        self.sync_forward_search(ctx, line_num);
                     }
                 }
             }
@@ -4189,10 +5196,9 @@ impl eframe::App for TypesafeApp {
                         i.consume_key(egui::Modifiers::CTRL, egui::Key::Space);
                     });
                     if let Some((_, completion)) = self.completion_suggestions.get(self.completion_selected_index).cloned() {
-                        self.apply_completion(ctx, &mut text, &completion);
+                        self.apply_completion(ctx, &completion);
                         self.show_completions = false;
                         self.completion_suggestions.clear();
-                        self.editor_content = text.clone();
                     }
                 }
                 if ctx.input(|i| i.key_pressed(egui::Key::Escape)) {
@@ -4205,40 +5211,63 @@ impl eframe::App for TypesafeApp {
                         if let Some(range) = state.cursor.char_range() {
                             let cursor_idx = range.primary.index;
                             // Search for next $digit placeholder
-                            if let Some(rel_idx) = text[cursor_idx..].find('$') {
-                                let target_idx = cursor_idx + rel_idx;
-                                if target_idx + 1 < text.len() {
-                                    let next_char = text.chars().nth(target_idx + 1).unwrap_or(' ');
-                                    if next_char.is_digit(10) {
-                                        // Found a placeholder (e.g., $2)
-                                        ctx.input_mut(|i| i.consume_key(egui::Modifiers::NONE, egui::Key::Tab));
-
-                                        // Remove the marker
-                                        text.replace_range(target_idx..target_idx+2, "");
-
-                                        // Move cursor to the placeholder position
-                                        state.cursor.set_char_range(Some(egui::text::CCursorRange::one(egui::text::CCursor::new(target_idx))));
-                                        state.store(ctx, egui::Id::new("main_editor"));
-
-                                        // Update content immediately
-                                        self.editor_content = text.clone();
-                                        self.is_dirty = true;
-                                    }
-                                }
+                            let after = self.editor_content.slice(cursor_idx..self.editor_content.len_chars());
+                            let placeholder = after.find('$').filter(|&b| after[b + 1..].starts_with(|c: char| c.is_ascii_digit()));
+                            if let Some(rel_byte) = placeholder {
+                                let target_idx = cursor_idx + after[..rel_byte].chars().count();
+                                // Found a placeholder (e.g., $2)
+                                ctx.input_mut(|i| i.consume_key(egui::Modifiers::NONE, egui::Key::Tab));
+
+                                // Remove the marker
+                                self.editor_content.transact(|tx| tx.delete(target_idx..target_idx + 2));
+
+                                // Move cursor to the placeholder position
+                                state.cursor.set_char_range(Some(egui::text::CCursorRange::one(egui::text::CCursor::new(target_idx))));
+                                state.store(ctx, egui::Id::new("main_editor"));
+                                self.is_dirty = true;
                             }
                         }
                     }
                 }
             }
 
-            let gutter_width = 30.0;
+            // Handle Tab key: indent with spaces rather than the TextEdit's tab character
+            if ctx.memory(|m| m.has_focus(editor_id)) && ctx.input(|i| i.key_pressed(egui::Key::Tab) && i.modifiers.is_none()) {
+                ctx.input_mut(|i| i.consume_key(egui::Modifiers::NONE, egui::Key::Tab));
+                if let Some(mut state) = egui::TextEdit::load_state(ctx, editor_id) {
+                    if let Some(range) = state.cursor.char_range() {
+                        let end = self.editor_content.len_chars();
+                        let start = range.primary.index.min(range.secondary.index).min(end);
+                        let stop = range.primary.index.max(range.secondary.index).min(end);
+                        self.editor_content.transact(|tx| tx.replace(start..stop, INDENT_UNIT));
+                        let new_cursor = start + INDENT_UNIT.chars().count();
+                        state.cursor.set_char_range(Some(CCursorRange::one(CCursor::new(new_cursor))));
+                        state.store(ctx, editor_id);
+                        self.is_dirty = true;
+                    }
+                }
+            }
+
+            // Folded lines are shown again when the cursor moves into them
+            if self.current_file_type == CurrentFileType::Tex {
+                if let Some(range) = egui::TextEdit::load_state(ctx, editor_id).and_then(|s| s.cursor.char_range()) {
+                    self.folds.reveal(self.editor_content.char_to_line(range.primary.index));
+                }
+            }
+
+            let gutter_width = 40.0;
             let scroll_target = self.pending_cursor_scroll;
             if self.pending_cursor_scroll.is_some() {
                 self.pending_cursor_scroll = None;
             }
 
-            egui::ScrollArea::vertical()
-                .id_source("editor_scroll")
+            let mut editor_scroll = egui::ScrollArea::vertical().id_source("editor_scroll");
+            if let Some(offset) = self.pending_editor_scroll.take() {
+                editor_scroll = editor_scroll.vertical_scroll_offset(offset);
+            }
+            // The document is lent to the TextEdit for the frame, while the layouter borrows the rest of the app
+            let mut text = std::mem::take(&mut self.editor_content);
+            let scroll_output = editor_scroll
                 .show(ui, |ui| {
                     let mut layouter = |ui: &egui::Ui, string: &str, wrap_width: f32| {
                         let mut layout_job = self.syntax_highlighting(&theme_clone, string);
@@ -4267,6 +5296,7 @@ impl eframe::App for TypesafeApp {
                             }
                             out
                         });
+                    self.editor_content = std::mem::take(&mut text);
 
                     let response = output.inner.response;
 
@@ -4390,7 +5420,7 @@ impl eframe::App for TypesafeApp {
                                     let start_byte = range.start;
                                     let line_num = self.editor_content[..start_byte].chars().filter(|&c| c == '\n').count() + 1;
 
-                                    self.sync_forward_search(line_num);
+                                    self.sync_forward_search(ctx, line_num);
                                 }
                                 ui.close_menu();
                             }
@@ -4443,13 +5473,14 @@ impl eframe::App for TypesafeApp {
                     if let Some(text) = replacement {
                         if let Some(range) = selected_range {
                              if range.start < self.editor_content.len() && range.end <= self.editor_content.len() {
-                                 self.editor_content.replace_range(range, &text);
+                                 let chars = self.editor_content.byte_to_char(range.start)..self.editor_content.byte_to_char(range.end);
+                                 self.editor_content.transact(|tx| tx.replace(chars, &text));
                                  self.is_dirty = true;
                              }
                         }
                     }
 
-                    // Paint line numbers
+                    // Paint line numbers and fold toggles
                             {
                                 let galley = output.inner.galley.clone();
                                 let painter = ui.painter();
@@ -4462,27 +5493,60 @@ impl eframe::App for TypesafeApp {
                                 );
                                 painter.rect_filled(gutter_rect, 0.0, theme_clone.bg_tertiary);
 
+                                let hidden = Intervals::new(self.folds.hidden_lines());
+                                let mut toggled = None;
                                 let mut logical_line = 1;
                                 let mut start_new_line = true;
 
                                 for row in &galley.rows {
-                                     if start_new_line {
+                                     let line = logical_line - 1;
+                                     if start_new_line && !hidden.contains(line) {
                                          let pos = min_pos + egui::vec2(0.0, row.rect.min.y - galley.rect.min.y);
                                          painter.text(
-                                             pos + egui::vec2(gutter_width - 4.0, 0.0),
+                                             pos + egui::vec2(gutter_width - 14.0, 0.0),
                                              egui::Align2::RIGHT_TOP,
                                              logical_line.to_string(),
                                              egui::FontId::monospace(10.0),
                                              theme_clone.text_secondary,
                                          );
+
+                                         if self.folds.region_at(line).is_some() {
+                                             let rect = egui::Rect::from_min_size(pos + egui::vec2(gutter_width - 13.0, 0.0), egui::vec2(12.0, row.rect.height()));
+                                             let toggle = ui.interact(rect, editor_id.with(("fold", line)), egui::Sense::click());
+                                             let folded = self.folds.is_folded(line);
+                                             painter.text(
+                                                 rect.center(),
+                                                 egui::Align2::CENTER_CENTER,
+                                                 if folded { "▶" } else { "▼" },
+                                                 egui::FontId::proportional(8.0),
+                                                 if toggle.hovered() { theme_clone.text_primary } else { theme_clone.text_secondary },
+                                             );
+                                             if toggle.clicked() {
+                                                 toggled = Some(line);
+                                             }
+                                             // Stands in for the hidden lines after the heading
+                                             if folded {
+                                                 painter.text(
+                                                     output.inner.galley_pos + egui::vec2(row.rect.max.x + 6.0, row.rect.min.y),
+                                                     egui::Align2::LEFT_TOP,
+                                                     "…",
+                                                     TextStyle::Monospace.resolve(ui.style()),
+                                                     theme_clone.accent,
+                                                 );
+                                             }
+                                         }
+                                     }
+                                     if start_new_line {
                                          logical_line += 1;
                                      }
                                      start_new_line = row.ends_with_newline;
                                 }
+                                if let Some(line) = toggled {
+                                    self.toggle_fold(ctx, line);
+                                }
                             }
 
                             if response.changed() {
-                                self.editor_content = text.clone();
                                 self.is_dirty = true;
                                 self.last_edit_time = ctx.input(|i| i.time);
                                 self.checks_dirty = true;
@@ -4492,7 +5556,7 @@ impl eframe::App for TypesafeApp {
                         if let Some(state) = egui::TextEdit::load_state(ctx, editor_id) {
                             if let Some(range) = state.cursor.char_range() {
                                 let idx = range.primary.index;
-                                let text_slice = &self.editor_content[..idx];
+                                let text_slice = self.editor_content.slice(0..idx);
 
                                 self.show_completions = false;
                                 self.completion_suggestions.clear();
@@ -4566,53 +5630,6 @@ impl eframe::App for TypesafeApp {
 
 
 
-                    // Disable old logic block (preserves file structure if block was larger)
-                    if false && response.has_focus() && ctx.input(|i| i.key_pressed(egui::Key::Enter) && i.modifiers.is_none()) {
-                        ctx.input_mut(|i| i.consume_key(egui::Modifiers::NONE, egui::Key::Enter));
-
-                        if let Some(mut state) = egui::TextEdit::load_state(ctx, editor_id) {
-                            if let Some(range) = state.cursor.char_range() {
-                                let cursor_idx = range.primary.index;
-                                let prefix = &text[..cursor_idx];
-                                let last_line_start = prefix.rfind('\n').map(|i| i + 1).unwrap_or(0);
-                                let last_line = &prefix[last_line_start..];
-
-                                // Calculate base indent
-                                let mut indent = last_line.chars().take_while(|c| c.is_whitespace()).collect::<String>();
-                                let trimmed = last_line.trim_end();
-                                let content = trimmed.trim_start();
-
-                                // Look ahead to decide if we should dedent
-                                let after = &text[cursor_idx..];
-                                let after_trimmed = after.trim_start();
-                                let next_char = after_trimmed.chars().next();
-                                let dedent_next = next_char == Some('}') || next_char == Some(']') || after_trimmed.starts_with("\\end{");
-
-                                if dedent_next && indent.len() >= INDENT_UNIT.len() {
-                                    indent.truncate(indent.len() - INDENT_UNIT.len());
-                                }
-
-                                let mut to_insert = format!("\n{}", indent);
-
-                                // Extra indent if current line opens a block
-                                let is_env_start = content.starts_with("\\begin") && !content.starts_with("\\end");
-                                let is_structure = content.starts_with("\\section") || content.starts_with("\\subsection") || content.starts_with("\\chapter");
-                                let opens_scope = content.ends_with('{') || content.ends_with('[');
-
-                                let opens_block = is_env_start || is_structure || opens_scope;
-
-                                if opens_block && !dedent_next {
-                                    to_insert.push_str(INDENT_UNIT);
-                                }
-
-                                text.insert_str(cursor_idx, &to_insert);
-                                let new_cursor_idx = cursor_idx + to_insert.len();
-                                state.cursor.set_char_range(Some(CCursorRange::one(CCursor::new(new_cursor_idx))));
-                                state.store(ctx, editor_id);
-                            }
-                        }
-                    }
-
                     // Auto-close pairs
                     if response.has_focus() {
                         let mut pair_to_insert = None;
@@ -4637,20 +5654,12 @@ impl eframe::App for TypesafeApp {
                                     // The TextEdit has already inserted the opener.
                                     // The cursor is now AFTER the opener.
                                     let cursor_idx = range.primary.index;
-                                    text.insert_str(cursor_idx, closer);
+                                    self.editor_content.transact(|tx| tx.insert(cursor_idx, closer));
                                 }
                             }
                         }
                     }
 
-                    // Handle Tab key
-                    if response.has_focus()
-                        && ctx.input(|i| i.key_pressed(egui::Key::Tab) && i.modifiers.is_none())
-                    {
-                        ctx.input_mut(|i| i.consume_key(egui::Modifiers::NONE, egui::Key::Tab));
-                        text.insert_str(text.len(), "    ");
-                    }
-
                     // Handle Manual Completion Request (Ctrl+Space)
                     if response.has_focus() && ctx.input(|i| i.key_pressed(egui::Key::Space) && i.modifiers.ctrl) {
                         ctx.input_mut(|i| i.consume_key(egui::Modifiers::CTRL, egui::Key::Space));
@@ -4658,7 +5667,7 @@ impl eframe::App for TypesafeApp {
                         if let Some(state) = egui::TextEdit::load_state(ctx, editor_id) {
                             if let Some(range) = state.cursor.char_range() {
                                 let idx = range.primary.index;
-                                let text_slice = &text[..idx];
+                                let text_slice = self.editor_content.slice(0..idx);
 
                                 // Force completion trigger by scanning backwards for a backslash
                                 if let Some(bs_idx) = text_slice.rfind('\\') {
@@ -4689,16 +5698,13 @@ impl eframe::App for TypesafeApp {
                         }
                     }
 
-                    // Sync text changes back to editor_content
                     if response.changed() {
-                        self.editor_content = text;
                         self.is_dirty = true;
                         self.update_outline();
                         ctx.request_repaint();
-                    } else {
-                        self.editor_content = text;
                     }
                 });
+            self.editor_scroll_offset = scroll_output.state.offset.y;
         });
 
             // Show autocomplete dropdown popup
@@ -4760,9 +5766,7 @@ impl eframe::App for TypesafeApp {
                                         }
 
                                         if btn.clicked() {
-                                            let mut text = self.editor_content.clone();
-                                            self.apply_completion(ctx, &mut text, &completion);
-                                            self.editor_content = text;
+                                            self.apply_completion(ctx, completion);
                                             self.show_completions = false;
                                             self.completion_suggestions.clear();
                                             self.completion_selected_index = 0;
@@ -4787,9 +5791,7 @@ impl eframe::App for TypesafeApp {
                 if ctx.input(|i| (i.key_pressed(egui::Key::Space) && i.modifiers.ctrl) || i.key_pressed(egui::Key::Tab)) {
                     if self.completion_selected_index < self.completion_suggestions.len() {
                         let (_, completion) = self.completion_suggestions[self.completion_selected_index].clone();
-                        let mut text = self.editor_content.clone();
-                        self.apply_completion(ctx, &mut text, &completion);
-                        self.editor_content = text;
+                        self.apply_completion(ctx, &completion);
                         self.show_completions = false;
                         self.completion_suggestions.clear();
                         self.completion_selected_index = 0;
@@ -4840,9 +5842,14 @@ impl eframe::App for TypesafeApp {
 
                             let commands = vec![
                                 ("Compile Project", "Build the current project"),
+                                ("Stop Compilation", "Stop the running build"),
+                                ("Clean Auxiliary Files", "Delete .aux, .log and other build files"),
                                 ("Save File", "Save current changes"),
                                 ("Open File", "Open a file..."),
                                 ("Open Folder", "Open a folder..."),
+                                ("Close Tab", "Close the current file"),
+                                ("Fold All", "Collapse every environment, section and comment block"),
+                                ("Unfold All", "Expand all folded regions"),
                                 ("Toggle Sidebar", "Show/Hide file panel"),
                             ];
 
@@ -4872,19 +5879,21 @@ impl eframe::App for TypesafeApp {
 
                                 if btn.clicked() || (is_selected && enter_pressed) {
                                     match *name {
-                                        "Compile Project" => if !self.is_compiling { self.compile(ctx); },
+                                        "Compile Project" => self.compile(ctx),
+                                        "Stop Compilation" => { self.build_manager.cancel(); },
+                                        "Clean Auxiliary Files" => self.clean_aux_files(),
                                         "Save File" => self.save_file(ctx, true),
                                         "Open File" => {
                                             if let Some(path) = rfd::FileDialog::new().add_filter("LaTeX", &["tex"]).pick_file() {
-                                                self.file_path = path.to_string_lossy().to_string();
-                                                let p = self.file_path.clone();
-                                                self.load_file(ctx, &p);
-                                                self.update_outline();
+                                                self.open_file(ctx, &path.to_string_lossy());
                                             }
                                         },
+                                        "Close Tab" => { self.close_buffer(ctx, self.active_buffer); },
+                                        "Fold All" => self.fold_all(ctx),
+                                        "Unfold All" => self.folds.unfold_all(),
                                         "Open Folder" => {
                                             if let Some(folder) = rfd::FileDialog::new().pick_folder() {
-                                                self.current_dir = folder;
+                                                self.open_project_dir(folder);
                                             }
                                         },
                                         "Toggle Sidebar" => self.show_file_panel = !self.show_file_panel,
@@ -4909,6 +5918,30 @@ impl eframe::App for TypesafeApp {
 
 // Helper Functions
 
+fn severity_title(severity: Severity) -> &'static str {
+    match severity {
+        Severity::Error => "Errors",
+        Severity::Warning => "Warnings",
+        Severity::Badbox => "Bad boxes",
+    }
+}
+
+fn severity_icon(severity: Severity) -> &'static str {
+    match severity {
+        Severity::Error => "✖",
+        Severity::Warning => "⚠",
+        Severity::Badbox => "▭",
+    }
+}
+
+fn severity_color(severity: Severity) -> Color32 {
+    match severity {
+        Severity::Error => Color32::from_rgb(255, 100, 100),
+        Severity::Warning => Color32::from_rgb(255, 190, 80),
+        Severity::Badbox => Color32::from_rgb(140, 170, 220),
+    }
+}
+
 fn ensure_fontconfig() {
     let fonts_conf_path = if std::path::Path::new("fonts.conf").exists() {
         "fonts.conf"
@@ -4948,51 +5981,124 @@ fn ensure_fontconfig() {
 
 
 
-fn render_pdf_page_to_texture(
-    ctx: &egui::Context,
-    pdfium: &Pdfium,
-    pdf_path: &std::path::PathBuf,
-    page_index: usize,
-    zoom: f32,
-    preview_size: &mut Option<[usize; 2]>,
-    page_sizes: &mut std::collections::HashMap<usize, (f32, f32)>,
-) -> Result<egui::TextureHandle, Box<dyn std::error::Error>> {
-    let doc = pdfium.load_pdf_from_file(pdf_path, None)?;
-
-    let page_index_u16: u16 = page_index.try_into()?;
-    let page = doc.pages().get(page_index_u16)?;
-
-    let width_pt = page.width().value;
-    let height_pt = page.height().value;
-    page_sizes.insert(page_index, (width_pt, height_pt));
-
-    let scale = ctx.pixels_per_point().max(1.0);
-    // Increase resolution: render at 3x scale relative to standard 72 DPI pt size
-    let width_pixels = width_pt * zoom * scale * 3.0;
-    let target_width = width_pixels.clamp(100.0, 8192.0).round() as i32;
-
-    let render_config = PdfRenderConfig::new()
-        .set_target_width(target_width)
-        .rotate_if_landscape(PdfPageRenderRotation::Degrees90, true);
-
-    let bitmap = page.render_with_config(&render_config)?;
-    let dyn_img = bitmap.as_image().into_rgba8();
-
-    let size = [dyn_img.width() as usize, dyn_img.height() as usize];
-    let pixels = dyn_img.into_raw();
-    let color_image = egui::ColorImage::from_rgba_unmultiplied(size, &pixels);
-
-    *preview_size = Some(size);
-
-    Ok(ctx.load_texture(
-        "pdf_preview",
-        color_image,
-        egui::TextureOptions {
-            magnification: egui::TextureFilter::Linear,
-            minification: egui::TextureFilter::Linear,
-            wrap_mode: egui::TextureWrapMode::ClampToEdge,
-        },
-    ))
+fn scroll_to_pdf_target(ui: &egui::Ui, target: &mut Option<(usize, f32)>, page_idx: usize, image_rect: egui::Rect) {
+    if let Some((p, rel_y)) = *target {
+        if p == page_idx {
+            let y_pos = image_rect.min.y + image_rect.height() * rel_y;
+            let target_rect = egui::Rect::from_min_size(
+                egui::pos2(image_rect.min.x, y_pos),
+                egui::vec2(image_rect.width(), 1.0)
+            );
+            ui.scroll_to_rect(target_rect, Some(egui::Align::Center));
+            *target = None;
+        }
+    }
+}
+
+// Byte ranges of `query` in `text`
+fn search_text(text: &str, query: &str, case_sensitive: bool, whole_word: bool) -> Vec<std::ops::Range<usize>> {
+    if query.is_empty() {
+        return Vec::new();
+    }
+
+    // Lowercasing can change a character's length, so offsets in the folded
+    // copy are mapped back to the original
+    let ranges: Vec<std::ops::Range<usize>> = if case_sensitive {
+        text.match_indices(query).map(|(i, m)| i..i + m.len()).collect()
+    } else {
+        let mut folded = String::with_capacity(text.len());
+        let mut origin = Vec::with_capacity(text.len() + 1);
+        for (b, c) in text.char_indices() {
+            for lower in c.to_lowercase() {
+                origin.resize(origin.len() + lower.len_utf8(), b);
+                folded.push(lower);
+            }
+        }
+        origin.push(text.len());
+        folded.match_indices(&query.to_lowercase()).map(|(i, m)| origin[i]..origin[i + m.len()]).collect()
+    };
+
+    ranges
+        .into_iter()
+        .filter(|r| {
+            !whole_word
+                || (!text[..r.start].chars().last().is_some_and(|c| c.is_alphanumeric())
+                    && !text[r.end..].chars().next().is_some_and(|c| c.is_alphanumeric()))
+        })
+        .collect()
+}
+
+// Colored byte ranges of one line highlighted by syntect, advancing its state
+fn syntect_spans(
+    parse_state: &mut ParseState,
+    highlight_state: &mut HighlightState,
+    highlighter: &Highlighter,
+    syntax_set: &SyntaxSet,
+    line: &str,
+) -> Vec<(std::ops::Range<usize>, Color32)> {
+    let ops = parse_state.parse_line(line, syntax_set).unwrap_or_default();
+    let mut spans = Vec::new();
+    let mut start = 0;
+    for (style, piece) in HighlightIterator::new(highlight_state, &ops, line, highlighter) {
+        let fg = style.foreground;
+        spans.push((start..start + piece.len(), Color32::from_rgb(fg.r, fg.g, fg.b)));
+        start += piece.len();
+    }
+    spans
+}
+
+// Blend of `a` and `b`, `t` of the way towards `b`
+fn mix(a: Color32, b: Color32, t: f32) -> Color32 {
+    let channel = |x: u8, y: u8| (x as f32 + (y as f32 - x as f32) * t).round() as u8;
+    Color32::from_rgb(channel(a.r(), b.r()), channel(a.g(), b.g()), channel(a.b(), b.b()))
+}
+
+fn content_hash(text: &str) -> u64 {
+    use std::hash::{Hash, Hasher};
+    let mut hasher = std::collections::hash_map::DefaultHasher::new();
+    text.hash(&mut hasher);
+    hasher.finish()
+}
+
+// Editor state with the cursor at a character index
+fn cursor_state(index: usize) -> egui::text_edit::TextEditState {
+    let mut state = egui::text_edit::TextEditState::default();
+    state.cursor.set_char_range(Some(CCursorRange::one(CCursor::new(index))));
+    state
+}
+
+// Word list for `language`: dictionary.txt for English, dictionary-<lang>.txt
+// (full tag first, then the primary language) otherwise. Empty when spelling is
+// off or no list is found, which disables the spellchecker.
+fn load_dictionary(language: &str) -> std::collections::HashSet<String> {
+    let language = language.trim().to_lowercase().replace('_', "-");
+    let primary = language.split('-').next().unwrap_or_default().to_string();
+    let names = match primary.as_str() {
+        "" | "off" | "none" => return std::collections::HashSet::new(),
+        "en" => vec!["dictionary.txt".to_string()],
+        _ => vec![format!("dictionary-{}.txt", language), format!("dictionary-{}.txt", primary)],
+    };
+
+    let found = names.iter().flat_map(|name| [PathBuf::from(name), PathBuf::from("../..").join(name)]).find(|p| p.exists());
+    match found {
+        Some(path) => std::fs::read_to_string(path)
+            .map(|content| content.lines().map(|line| line.trim().to_lowercase()).collect())
+            .unwrap_or_default(),
+        None => {
+            if primary == "en" {
+                // Attempt download in a separate thread
+                std::thread::spawn(|| {
+                    // Using atebits/Words for a better standard english dictionary
+                    if let Ok(resp) = reqwest::blocking::get("https://raw.githubusercontent.com/atebits/Words/master/Words/en.txt") {
+                        if let Ok(text) = resp.text() {
+                            let _ = std::fs::write("dictionary.txt", &text);
+                        }
+                    }
+                });
+            }
+            std::collections::HashSet::new()
+        }
+    }
 }
 
 fn load_icon() -> Option<egui::IconData> {
@@ -5027,7 +6133,112 @@ fn load_icon() -> Option<egui::IconData> {
     }
 }
 
+// Headless `typesafe build <root.tex>`: runs the editor's build pipeline and
+// prints diagnostics. Project hooks run if the editor trusted them or with
+// `trust_hooks`. Returns the process exit code.
+fn run_cli_build(root: &str, trust_hooks: bool) -> i32 {
+    #[cfg(windows)]
+    {
+        // Release builds use the GUI subsystem, so borrow the parent's console for output
+        extern "system" { fn AttachConsole(process_id: u32) -> i32; }
+        const ATTACH_PARENT_PROCESS: u32 = u32::MAX;
+        unsafe { AttachConsole(ATTACH_PARENT_PROCESS); }
+    }
+
+    // tectonic runs inside the root's folder, so hand it an absolute path
+    let root_path = std::fs::canonicalize(root).unwrap_or_else(|_| PathBuf::from(root));
+    let content = match std::fs::read_to_string(&root_path) {
+        Ok(content) => content,
+        Err(e) => {
+            eprintln!("error: cannot read {}: {}", root, e);
+            return 2;
+        }
+    };
+
+    let (tx, rx) = unbounded();
+    let settings = Settings::load();
+    let mut project = match root_path.parent().and_then(ProjectConfig::find).map(|p| ProjectConfig::load(&p)).transpose() {
+        Ok(project) => project,
+        Err(e) => {
+            eprintln!("error: {}", e);
+            return 2;
+        }
+    };
+    let mut engine_settings = project
+        .as_ref()
+        .and_then(ProjectConfig::engine_settings)
+        .unwrap_or_else(|| settings.build_settings_for(&root_path).clone());
+    let magic = MagicComments::parse(&content);
+    if let Some(engine) = magic.program.as_deref().and_then(Engine::from_name) {
+        engine_settings.engine = engine;
+    }
+    let mut job = CompileJob::new(&root_path).with_engine(&engine_settings);
+    job.bib_tool = magic.bib_program.as_deref().and_then(BibTool::from_name);
+    job.out_dir = project.as_ref().and_then(|p| p.output_dir.clone()).or_else(|| settings.output_dir_for(&root_path));
+    if let Some(untrusted) = project.as_mut().filter(|p| p.has_hooks() && !trust_hooks && !settings.hooks_trusted(p)) {
+        eprintln!("warning: skipping the build hooks of {}; trust them in the editor or pass --trust-hooks", untrusted.path.display());
+        untrusted.hooks = Default::default();
+    }
+    job.hooks = project.map(|p| p.hooks).unwrap_or_default();
+    job.timeout = settings.compile_timeout();
+    job.has_biblatex = content.contains("biblatex");
+    let worker = std::thread::spawn(move || job.run(&tx, &|| {}));
+
+    for msg in rx.iter() {
+        match msg {
+            CompilationMsg::Start => {}
+            CompilationMsg::Log(line) => println!("{}", line),
+            CompilationMsg::Diagnostics(diags) => {
+                for diag in diags {
+                    let location = match (diag.line, diag.column) {
+                        (0, _) => diag.file.clone(),
+                        (line, Some(column)) => format!("{}:{}:{}", diag.file, line, column + 1),
+                        (line, None) => format!("{}:{}", diag.file, line),
+                    };
+                    eprintln!("{}: {}: {}", location, diag.severity.label(), diag.message);
+                }
+            }
+            CompilationMsg::Success(pdf_path, _, _) => println!("Output: {}", pdf_path.display()),
+            CompilationMsg::Error(err) => eprintln!("error: {}", err),
+            CompilationMsg::Cancelled => eprintln!("error: build cancelled"),
+        }
+    }
+
+    match worker.join() {
+        Ok(result) if result.succeeded() => 0,
+        _ => 1,
+    }
+}
+
 fn main() -> Result<(), eframe::Error> {
+    let args: Vec<String> = std::env::args().collect();
+    if args.get(1).map(String::as_str) == Some("build") {
+        let trust_hooks = args.iter().any(|a| a == "--trust-hooks");
+        let Some(root) = args.iter().skip(2).find(|a| *a != "--trust-hooks") else {
+            eprintln!("usage: typesafe build [--trust-hooks] <root.tex>");
+            std::process::exit(2);
+        };
+        std::process::exit(run_cli_build(root, trust_hooks));
+    }
+    if args.iter().skip(1).any(|a| a == "--help" || a == "-h") {
+        println!("{}", cli::USAGE);
+        return Ok(());
+    }
+    let cwd = std::env::current_dir().unwrap_or_default();
+    let launch = match LaunchArgs::parse(&args[1..], &cwd) {
+        Ok(launch) => launch,
+        Err(e) => {
+            eprintln!("error: {}\n{}", e, cli::USAGE);
+            std::process::exit(2);
+        }
+    };
+
+    // Hand the arguments to an editor that is already open
+    let request = ipc::Request { cwd: cwd.clone(), args: args[1..].to_vec() };
+    if ipc::send(&request).is_ok() {
+        return Ok(());
+    }
+
     ensure_fontconfig();
 
     let mut viewport = egui::ViewportBuilder::default()
@@ -5046,6 +6257,25 @@ fn main() -> Result<(), eframe::Error> {
     eframe::run_native(
         "Typesafe",
         options,
-        Box::new(|_cc| Box::<TypesafeApp>::default()),
+        Box::new(move |cc| {
+            let mut app = TypesafeApp::default();
+            if !launch.is_empty() {
+                app.pending_launch = Some(launch);
+            }
+
+            let (launch_tx, launch_rx) = unbounded();
+            let ctx = cc.egui_ctx.clone();
+            let listening = ipc::listen(move |request| {
+                if let Ok(launch) = LaunchArgs::parse(&request.args, &request.cwd) {
+                    let _ = launch_tx.send(launch);
+                    ctx.request_repaint();
+                }
+            });
+            // Another window got there first; this one just runs on its own
+            if listening.is_ok() {
+                app.launch_rx = Some(launch_rx);
+            }
+            Box::new(app)
+        }),
     )
 }
@@ -0,0 +1,148 @@
+// Project Configuration
+//
+// A `.typesafe.toml` (or `.typesafe.json`) in the project folder. Anything it
+// sets takes precedence over the global `Settings`. Paths in it are relative to
+// the folder holding the file. Its hooks are shell commands, so they only run
+// once the file has been trusted as it is now.
+//
+//     root = "thesis.tex"
+//     engine = "latexmk"
+//     engine_args = "-pdf -shell-escape"
+//     output_dir = "build"
+//     spelling_language = "en-GB"
+//     dictionaries = ["words.txt"]
+//     bib_files = ["../shared/refs.bib"]
+//
+//     [hooks]
+//     before_build = ["make figures"]
+//     after_build = ["cp \"$TYPESAFE_PDF\" dist/"]
+
+use crate::compiler::{BuildHooks, Engine, EngineSettings};
+use serde::{Deserialize, Deserializer};
+use sha2::{Digest, Sha256};
+use std::path::{Path, PathBuf};
+
+pub const FILE_NAMES: &[&str] = &[".typesafe.toml", ".typesafe.json"];
+
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ProjectConfig {
+    pub root: Option<PathBuf>,
+    #[serde(deserialize_with = "engine_by_name")]
+    pub engine: Option<Engine>,
+    // Replaces the engine's default arguments, space-separated
+    pub engine_args: Option<String>,
+    pub output_dir: Option<PathBuf>,
+    pub spelling_language: Option<String>,
+    // Word lists accepted by the spellchecker, one word per line
+    pub dictionaries: Vec<PathBuf>,
+    // Searched for citation keys in addition to the project folder's .bib files
+    pub bib_files: Vec<PathBuf>,
+    pub hooks: BuildHooks,
+    // The file this was read from
+    #[serde(skip)]
+    pub path: PathBuf,
+    // SHA-256 of the file, which a trust decision applies to
+    #[serde(skip)]
+    pub hash: String,
+}
+
+fn engine_by_name<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<Engine>, D::Error> {
+    let name = String::deserialize(deserializer)?;
+    match Engine::from_name(&name) {
+        Some(engine) => Ok(Some(engine)),
+        None => Err(serde::de::Error::custom(format!("unknown engine `{}`", name))),
+    }
+}
+
+impl ProjectConfig {
+    // Configuration file in `dir` itself; parent folders are not searched
+    pub fn find(dir: &Path) -> Option<PathBuf> {
+        FILE_NAMES.iter().map(|name| dir.join(name)).find(|p| p.is_file())
+    }
+
+    pub fn load(path: &Path) -> Result<Self, String> {
+        let text = std::fs::read_to_string(path).map_err(|e| format!("Cannot read {}: {}", path.display(), e))?;
+        let parsed = if path.extension().is_some_and(|e| e == "json") {
+            serde_json::from_str::<ProjectConfig>(&text).map_err(|e| e.to_string())
+        } else {
+            toml::from_str::<ProjectConfig>(&text).map_err(|e| e.to_string())
+        };
+        let mut config = parsed.map_err(|e| format!("Invalid {}: {}", path.display(), e))?;
+
+        config.path = path.to_path_buf();
+        config.hash = format!("{:x}", Sha256::digest(text.as_bytes()));
+        let dir = config.dir().to_path_buf();
+        let resolve = |p: &mut PathBuf| *p = dir.join(&*p);
+        config.root.iter_mut().chain(config.output_dir.iter_mut()).for_each(resolve);
+        config.dictionaries.iter_mut().chain(config.bib_files.iter_mut()).for_each(resolve);
+        Ok(config)
+    }
+
+    // Folder holding the configuration file
+    pub fn dir(&self) -> &Path {
+        self.path.parent().unwrap_or(Path::new("."))
+    }
+
+    pub fn has_hooks(&self) -> bool {
+        !self.hooks.before_build.is_empty() || !self.hooks.after_build.is_empty()
+    }
+
+    // Engine settings when the file picks an engine, else None
+    pub fn engine_settings(&self) -> Option<EngineSettings> {
+        let engine = self.engine?;
+        let mut settings = EngineSettings { engine, ..Default::default() };
+        if let Some(args) = &self.engine_args {
+            settings.args.insert(engine, args.clone());
+        }
+        Some(settings)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn scratch_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("typesafe-project-{}-{}", name, std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(dir.join("chapters")).unwrap();
+        dir
+    }
+
+    #[test]
+    fn toml_paths_are_relative_to_the_file() {
+        let dir = scratch_dir("toml");
+        let path = dir.join(".typesafe.toml");
+        std::fs::write(&path, "root = \"thesis.tex\"\nengine = \"latexmk\"\nengine_args = \"-pdf -shell-escape\"\nbib_files = [\"refs.bib\"]\n\n[hooks]\nbefore_build = [\"make\"]\n").unwrap();
+
+        assert_eq!(ProjectConfig::find(&dir), Some(path.clone()));
+        // A subfolder does not pick up its parent's file
+        assert_eq!(ProjectConfig::find(&dir.join("chapters")), None);
+        let config = ProjectConfig::load(&path).unwrap();
+        assert!(config.has_hooks() && config.hash.len() == 64);
+        assert_eq!(config.root, Some(dir.join("thesis.tex")));
+        assert_eq!(config.bib_files, vec![dir.join("refs.bib")]);
+        assert_eq!(config.hooks.before_build, vec!["make"]);
+
+        let engine = config.engine_settings().unwrap();
+        assert_eq!(engine.engine, Engine::Latexmk);
+        assert_eq!(engine.args_for(Engine::Latexmk), vec!["-pdf", "-shell-escape"]);
+    }
+
+    #[test]
+    fn json_is_accepted_and_errors_name_the_file() {
+        let dir = scratch_dir("json");
+        let path = dir.join(".typesafe.json");
+        std::fs::write(&path, r#"{"spelling_language": "de", "dictionaries": ["words.txt"], "output_dir": "build"}"#).unwrap();
+        let config = ProjectConfig::load(&path).unwrap();
+        assert_eq!(config.spelling_language.as_deref(), Some("de"));
+        assert_eq!(config.dictionaries, vec![dir.join("words.txt")]);
+        assert_eq!(config.output_dir, Some(dir.join("build")));
+        assert!(config.engine_settings().is_none());
+
+        std::fs::write(&path, r#"{"engine": "troff"}"#).unwrap();
+        let err = ProjectConfig::load(&path).unwrap_err();
+        assert!(err.contains(".typesafe.json") && err.contains("unknown engine `troff`"), "{}", err);
+    }
+}
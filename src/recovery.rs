@@ -0,0 +1,242 @@
+// Crash Recovery
+//
+// Unsaved buffer contents are journaled to swap files in the config directory
+// by a background thread. Each buffer has its own swap id, so untitled buffers
+// that share a path do not overwrite each other. A swap is removed once its
+// buffer is saved or closed, so swaps found at startup hold work that never
+// reached the disk; the editor offers them back with a diff against the file
+// as it is now.
+
+use crossbeam_channel::{unbounded, Sender};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+
+// Past this many line pairs the diff gives up on alignment; at 4 bytes a cell
+// the table stays under 4 MB
+const MAX_DIFF_CELLS: usize = 1_000_000;
+
+// A swap id no other buffer of this or an earlier run has: the time this run
+// started and a counter
+pub fn new_id() -> String {
+    static STARTED: std::sync::OnceLock<u128> = std::sync::OnceLock::new();
+    static NEXT: AtomicU64 = AtomicU64::new(0);
+    let started = STARTED.get_or_init(|| std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH).map_or(0, |d| d.as_nanos()));
+    format!("{:x}-{}", started, NEXT.fetch_add(1, Ordering::Relaxed))
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Swap {
+    // The buffer's swap id
+    pub id: String,
+    // The buffer's file; it may not exist yet for new documents
+    pub path: String,
+    pub content: String,
+    // Seconds since the Unix epoch
+    pub saved_at: u64,
+}
+
+impl Swap {
+    pub fn new(id: &str, path: &str, content: &str) -> Self {
+        let saved_at = std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH).map_or(0, |d| d.as_secs());
+        Self { id: id.to_string(), path: path.to_string(), content: content.to_string(), saved_at }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct SwapStore {
+    dir: PathBuf,
+}
+
+impl SwapStore {
+    pub fn new(dir: impl Into<PathBuf>) -> Self {
+        Self { dir: dir.into() }
+    }
+
+    // `swap/` next to settings.json
+    pub fn default_location() -> Self {
+        match directories::ProjectDirs::from("com", "typesafe", "typesafe") {
+            Some(dirs) => Self::new(dirs.config_dir().join("swap")),
+            None => Self::new("swap"),
+        }
+    }
+
+    fn file_for(&self, id: &str) -> PathBuf {
+        let hash = format!("{:x}", Sha256::digest(id.as_bytes()));
+        self.dir.join(format!("{}.json", &hash[..16]))
+    }
+
+    // Every swap in the store, newest first
+    pub fn load_all(&self) -> Vec<Swap> {
+        let Ok(entries) = std::fs::read_dir(&self.dir) else { return Vec::new() };
+        let mut swaps: Vec<Swap> = entries
+            .filter_map(|e| e.ok())
+            .map(|e| e.path())
+            .filter(|p| p.extension().is_some_and(|e| e == "json"))
+            .filter_map(|p| serde_json::from_str(&std::fs::read_to_string(p).ok()?).ok())
+            .collect();
+        swaps.sort_by_key(|s| std::cmp::Reverse(s.saved_at));
+        swaps
+    }
+
+    // Replaces the swap with `swap.id` without ever leaving a partial file
+    pub fn write(&self, swap: &Swap) -> io::Result<()> {
+        std::fs::create_dir_all(&self.dir)?;
+        let target = self.file_for(&swap.id);
+        let partial = target.with_extension("tmp");
+        std::fs::write(&partial, serde_json::to_vec(swap).map_err(io::Error::other)?)?;
+        std::fs::rename(&partial, &target)
+    }
+
+    pub fn remove(&self, id: &str) {
+        let _ = std::fs::remove_file(self.file_for(id));
+    }
+}
+
+enum SwapMsg {
+    Write(Swap),
+    Remove(String),
+}
+
+// Writes and removes swaps on a worker thread, so typing never waits on the disk
+pub struct SwapWriter {
+    tx: Sender<SwapMsg>,
+}
+
+impl SwapWriter {
+    pub fn spawn(store: SwapStore) -> Self {
+        let (tx, rx) = unbounded::<SwapMsg>();
+        std::thread::spawn(move || {
+            while let Ok(first) = rx.recv() {
+                // Only the latest request per swap matters
+                let mut latest = HashMap::new();
+                for msg in std::iter::once(first).chain(rx.try_iter()) {
+                    match msg {
+                        SwapMsg::Write(swap) => latest.insert(swap.id.clone(), Some(swap)),
+                        SwapMsg::Remove(id) => latest.insert(id, None),
+                    };
+                }
+                for (id, swap) in latest {
+                    match swap {
+                        Some(swap) => {
+                            let _ = store.write(&swap);
+                        }
+                        None => store.remove(&id),
+                    }
+                }
+            }
+        });
+        Self { tx }
+    }
+
+    pub fn write(&self, id: &str, path: &str, content: &str) {
+        let _ = self.tx.send(SwapMsg::Write(Swap::new(id, path, content)));
+    }
+
+    pub fn remove(&self, id: &str) {
+        let _ = self.tx.send(SwapMsg::Remove(id.to_string()));
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DiffKind {
+    Same,
+    Removed,
+    Added,
+}
+
+// Line diff turning `old` into `new`, by longest common subsequence
+pub fn diff_lines<'a>(old: &'a str, new: &'a str) -> Vec<(DiffKind, &'a str)> {
+    let old: Vec<&str> = old.lines().collect();
+    let new: Vec<&str> = new.lines().collect();
+    let prefix = old.iter().zip(&new).take_while(|(a, b)| a == b).count();
+    let suffix = old[prefix..].iter().rev().zip(new[prefix..].iter().rev()).take_while(|(a, b)| a == b).count();
+    let (a, b) = (&old[prefix..old.len() - suffix], &new[prefix..new.len() - suffix]);
+
+    let mut out: Vec<(DiffKind, &str)> = old[..prefix].iter().map(|l| (DiffKind::Same, *l)).collect();
+    if a.len().saturating_mul(b.len()) > MAX_DIFF_CELLS {
+        out.extend(a.iter().map(|l| (DiffKind::Removed, *l)));
+        out.extend(b.iter().map(|l| (DiffKind::Added, *l)));
+    } else {
+        // lcs(i, j): common lines of a[i..] and b[j..], one flat row per line of `a`
+        let width = b.len() + 1;
+        let mut table = vec![0u32; (a.len() + 1) * width];
+        for i in (0..a.len()).rev() {
+            for j in (0..b.len()).rev() {
+                table[i * width + j] = if a[i] == b[j] {
+                    table[(i + 1) * width + j + 1] + 1
+                } else {
+                    table[(i + 1) * width + j].max(table[i * width + j + 1])
+                };
+            }
+        }
+        let lcs = |i: usize, j: usize| table[i * width + j];
+        let (mut i, mut j) = (0, 0);
+        while i < a.len() || j < b.len() {
+            if i < a.len() && j < b.len() && a[i] == b[j] {
+                out.push((DiffKind::Same, a[i]));
+                i += 1;
+                j += 1;
+            } else if j < b.len() && (i == a.len() || lcs(i, j + 1) >= lcs(i + 1, j)) {
+                out.push((DiffKind::Added, b[j]));
+                j += 1;
+            } else {
+                out.push((DiffKind::Removed, a[i]));
+                i += 1;
+            }
+        }
+    }
+    out.extend(old[old.len() - suffix..].iter().map(|l| (DiffKind::Same, *l)));
+    out
+}
+
+// Whether `swap` still differs from its file on disk
+pub fn is_recoverable(swap: &Swap) -> bool {
+    std::fs::read_to_string(Path::new(&swap.path)).map_or(true, |disk| disk != swap.content)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn swaps_round_trip_through_the_store() {
+        let dir = std::env::temp_dir().join(format!("typesafe-swap-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        let store = SwapStore::new(&dir);
+        assert!(store.load_all().is_empty());
+
+        let mut older = Swap::new("1", "/work/a.tex", "old");
+        older.saved_at -= 10;
+        store.write(&older).unwrap();
+        store.write(&Swap::new("2", "/work/b.tex", "newer")).unwrap();
+        let paths: Vec<String> = store.load_all().into_iter().map(|s| s.path).collect();
+        assert_eq!(paths, vec!["/work/b.tex", "/work/a.tex"]);
+
+        // Untitled buffers share a path but not a swap
+        let (first, second) = (new_id(), new_id());
+        assert_ne!(first, second);
+        store.write(&Swap::new(&first, "/work/untitled.tex", "one")).unwrap();
+        store.write(&Swap::new(&second, "/work/untitled.tex", "two")).unwrap();
+        assert_eq!(store.load_all().len(), 4);
+        store.remove(&first);
+        store.remove(&second);
+
+        store.remove("1");
+        assert_eq!(store.load_all().len(), 1);
+        assert!(is_recoverable(&store.load_all()[0]));
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn diff_keeps_common_lines() {
+        use DiffKind::*;
+        let diff = diff_lines("a\nb\nc\nd\n", "a\nc\nx\nd\n");
+        assert_eq!(diff, vec![(Same, "a"), (Removed, "b"), (Same, "c"), (Added, "x"), (Same, "d")]);
+        assert!(diff_lines("same\n", "same\n").iter().all(|(k, _)| *k == Same));
+        assert_eq!(diff_lines("", "new"), vec![(Added, "new")]);
+    }
+}
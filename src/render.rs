@@ -0,0 +1,482 @@
+// PDF Render Worker
+//
+// Pages are rendered on a background thread that keeps the current document
+// open. The UI asks for the pages it is showing; each one comes back first as a
+// cheap low-resolution pass and then at full resolution. `PageCache` holds the
+// resulting textures across recompiles: a page whose low-resolution render
+// hashes the same as before keeps its existing texture.
+//
+// Searches run on the same thread. The text of every page is read once per
+// document and kept, so each keystroke in the search box only scans strings;
+// searches queued behind a newer one are skipped.
+
+use crossbeam_channel::{unbounded, Receiver, Sender};
+use pdfium_render::prelude::*;
+use std::collections::hash_map::DefaultHasher;
+use std::collections::HashMap;
+use std::hash::{Hash, Hasher};
+use std::path::PathBuf;
+use std::sync::Arc;
+
+// Texture memory kept for rendered pages
+pub const TEXTURE_BUDGET: usize = 256 * 1024 * 1024;
+// Render at this multiple of the displayed size so text stays sharp
+const OVERSAMPLE: f32 = 3.0;
+// Low-resolution passes are this fraction of the full width
+const LOW_RES_DIVISOR: u32 = 4;
+const MIN_WIDTH: u32 = 64;
+const MAX_WIDTH: u32 = 8192;
+
+// Pixel width to render a page `page_width` points wide at `zoom`
+pub fn target_width(page_width: f32, zoom: f32, pixels_per_point: f32) -> u32 {
+    (page_width * zoom * pixels_per_point.max(1.0) * OVERSAMPLE).round().clamp(MIN_WIDTH as f32, MAX_WIDTH as f32) as u32
+}
+
+fn low_res_width(width: u32) -> u32 {
+    (width / LOW_RES_DIVISOR).max(MIN_WIDTH)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RenderJob {
+    pub page: usize,
+    pub width: u32,
+    pub full: bool,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct SearchHit {
+    pub page: usize,
+    // Match bounds in PDF points, top-left origin, one per line of the match
+    pub rects: Vec<egui::Rect>,
+    // The line the match starts on
+    pub snippet: String,
+}
+
+pub enum RenderMsg {
+    // Page sizes in PDF points
+    Opened { generation: u64, sizes: Vec<(f32, f32)> },
+    Page { generation: u64, job: RenderJob, hash: u64, image: egui::ColorImage },
+    Failed { generation: u64, error: String },
+    Found { generation: u64, query: String, hits: Vec<SearchHit> },
+}
+
+enum Command {
+    Open(u64, PathBuf),
+    Render(u64, Vec<RenderJob>),
+    Search(u64, String),
+}
+
+pub struct RenderWorker {
+    tx: Sender<Command>,
+    generation: u64,
+}
+
+impl RenderWorker {
+    pub fn spawn(pdfium: Arc<Pdfium>, results: Sender<RenderMsg>, notify: impl Fn() + Send + 'static) -> Self {
+        let (tx, rx) = unbounded();
+        std::thread::spawn(move || run(&pdfium, rx, results, notify));
+        Self { tx, generation: 0 }
+    }
+
+    // Incremented on every `open`; results from older documents carry older values
+    pub fn generation(&self) -> u64 {
+        self.generation
+    }
+
+    pub fn open(&mut self, path: PathBuf) -> u64 {
+        self.generation += 1;
+        let _ = self.tx.send(Command::Open(self.generation, path));
+        self.generation
+    }
+
+    // Replaces whatever the worker had left to do
+    pub fn render(&self, jobs: Vec<RenderJob>) {
+        let _ = self.tx.send(Command::Render(self.generation, jobs));
+    }
+
+    // Answered with `RenderMsg::Found` for the same query
+    pub fn search(&self, query: String) {
+        let _ = self.tx.send(Command::Search(self.generation, query));
+    }
+}
+
+fn run(pdfium: &Pdfium, rx: Receiver<Command>, tx: Sender<RenderMsg>, notify: impl Fn()) {
+    let mut document: Option<PdfDocument> = None;
+    let mut generation = 0;
+    let mut queue: Vec<RenderJob> = Vec::new();
+    // Page text of the open document, read on its first search
+    let mut texts: Option<Vec<PageText>> = None;
+
+    loop {
+        // Wait while idle, otherwise pick up anything new between pages
+        let mut commands: Vec<Command> = Vec::new();
+        if queue.is_empty() {
+            match rx.recv() {
+                Ok(command) => commands.push(command),
+                Err(_) => return,
+            }
+        }
+        commands.extend(rx.try_iter());
+
+        let mut search = None;
+        for command in commands {
+            match command {
+                Command::Open(next, path) => {
+                    generation = next;
+                    queue.clear();
+                    search = None;
+                    texts = None;
+                    // Read the file up front; the next build overwrites it while we render
+                    document = None;
+                    let opened = std::fs::read(&path)
+                        .map_err(|e| e.to_string())
+                        .and_then(|bytes| pdfium.load_pdf_from_byte_vec(bytes, None).map_err(|e| e.to_string()));
+                    let msg = match opened {
+                        Ok(doc) => {
+                            let sizes = doc.pages().iter().map(|p| (p.width().value, p.height().value)).collect();
+                            document = Some(doc);
+                            RenderMsg::Opened { generation, sizes }
+                        }
+                        Err(error) => RenderMsg::Failed { generation, error },
+                    };
+                    let _ = tx.send(msg);
+                    notify();
+                }
+                Command::Render(for_generation, jobs) => {
+                    if for_generation == generation {
+                        queue = jobs;
+                        queue.reverse();
+                    }
+                }
+                Command::Search(for_generation, query) => {
+                    if for_generation == generation {
+                        search = Some(query);
+                    }
+                }
+            }
+        }
+
+        if let (Some(doc), Some(query)) = (&document, search) {
+            let texts = texts.get_or_insert_with(|| doc.pages().iter().map(|page| page_text(&page)).collect());
+            let hits = search_pages(texts, &query);
+            let _ = tx.send(RenderMsg::Found { generation, query, hits });
+            notify();
+        }
+
+        let (Some(doc), Some(job)) = (&document, queue.pop()) else { continue };
+        if let Ok(image) = render_page(doc, job) {
+            let _ = tx.send(RenderMsg::Page { generation, job, hash: image_hash(&image), image });
+            notify();
+        }
+    }
+}
+
+fn render_page(doc: &PdfDocument, job: RenderJob) -> Result<egui::ColorImage, PdfiumError> {
+    let index: u16 = job.page.try_into().map_err(|_| PdfiumError::PageIndexOutOfBounds)?;
+    let page = doc.pages().get(index)?;
+    let config = PdfRenderConfig::new().set_target_width(job.width as i32);
+    let image = page.render_with_config(&config)?.as_image().into_rgba8();
+    let size = [image.width() as usize, image.height() as usize];
+    Ok(egui::ColorImage::from_rgba_unmultiplied(size, &image.into_raw()))
+}
+
+// Characters of a page in reading order with their bounds, top-left origin.
+// Characters pdfium inserts itself, such as line breaks, have none.
+#[derive(Default)]
+struct PageText {
+    chars: Vec<char>,
+    bounds: Vec<Option<egui::Rect>>,
+}
+
+fn page_text(page: &PdfPage) -> PageText {
+    let mut text = PageText::default();
+    let Ok(page_text) = page.text() else { return text };
+    let height = page.height().value;
+    for c in page_text.chars().iter() {
+        let Some(ch) = c.unicode_char() else { continue };
+        let bounds = c.loose_bounds().ok().filter(|_| !c.is_generated().unwrap_or(false)).map(|b| {
+            // PDF space is bottom-up, textures are top-down
+            egui::Rect::from_min_max(egui::pos2(b.left().value, height - b.top().value), egui::pos2(b.right().value, height - b.bottom().value))
+        });
+        text.chars.push(ch);
+        text.bounds.push(bounds);
+    }
+    text
+}
+
+// Case-insensitive matches of `query` in every page. Any run of whitespace
+// matches any other, so a match may go over a line break.
+fn search_pages(pages: &[PageText], query: &str) -> Vec<SearchHit> {
+    let needle = searchable(query.trim().chars());
+    let mut hits = Vec::new();
+    if needle.is_empty() {
+        return hits;
+    }
+    for (page, text) in pages.iter().enumerate() {
+        // Searchable characters with the index each one starts at in `text.chars`
+        let mut indices = Vec::new();
+        let mut hay = Vec::new();
+        for (i, ch) in text.chars.iter().enumerate() {
+            push_searchable(&mut hay, *ch);
+            indices.resize(hay.len(), i);
+        }
+
+        let mut at = 0;
+        while at + needle.len() <= hay.len() {
+            if hay[at..at + needle.len()] != needle[..] {
+                at += 1;
+                continue;
+            }
+            let (first, last) = (indices[at], indices[at + needle.len() - 1]);
+            let rects = line_rects(text.bounds[first..=last].iter().flatten());
+            if !rects.is_empty() {
+                hits.push(SearchHit { page, rects, snippet: line_around(&text.chars, first) });
+            }
+            at += needle.len();
+        }
+    }
+    hits
+}
+
+fn searchable(chars: impl Iterator<Item = char>) -> Vec<char> {
+    let mut out = Vec::new();
+    for ch in chars {
+        push_searchable(&mut out, ch);
+    }
+    out
+}
+
+fn push_searchable(out: &mut Vec<char>, ch: char) {
+    if ch.is_whitespace() {
+        if out.last() != Some(&' ') {
+            out.push(' ');
+        }
+    } else {
+        out.extend(ch.to_lowercase());
+    }
+}
+
+// Joins the bounds of consecutive characters on the same line
+fn line_rects<'a>(bounds: impl Iterator<Item = &'a egui::Rect>) -> Vec<egui::Rect> {
+    let mut rects: Vec<egui::Rect> = Vec::new();
+    for rect in bounds {
+        match rects.last_mut() {
+            Some(line) if line.y_range().contains(rect.center().y) => *line = line.union(*rect),
+            _ => rects.push(*rect),
+        }
+    }
+    rects
+}
+
+fn line_around(chars: &[char], index: usize) -> String {
+    let is_break = |c: &char| *c == '\n' || *c == '\r';
+    let start = chars[..index].iter().rposition(is_break).map_or(0, |i| i + 1);
+    let end = chars[index..].iter().position(is_break).map_or(chars.len(), |i| index + i);
+    chars[start..end].iter().collect::<String>().split_whitespace().collect::<Vec<_>>().join(" ")
+}
+
+fn image_hash(image: &egui::ColorImage) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    image.size.hash(&mut hasher);
+    image.pixels.hash(&mut hasher);
+    hasher.finish()
+}
+
+// Page Cache
+
+pub struct CachedPage<T> {
+    pub texture: T,
+    generation: u64,
+    width: u32,
+    full: bool,
+    // Hash of this generation's low-resolution render, 0 if unknown
+    hash: u64,
+    bytes: usize,
+    last_used: u64,
+}
+
+pub struct PageCache<T> {
+    pages: HashMap<usize, CachedPage<T>>,
+    budget: usize,
+    clock: u64,
+}
+
+impl<T> PageCache<T> {
+    pub fn new(budget: usize) -> Self {
+        Self { pages: HashMap::new(), budget, clock: 0 }
+    }
+
+    pub fn clear(&mut self) {
+        self.pages.clear();
+    }
+
+    // Drops pages past the end of a document that got shorter
+    pub fn truncate(&mut self, page_count: usize) {
+        self.pages.retain(|&page, _| page < page_count);
+    }
+
+    pub fn get(&mut self, page: usize) -> Option<&T> {
+        self.clock += 1;
+        let entry = self.pages.get_mut(&page)?;
+        entry.last_used = self.clock;
+        Some(&entry.texture)
+    }
+
+    // Work needed to show `pages` (page, full width) for `generation`. Pages
+    // from an older build get a low-resolution pass first so their hash can be
+    // compared; low-resolution jobs come before full ones.
+    pub fn jobs(&self, generation: u64, pages: &[(usize, u32)]) -> Vec<RenderJob> {
+        let mut low = Vec::new();
+        let mut full = Vec::new();
+        for &(page, width) in pages {
+            match self.pages.get(&page) {
+                Some(entry) if entry.generation == generation => {
+                    if !entry.full || entry.width != width {
+                        full.push(RenderJob { page, width, full: true });
+                    }
+                }
+                _ => low.push(RenderJob { page, width: low_res_width(width), full: false }),
+            }
+        }
+        low.extend(full);
+        low
+    }
+
+    // Stores a finished render. `upload` turns the image into a texture and is
+    // not called when the page's existing texture is still valid.
+    pub fn insert(&mut self, generation: u64, job: RenderJob, hash: u64, image: egui::ColorImage, upload: impl FnOnce(egui::ColorImage) -> T) {
+        if let Some(entry) = self.pages.get_mut(&job.page) {
+            if !job.full {
+                if entry.generation == generation && entry.full {
+                    return;
+                }
+                if entry.hash == hash {
+                    // Unchanged since the last build
+                    entry.generation = generation;
+                    return;
+                }
+            }
+        }
+
+        let previous_hash = self.pages.get(&job.page).filter(|e| e.generation == generation).map(|e| e.hash);
+        self.clock += 1;
+        let bytes = image.pixels.len() * 4;
+        self.pages.insert(job.page, CachedPage {
+            texture: upload(image),
+            generation,
+            width: job.width,
+            full: job.full,
+            hash: if job.full { previous_hash.unwrap_or(0) } else { hash },
+            bytes,
+            last_used: self.clock,
+        });
+        self.evict(job.page);
+    }
+
+    // Least recently drawn pages go first; `keep` was just inserted
+    fn evict(&mut self, keep: usize) {
+        let mut total: usize = self.pages.values().map(|e| e.bytes).sum();
+        while total > self.budget {
+            let Some(page) = self.pages.iter().filter(|(&p, _)| p != keep).min_by_key(|(_, e)| e.last_used).map(|(&p, _)| p) else { break };
+            total -= self.pages.remove(&page).map(|e| e.bytes).unwrap_or(0);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn image(width: usize, shade: u8) -> egui::ColorImage {
+        egui::ColorImage::new([width, width], egui::Color32::from_gray(shade))
+    }
+
+    fn render(cache: &mut PageCache<u32>, generation: u64, job: RenderJob, img: egui::ColorImage, id: u32) -> bool {
+        let hash = image_hash(&img);
+        let mut uploaded = false;
+        cache.insert(generation, job, hash, img, |_| {
+            uploaded = true;
+            id
+        });
+        uploaded
+    }
+
+    #[test]
+    fn low_then_full_resolution() {
+        let mut cache = PageCache::new(usize::MAX);
+        assert_eq!(cache.jobs(1, &[(0, 400)]), vec![RenderJob { page: 0, width: 100, full: false }]);
+
+        render(&mut cache, 1, RenderJob { page: 0, width: 100, full: false }, image(10, 0), 1);
+        assert_eq!(cache.jobs(1, &[(0, 400)]), vec![RenderJob { page: 0, width: 400, full: true }]);
+
+        render(&mut cache, 1, RenderJob { page: 0, width: 400, full: true }, image(40, 0), 2);
+        assert!(cache.jobs(1, &[(0, 400)]).is_empty());
+        assert_eq!(cache.get(0), Some(&2));
+
+        // Zooming asks for a new full render but keeps showing the old texture
+        assert_eq!(cache.jobs(1, &[(0, 800)]), vec![RenderJob { page: 0, width: 800, full: true }]);
+    }
+
+    #[test]
+    fn unchanged_pages_keep_their_texture_after_a_rebuild() {
+        let mut cache = PageCache::new(usize::MAX);
+        let low = RenderJob { page: 0, width: 100, full: false };
+        render(&mut cache, 1, low, image(10, 0), 1);
+        render(&mut cache, 1, RenderJob { page: 0, width: 400, full: true }, image(40, 0), 2);
+        render(&mut cache, 1, RenderJob { page: 1, width: 100, full: false }, image(10, 0), 3);
+
+        // Page 0 renders the same in the new build, page 1 does not
+        assert!(!render(&mut cache, 2, low, image(10, 0), 4));
+        assert!(render(&mut cache, 2, RenderJob { page: 1, width: 100, full: false }, image(10, 9), 5));
+        assert_eq!(cache.get(0), Some(&2));
+        assert_eq!(cache.get(1), Some(&5));
+        assert!(cache.jobs(2, &[(0, 400)]).is_empty());
+    }
+
+    fn page(lines: &[&str]) -> PageText {
+        let mut text = PageText::default();
+        for (y, line) in lines.iter().enumerate() {
+            if y > 0 {
+                text.chars.extend(['\r', '\n']);
+                text.bounds.extend([None, None]);
+            }
+            for (x, ch) in line.chars().enumerate() {
+                text.chars.push(ch);
+                let min = egui::pos2(x as f32 * 10.0, y as f32 * 20.0);
+                text.bounds.push(Some(egui::Rect::from_min_size(min, egui::vec2(10.0, 12.0))));
+            }
+        }
+        text
+    }
+
+    #[test]
+    fn search_finds_matches_across_pages_and_lines() {
+        let pages = [page(&["The Quick fox", "and the quick hare"]), page(&["quick-", "ly"])];
+        let hits = search_pages(&pages, "quick");
+        assert_eq!(hits.iter().map(|h| h.page).collect::<Vec<_>>(), vec![0, 0, 1]);
+        assert_eq!(hits[0].rects, vec![egui::Rect::from_min_max(egui::pos2(40.0, 0.0), egui::pos2(90.0, 12.0))]);
+        assert_eq!(hits[1].snippet, "and the quick hare");
+
+        // A match over a line break gets a rect per line
+        let hits = search_pages(&pages, " fox  and ");
+        assert_eq!(hits.len(), 1);
+        assert_eq!(hits[0].rects.len(), 2);
+        assert_eq!(hits[0].snippet, "The Quick fox");
+        assert!(search_pages(&pages, "  ").is_empty());
+    }
+
+    #[test]
+    fn evicts_least_recently_drawn_pages() {
+        let page_bytes = 10 * 10 * 4;
+        let mut cache = PageCache::new(page_bytes * 2);
+        for page in 0..2 {
+            render(&mut cache, 1, RenderJob { page, width: 10, full: true }, image(10, 0), page as u32);
+        }
+        cache.get(0);
+        render(&mut cache, 1, RenderJob { page: 2, width: 10, full: true }, image(10, 0), 2);
+
+        assert!(cache.get(1).is_none());
+        assert!(cache.get(0).is_some());
+        assert!(cache.get(2).is_some());
+    }
+}
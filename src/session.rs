@@ -0,0 +1,179 @@
+// Session
+//
+// What was open when Typesafe last ran: the panel layout and, per project
+// folder, the tabs with their cursors and folds, the root document and where
+// the PDF preview was scrolled to. Written next to settings.json on exit and
+// every so often while running. Projects are kept most recent first; the first
+// one is reopened at startup and the rest make up File > Recent Projects.
+
+use crate::PdfFitMode;
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+
+// Projects remembered in the history
+pub const MAX_PROJECTS: usize = 10;
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Layout {
+    pub show_file_panel: bool,
+    pub show_preview_panel: bool,
+    pub show_log: bool,
+    pub show_pdf_popup: bool,
+    pub zoom: f32,
+    pub fit_mode: PdfFitMode,
+    pub multi_page_view: bool,
+    pub popout_zoom: f32,
+    pub popout_fit_mode: PdfFitMode,
+    pub popout_multi_page_view: bool,
+}
+
+impl Default for Layout {
+    fn default() -> Self {
+        Self {
+            show_file_panel: true,
+            show_preview_panel: true,
+            show_log: false,
+            show_pdf_popup: false,
+            zoom: 1.0,
+            fit_mode: PdfFitMode::Normal,
+            multi_page_view: false,
+            popout_zoom: 1.0,
+            popout_fit_mode: PdfFitMode::Normal,
+            popout_multi_page_view: false,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct OpenFile {
+    pub path: String,
+    // Character index of the cursor
+    pub cursor: usize,
+    pub scroll_offset: f32,
+    // First lines of the folded regions
+    pub folds: Vec<usize>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ProjectSession {
+    pub dir: PathBuf,
+    pub root_file: Option<String>,
+    pub files: Vec<OpenFile>,
+    // Index into `files` of the selected tab
+    pub active: usize,
+    // PDF in the preview, with the page at the top of the view and how far into it
+    pub pdf: Option<PathBuf>,
+    pub pdf_page: usize,
+    pub pdf_offset: f32,
+}
+
+impl ProjectSession {
+    // Tabs whose files still exist, and the selected one among them
+    pub fn existing_files(&self) -> (Vec<OpenFile>, usize) {
+        let active_path = self.files.get(self.active).map(|f| f.path.clone());
+        let files: Vec<OpenFile> = self.files.iter().filter(|f| Path::new(&f.path).exists()).cloned().collect();
+        let active = files.iter().position(|f| Some(&f.path) == active_path.as_ref()).unwrap_or(0);
+        (files, active)
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Session {
+    pub layout: Layout,
+    // Most recent first
+    pub projects: Vec<ProjectSession>,
+}
+
+impl Session {
+    fn path() -> PathBuf {
+        match directories::ProjectDirs::from("com", "typesafe", "typesafe") {
+            Some(dirs) => dirs.config_dir().join("session.json"),
+            None => PathBuf::from("session.json"),
+        }
+    }
+
+    pub fn load() -> Self {
+        std::fs::read_to_string(Self::path()).ok().and_then(|s| serde_json::from_str(&s).ok()).unwrap_or_default()
+    }
+
+    pub fn save(&self) {
+        let path = Self::path();
+        if let Some(dir) = path.parent() {
+            let _ = std::fs::create_dir_all(dir);
+        }
+        if let Ok(content) = serde_json::to_string_pretty(self) {
+            let _ = std::fs::write(path, content);
+        }
+    }
+
+    // The project to reopen at startup
+    pub fn last_project(&self) -> Option<&ProjectSession> {
+        self.projects.first().filter(|p| p.dir.is_dir())
+    }
+
+    pub fn project(&self, dir: &Path) -> Option<&ProjectSession> {
+        self.projects.iter().find(|p| p.dir == dir)
+    }
+
+    // Makes `project` the most recent entry, replacing any older one for its folder
+    pub fn remember(&mut self, project: ProjectSession) {
+        self.projects.retain(|p| p.dir != project.dir);
+        self.projects.insert(0, project);
+        self.projects.truncate(MAX_PROJECTS);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn project(dir: &str) -> ProjectSession {
+        ProjectSession { dir: PathBuf::from(dir), ..Default::default() }
+    }
+
+    #[test]
+    fn remembering_moves_a_project_to_the_front() {
+        let mut session = Session::default();
+        for i in 0..MAX_PROJECTS + 2 {
+            session.remember(project(&format!("/work/{}", i)));
+        }
+        assert_eq!(session.projects.len(), MAX_PROJECTS);
+        assert_eq!(session.projects[0].dir, PathBuf::from(format!("/work/{}", MAX_PROJECTS + 1)));
+
+        let mut thesis = project("/work/5");
+        thesis.root_file = Some("/work/5/thesis.tex".to_string());
+        session.remember(thesis.clone());
+        assert_eq!(session.projects.len(), MAX_PROJECTS);
+        assert_eq!(session.projects[0], thesis);
+        assert_eq!(session.projects.iter().filter(|p| p.dir == thesis.dir).count(), 1);
+    }
+
+    #[test]
+    fn missing_fields_and_files_are_tolerated() {
+        let session: Session = serde_json::from_str(r#"{"projects": [{"dir": "/work", "files": [{"path": "/nonexistent/a.tex"}]}]}"#).unwrap();
+        assert_eq!(session.layout, Layout::default());
+        assert_eq!(session.projects[0].files[0].cursor, 0);
+        assert!(session.projects[0].files[0].folds.is_empty());
+
+        let dir = std::env::temp_dir();
+        let kept = dir.join(format!("typesafe-session-{}.tex", std::process::id()));
+        std::fs::write(&kept, "").unwrap();
+        let entry = ProjectSession {
+            files: vec![
+                OpenFile { path: "/nonexistent/a.tex".to_string(), ..Default::default() },
+                OpenFile { path: kept.to_string_lossy().to_string(), cursor: 7, scroll_offset: 0.0, folds: vec![3] },
+            ],
+            active: 1,
+            ..project("/work")
+        };
+        let (files, active) = entry.existing_files();
+        assert_eq!(files.len(), 1);
+        assert_eq!((files[active].cursor, active), (7, 0));
+        assert_eq!(files[active].folds, vec![3]);
+        let _ = std::fs::remove_file(kept);
+    }
+}
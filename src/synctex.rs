@@ -0,0 +1,388 @@
+// SyncTeX Index
+//
+// Parses a `.synctex(.gz)` file once and keeps its records indexed by source
+// line (forward search) and by page (inverse search). Building the index reads
+// the whole file, so it happens on a background thread after each compile.
+
+use flate2::read::GzDecoder;
+use std::collections::HashMap;
+use std::io::{BufRead, BufReader, Read};
+use std::path::{Component, Path, PathBuf};
+
+// Scaled points per PDF big point
+const SP_PER_BP: f32 = 65781.76;
+// Inputs that are packages or classes rather than the user's document
+const LIBRARY_EXTENSIONS: &[&str] = &["sty", "cls", "clo", "def", "cfg", "fd", "ldf"];
+// Size given to glyph positions when highlighting, in PDF points
+const POINT_HEIGHT: f32 = 8.0;
+const POINT_DEPTH: f32 = 2.0;
+const POINT_WIDTH: f32 = 5.0;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RecordKind {
+    VBox,
+    HBox,
+    // Glyph, kern, glue and math positions, which carry no height
+    Point,
+}
+
+#[derive(Debug, Clone)]
+pub struct SyncRecord {
+    // 0-based page
+    pub page: usize,
+    pub tag: usize,
+    pub line: usize,
+    pub kind: RecordKind,
+    // PDF points, top-left origin
+    pub rect: egui::Rect,
+}
+
+#[derive(Debug, Default)]
+pub struct SyncTexIndex {
+    // The folder the engine ran in
+    base_dir: PathBuf,
+    inputs: HashMap<usize, PathBuf>,
+    records: Vec<SyncRecord>,
+    by_line: HashMap<(usize, usize), Vec<usize>>,
+    by_page: HashMap<usize, Vec<usize>>,
+}
+
+impl SyncTexIndex {
+    // Finds `<stem>.synctex.gz` (or an uncompressed `.synctex`) beside the PDF
+    pub fn for_pdf(pdf_path: &Path) -> Option<PathBuf> {
+        let stem = pdf_path.file_stem()?.to_string_lossy().to_string();
+        let dir = pdf_path.parent().unwrap_or(Path::new("."));
+        [format!("{}.synctex.gz", stem), format!("{}.synctex", stem)]
+            .into_iter()
+            .map(|name| dir.join(name))
+            .find(|p| p.exists())
+    }
+
+    // `base_dir` is the folder the engine ran in; see `parse`
+    pub fn load(path: &Path, base_dir: &Path) -> std::io::Result<Self> {
+        let file = std::fs::File::open(path)?;
+        let reader: Box<dyn Read> = if path.extension().is_some_and(|e| e == "gz") {
+            Box::new(GzDecoder::new(file))
+        } else {
+            Box::new(file)
+        };
+        Self::parse(BufReader::new(reader), base_dir)
+    }
+
+    // Relative `Input:` paths are resolved against `base_dir`, the folder the
+    // engine ran in
+    pub fn parse(reader: impl BufRead, base_dir: &Path) -> std::io::Result<Self> {
+        let mut index = Self { base_dir: normalize(base_dir), ..Self::default() };
+        let mut unit = 1.0_f32;
+        let mut magnification = 1.0_f32;
+        let mut offset = (0.0_f32, 0.0_f32);
+        let mut page = 0;
+
+        for line in reader.lines() {
+            let line = line?;
+            let Some(first) = line.chars().next() else { continue };
+            let rest = &line[first.len_utf8()..];
+
+            match first {
+                '{' => page = rest.trim().parse::<usize>().unwrap_or(1).saturating_sub(1),
+                '[' | '(' | 'v' | 'h' | 'x' | 'k' | 'g' | '$' => {
+                    let kind = match first {
+                        '[' | 'v' => RecordKind::VBox,
+                        '(' | 'h' => RecordKind::HBox,
+                        _ => RecordKind::Point,
+                    };
+                    let scale = unit * magnification / SP_PER_BP;
+                    if let Some(record) = parse_record(rest, kind, page, scale, offset) {
+                        let id = index.records.len();
+                        index.by_line.entry((record.tag, record.line)).or_default().push(id);
+                        index.by_page.entry(page).or_default().push(id);
+                        index.records.push(record);
+                    }
+                }
+                _ => {
+                    if let Some(input) = line.strip_prefix("Input:") {
+                        if let Some((tag, path)) = input.split_once(':') {
+                            if let Ok(tag) = tag.parse() {
+                                index.inputs.insert(tag, normalize(&base_dir.join(path)));
+                            }
+                        }
+                    } else if let Some(value) = line.strip_prefix("Unit:") {
+                        unit = value.trim().parse().unwrap_or(1.0);
+                    } else if let Some(value) = line.strip_prefix("Magnification:") {
+                        magnification = value.trim().parse::<f32>().map(|m| m / 1000.0).unwrap_or(1.0);
+                    } else if let Some(value) = line.strip_prefix("X Offset:") {
+                        offset.0 = value.trim().parse().unwrap_or(0.0);
+                    } else if let Some(value) = line.strip_prefix("Y Offset:") {
+                        offset.1 = value.trim().parse().unwrap_or(0.0);
+                    }
+                }
+            }
+        }
+
+        Ok(index)
+    }
+
+    pub fn input(&self, tag: usize) -> Option<&Path> {
+        self.inputs.get(&tag).map(PathBuf::as_path)
+    }
+
+    fn tag_for(&self, file: &Path) -> Option<usize> {
+        let file = normalize(file);
+        if let Some((tag, _)) = self.inputs.iter().find(|(_, input)| **input == file) {
+            return Some(*tag);
+        }
+        // Engines sometimes record a different spelling of the same path, so
+        // compare where each lies below the folder the engine ran in
+        let relative = relative_to(&file, &self.base_dir)?;
+        self.inputs
+            .iter()
+            .find(|(_, input)| relative_to(input, &self.base_dir).as_ref() == Some(&relative))
+            .map(|(tag, _)| *tag)
+    }
+
+    // Records produced by `line` of `file`. Lines that typeset nothing (blank
+    // lines, comments) fall through to the next line that did.
+    pub fn forward(&self, file: &Path, line: usize) -> Vec<&SyncRecord> {
+        let Some(tag) = self.tag_for(file) else { return Vec::new() };
+        let nearest = self
+            .by_line
+            .keys()
+            .filter(|(t, l)| *t == tag && *l >= line)
+            .map(|(_, l)| *l)
+            .min()
+            .or_else(|| self.by_line.keys().filter(|(t, _)| *t == tag).map(|(_, l)| *l).max());
+
+        match nearest.and_then(|l| self.by_line.get(&(tag, l))) {
+            Some(ids) => ids.iter().map(|&id| &self.records[id]).collect(),
+            None => Vec::new(),
+        }
+    }
+
+    // Page areas covered by `line` of `file`, for highlighting. Line boxes are
+    // used as they are; loose glyph positions outside them are grouped by
+    // baseline into boxes of roughly one text line.
+    pub fn forward_area(&self, file: &Path, line: usize) -> Vec<(usize, egui::Rect)> {
+        let records = self.forward(file, line);
+        let mut area: Vec<(usize, egui::Rect)> =
+            records.iter().filter(|r| r.kind == RecordKind::HBox && r.rect.area() > 0.0).map(|r| (r.page, r.rect)).collect();
+
+        let mut baselines: Vec<(usize, egui::Rect)> = Vec::new();
+        for record in records.iter().filter(|r| r.kind == RecordKind::Point) {
+            let pos = record.rect.min;
+            if area.iter().any(|(page, rect)| *page == record.page && rect.expand(0.5).contains(pos)) {
+                continue;
+            }
+            match baselines.iter_mut().find(|(page, rect)| *page == record.page && (rect.bottom() - POINT_DEPTH - pos.y).abs() < 1.0) {
+                Some((_, rect)) => *rect = rect.union(point_box(pos)),
+                None => baselines.push((record.page, point_box(pos))),
+            }
+        }
+
+        area.extend(baselines);
+        area
+    }
+
+    fn is_document_source(&self, tag: usize) -> bool {
+        self.input(tag).is_some_and(|path| {
+            let ext = path.extension().map(|e| e.to_string_lossy().to_lowercase()).unwrap_or_default();
+            !LIBRARY_EXTENSIONS.contains(&ext.as_str())
+        })
+    }
+
+    // Source location closest to `pos` (PDF points) on `page`, measured in both
+    // directions so the right column wins in multi-column layouts. Boxes
+    // containing the point win over nearby ones, and smaller boxes over larger.
+    // Records made by package or class macros are skipped.
+    pub fn inverse(&self, page: usize, pos: egui::Pos2) -> Option<(PathBuf, usize)> {
+        let ids: Vec<usize> = self.by_page.get(&page)?.iter().copied().filter(|&id| self.is_document_source(self.records[id].tag)).collect();
+        let score = |record: &SyncRecord| {
+            let rect = record.rect;
+            let dx = (rect.left() - pos.x).max(pos.x - rect.right()).max(0.0);
+            let dy = (rect.top() - pos.y).max(pos.y - rect.bottom()).max(0.0);
+            (dx.hypot(dy), rect.area())
+        };
+
+        // Vertical boxes span whole paragraphs or pages; only use them as a last resort
+        let best = ids
+            .iter()
+            .map(|&id| &self.records[id])
+            .filter(|r| r.kind != RecordKind::VBox)
+            .min_by(|a, b| score(a).partial_cmp(&score(b)).unwrap_or(std::cmp::Ordering::Equal))
+            .or_else(|| {
+                ids.iter()
+                    .map(|&id| &self.records[id])
+                    .min_by(|a, b| score(a).partial_cmp(&score(b)).unwrap_or(std::cmp::Ordering::Equal))
+            })?;
+
+        Some((self.input(best.tag)?.to_path_buf(), best.line))
+    }
+}
+
+// "tag,line[,column]:h,v[:W,H,D]"
+fn parse_record(text: &str, kind: RecordKind, page: usize, scale: f32, offset: (f32, f32)) -> Option<SyncRecord> {
+    let mut parts = text.split(':');
+    let mut link = parts.next()?.split(',');
+    let tag = link.next()?.parse().ok()?;
+    let line = link.next()?.parse().ok()?;
+
+    let mut point = parts.next()?.split(',').map(|n| n.trim().parse::<f32>());
+    let h = point.next()?.ok()?;
+    let v = point.next()?.ok()?;
+    let x = (h + offset.0) * scale;
+    let y = (v + offset.1) * scale;
+
+    let size: Vec<f32> = parts
+        .next()
+        .map(|s| s.split(',').filter_map(|n| n.trim().parse().ok()).collect())
+        .unwrap_or_default();
+    let rect = match (kind, size.as_slice()) {
+        (RecordKind::VBox | RecordKind::HBox, [w, height, depth, ..]) => {
+            egui::Rect::from_min_max(egui::pos2(x, y - height * scale), egui::pos2(x + w * scale, y + depth * scale))
+        }
+        _ => egui::Rect::from_min_max(egui::pos2(x, y), egui::pos2(x, y)),
+    };
+
+    Some(SyncRecord { page, tag, line, kind, rect })
+}
+
+// Approximate text line around a glyph position on its baseline
+fn point_box(pos: egui::Pos2) -> egui::Rect {
+    egui::Rect::from_min_max(egui::pos2(pos.x, pos.y - POINT_HEIGHT), egui::pos2(pos.x + POINT_WIDTH, pos.y + POINT_DEPTH))
+}
+
+// Drops `.` and folds `..` into the folder before it without touching the
+// disk, so the result compares equal to the paths of open files
+pub(crate) fn normalize(path: &Path) -> PathBuf {
+    let mut out = PathBuf::new();
+    for component in path.components() {
+        match component {
+            Component::CurDir => {}
+            Component::ParentDir if matches!(out.components().next_back(), Some(Component::Normal(_))) => {
+                out.pop();
+            }
+            _ => out.push(component),
+        }
+    }
+    out
+}
+
+// `path` below `base`, as written or else with symbolic links resolved
+fn relative_to(path: &Path, base: &Path) -> Option<PathBuf> {
+    if let Ok(relative) = path.strip_prefix(base) {
+        return Some(relative.to_path_buf());
+    }
+    let (path, base) = (std::fs::canonicalize(path).ok()?, std::fs::canonicalize(base).ok()?);
+    path.strip_prefix(base).ok().map(Path::to_path_buf)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SAMPLE: &str = "\
+SyncTeX Version:1
+Input:1:./main.tex
+Input:2:./chapters/two.tex
+Input:3:/usr/share/texmf/tex/latex/foo/foo.sty
+Output:pdf
+Magnification:1000
+Unit:1
+X Offset:0
+Y Offset:0
+Content:
+!100
+{1
+[1,3:4736286,4736286:26673152,41817146,0
+(1,5:4736286,6578176:13000000,655360,196608
+x1,5:4736286,6578176
+k1,5:5000000,6578176:-20000
+x3,40:5262541,6446612
+)
+(2,7:18000000,6578176:13000000,655360,196608
+g2,7:18000000,6578176
+)
+]
+}1
+{2
+[1,3:4736286,4736286:26673152,41817146,0
+(2,9:4736286,9868800:26673152,655360,196608
+)
+]
+}2
+";
+
+    fn index() -> SyncTexIndex {
+        SyncTexIndex::parse(SAMPLE.as_bytes(), Path::new("/work")).unwrap()
+    }
+
+    #[test]
+    fn forward_maps_lines_to_boxes() {
+        let index = index();
+        let records = index.forward(Path::new("/work/main.tex"), 5);
+        assert_eq!(records.len(), 3);
+        assert!(records.iter().all(|r| r.page == 0 && r.line == 5));
+
+        let hbox = records.iter().find(|r| r.kind == RecordKind::HBox).unwrap();
+        assert!((hbox.rect.left() - 72.0).abs() < 0.1);
+        assert!((hbox.rect.bottom() - (6578176.0 + 196608.0) / SP_PER_BP).abs() < 0.01);
+
+        // Line 8 typesets nothing, so it falls through to line 9 on page 2
+        let records = index.forward(Path::new("/work/chapters/two.tex"), 8);
+        assert_eq!(records.len(), 1);
+        assert_eq!(records[0].page, 1);
+
+        // Another spelling of the same file matches, a namesake elsewhere does not
+        assert_eq!(index.forward(Path::new("/work/chapters/../chapters/./two.tex"), 9).len(), 1);
+        assert!(index.forward(Path::new("/work/appendix/two.tex"), 9).is_empty());
+        assert!(index.forward(Path::new("/other/main.tex"), 5).is_empty());
+    }
+
+    #[test]
+    fn inputs_are_matched_below_the_engine_folder() {
+        let dir = std::env::temp_dir().join(format!("typesafe-synctex-{}", std::process::id()));
+        std::fs::create_dir_all(dir.join("chapters")).unwrap();
+        std::fs::write(dir.join("chapters/two.tex"), "").unwrap();
+        // The engine ran in the canonical folder, the editor opened it through a symbolic link
+        let canonical = std::fs::canonicalize(&dir).unwrap();
+        let sample = SAMPLE.replace("./chapters/two.tex", &format!("{}/chapters/two.tex", canonical.display()));
+        let index = SyncTexIndex::parse(sample.as_bytes(), &canonical).unwrap();
+        #[cfg(unix)]
+        {
+            let link = dir.with_extension("link");
+            let _ = std::fs::remove_file(&link);
+            std::os::unix::fs::symlink(&canonical, &link).unwrap();
+            assert_eq!(index.forward(&link.join("chapters/two.tex"), 9).len(), 1);
+            let _ = std::fs::remove_file(&link);
+        }
+        assert_eq!(index.forward(&canonical.join("chapters/two.tex"), 9).len(), 1);
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn forward_area_covers_boxes_and_stray_glyphs() {
+        let index = index();
+        let area = index.forward_area(Path::new("/work/main.tex"), 5);
+        // The line box, with the glyph and kern inside it folded in
+        assert_eq!(area.len(), 1);
+        assert_eq!(area[0].0, 0);
+        assert!((area[0].1.width() - 13000000.0 / SP_PER_BP).abs() < 0.01);
+
+        // Only a glyph position from the package: grouped into a line-sized box
+        let area = index.forward_area(Path::new("/usr/share/texmf/tex/latex/foo/foo.sty"), 40);
+        assert_eq!(area.len(), 1);
+        assert!((area[0].1.height() - (POINT_HEIGHT + POINT_DEPTH)).abs() < 0.01);
+    }
+
+    #[test]
+    fn inverse_uses_both_coordinates() {
+        let index = index();
+        let y = 6578176.0 / SP_PER_BP - 2.0;
+
+        // Same baseline, two columns; the package's glyph exactly under the
+        // first point is ignored
+        assert_eq!(index.inverse(0, egui::pos2(80.0, y)), Some((PathBuf::from("/work/main.tex"), 5)));
+        assert_eq!(index.inverse(0, egui::pos2(300.0, y)), Some((PathBuf::from("/work/chapters/two.tex"), 7)));
+        assert_eq!(index.inverse(1, egui::pos2(100.0, 150.0)), Some((PathBuf::from("/work/chapters/two.tex"), 9)));
+        assert_eq!(index.inverse(5, egui::pos2(0.0, 0.0)), None);
+    }
+}
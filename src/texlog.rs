@@ -0,0 +1,310 @@
+// TeX Log Parsing
+//
+// Turns a TeX/Tectonic .log into diagnostics. TeX only prints the line of a
+// message, so the source file is recovered from the `(file ... )` nesting it
+// writes as inputs are opened and closed.
+
+use crate::compiler::{Diagnostic, Severity};
+use regex::Regex;
+use std::path::{Path, PathBuf};
+
+// TeX hard-wraps log lines at this many characters (max_print_line)
+const MAX_PRINT_LINE: usize = 79;
+// How far past an error to look for its `l.<line>` context
+const CONTEXT_LOOKAHEAD: usize = 12;
+// Upper bound on the box excerpt printed after an Over/Underfull message
+const BADBOX_EXCERPT_LINES: usize = 20;
+
+struct Patterns {
+    file_line_error: Regex,
+    line_context: Regex,
+    warning: Regex,
+    input_line: Regex,
+    badbox: Regex,
+    badbox_line: Regex,
+}
+
+impl Patterns {
+    fn new() -> Self {
+        Self {
+            file_line_error: Regex::new(r"^((?:[A-Za-z]:)?[^:()]+\.[A-Za-z]+):(\d+): (.*)$").unwrap(),
+            line_context: Regex::new(r"^l\.(\d+) ?(.*)$").unwrap(),
+            warning: Regex::new(r"^(?:(LaTeX Font|LaTeX|pdfTeX)|(?:Package|Class|Module) (\S+)) Warning: (.*)$").unwrap(),
+            input_line: Regex::new(r"\s*on input line (\d+)\.?").unwrap(),
+            badbox: Regex::new(r"^((?:Over|Under)full \\[hv]box \([^)]*\))(.*)$").unwrap(),
+            badbox_line: Regex::new(r"at lines? (\d+)").unwrap(),
+        }
+    }
+}
+
+// Parses a complete log. Messages outside any recognised input are attributed
+// to `main_file`; relative paths are resolved against its folder.
+pub fn parse_log(log: &str, main_file: &Path) -> Vec<Diagnostic> {
+    let patterns = Patterns::new();
+    let base_dir = main_file.parent().unwrap_or(Path::new("."));
+    let lines = unwrap_lines(log);
+
+    // One entry per open paren; None for parens that are not files
+    let mut files: Vec<Option<PathBuf>> = Vec::new();
+    let mut diagnostics = Vec::new();
+    let mut i = 0;
+
+    while i < lines.len() {
+        let current_file = files.iter().rev().flatten().next().cloned().unwrap_or_else(|| main_file.to_path_buf());
+        match parse_message(&patterns, &lines, i, &current_file, base_dir) {
+            Some((diagnostic, consumed)) => {
+                diagnostics.push(diagnostic);
+                i += consumed;
+            }
+            None => {
+                track_files(&lines[i], base_dir, &mut files);
+                i += 1;
+            }
+        }
+    }
+
+    diagnostics
+}
+
+// Rejoins lines TeX split at MAX_PRINT_LINE
+fn unwrap_lines(log: &str) -> Vec<String> {
+    let mut lines = Vec::new();
+    let mut pending = String::new();
+    for line in log.lines() {
+        pending.push_str(line);
+        if line.chars().count() != MAX_PRINT_LINE {
+            lines.push(std::mem::take(&mut pending));
+        }
+    }
+    if !pending.is_empty() {
+        lines.push(pending);
+    }
+    lines
+}
+
+// Recognises a message starting at `lines[i]`. Returns it with the number of
+// lines it spans, so quoted source text is kept out of the file tracking.
+fn parse_message(patterns: &Patterns, lines: &[String], i: usize, current_file: &Path, base_dir: &Path) -> Option<(Diagnostic, usize)> {
+    let line = lines[i].as_str();
+
+    // "! Undefined control sequence."
+    if let Some(message) = line.strip_prefix("! ") {
+        let (line_num, column, consumed) = error_context(patterns, lines, i);
+        return Some((diagnostic(Severity::Error, message, current_file, line_num, column), consumed));
+    }
+
+    // "./chapter.tex:12: Undefined control sequence." (-file-line-error)
+    if let Some(caps) = patterns.file_line_error.captures(line) {
+        let file = resolve(&caps[1], base_dir);
+        let line_num = caps[2].parse().unwrap_or(0);
+        let (_, column, consumed) = error_context(patterns, lines, i);
+        return Some((diagnostic(Severity::Error, &caps[3], &file, line_num, column), consumed));
+    }
+
+    // "LaTeX Warning: ...", "Package hyperref Warning: ..." with "(name)" continuations
+    if let Some(caps) = patterns.warning.captures(line) {
+        let source = caps.get(1).or(caps.get(2)).map_or("", |m| m.as_str());
+        let prefix = match source {
+            "LaTeX Font" => "(Font)".to_string(),
+            s => format!("({})", s),
+        };
+        let mut text = caps[3].trim().to_string();
+        let mut consumed = 1;
+        while let Some(next) = lines.get(i + consumed).and_then(|l| l.strip_prefix(prefix.as_str())) {
+            text.push(' ');
+            text.push_str(next.trim());
+            consumed += 1;
+        }
+
+        let line_num = patterns.input_line.captures(&text).and_then(|c| c[1].parse().ok()).unwrap_or(0);
+        let text = patterns.input_line.replace(&text, "").to_string();
+        let message = match caps.get(2) {
+            Some(package) => format!("{}: {}", package.as_str(), text),
+            None => text,
+        };
+        return Some((diagnostic(Severity::Warning, &message, current_file, line_num, None), consumed));
+    }
+
+    // "Overfull \hbox (15.0pt too wide) in paragraph at lines 12--14", followed by
+    // an excerpt of the box that runs to the next blank line
+    if let Some(caps) = patterns.badbox.captures(line) {
+        let line_num = patterns.badbox_line.captures(&caps[2]).and_then(|c| c[1].parse().ok()).unwrap_or(0);
+        let mut consumed = 1;
+        while consumed <= BADBOX_EXCERPT_LINES && lines.get(i + consumed).is_some_and(|l| !l.trim().is_empty()) {
+            consumed += 1;
+        }
+        return Some((diagnostic(Severity::Badbox, &caps[1], current_file, line_num, None), consumed));
+    }
+
+    None
+}
+
+// Finds the `l.<line> <text>` context printed after an error. The column is
+// where TeX stopped reading, unless the context was truncated with "...".
+fn error_context(patterns: &Patterns, lines: &[String], i: usize) -> (usize, Option<usize>, usize) {
+    for (j, line) in lines.iter().enumerate().skip(i + 1).take(CONTEXT_LOOKAHEAD) {
+        if line.starts_with("! ") {
+            break;
+        }
+        if let Some(caps) = patterns.line_context.captures(line) {
+            let line_num = caps[1].parse().unwrap_or(0);
+            let column = (!caps[2].starts_with("...")).then(|| caps[2].chars().count());
+            // The unread remainder of the source line is printed indented below
+            let remainder = lines.get(j + 1).is_some_and(|l| l.starts_with(' '));
+            return (line_num, column, j - i + 1 + usize::from(remainder));
+        }
+    }
+    (0, None, 1)
+}
+
+// Applies the parens on one log line to the open-file stack
+fn track_files(line: &str, base_dir: &Path, files: &mut Vec<Option<PathBuf>>) {
+    let mut chars = line.char_indices().peekable();
+    while let Some((idx, c)) = chars.next() {
+        match c {
+            '(' => {
+                let rest = &line[idx + 1..];
+                let token = if let Some(quoted) = rest.strip_prefix('"') {
+                    quoted.split('"').next().unwrap_or("")
+                } else {
+                    rest.split(|c: char| c.is_whitespace() || c == '(' || c == ')').next().unwrap_or("")
+                };
+                files.push(looks_like_file(token).then(|| resolve(token, base_dir)));
+                // Skip the name so parens inside it are not counted
+                let skip = if rest.starts_with('"') { token.len() + 2 } else { token.len() };
+                while chars.peek().is_some_and(|&(i, _)| i <= idx + skip) {
+                    chars.next();
+                }
+            }
+            ')' => {
+                files.pop();
+            }
+            _ => {}
+        }
+    }
+}
+
+fn looks_like_file(token: &str) -> bool {
+    if token.starts_with("./") || token.starts_with("../") || token.starts_with('/') || token.starts_with(".\\") {
+        return true;
+    }
+    let bytes = token.as_bytes();
+    if bytes.len() > 2 && bytes[0].is_ascii_alphabetic() && bytes[1] == b':' && (bytes[2] == b'\\' || bytes[2] == b'/') {
+        return true;
+    }
+    // "article.cls" but not "15.0pt" or "e.g.,"
+    match token.rsplit_once('.') {
+        Some((name, ext)) => !name.is_empty() && (1..=4).contains(&ext.len()) && ext.chars().all(|c| c.is_ascii_alphabetic()),
+        None => false,
+    }
+}
+
+fn resolve(name: &str, base_dir: &Path) -> PathBuf {
+    let path = Path::new(name);
+    if path.is_absolute() {
+        path.to_path_buf()
+    } else {
+        base_dir.join(path.strip_prefix(".").unwrap_or(path))
+    }
+}
+
+fn diagnostic(severity: Severity, message: &str, file: &Path, line: usize, column: Option<usize>) -> Diagnostic {
+    Diagnostic {
+        severity,
+        message: message.trim().to_string(),
+        file: file.to_string_lossy().to_string(),
+        line,
+        column,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(log: &str) -> Vec<Diagnostic> {
+        parse_log(log, Path::new("/work/main.tex"))
+    }
+
+    #[test]
+    fn errors_are_attributed_to_the_open_file() {
+        let log = "\
+This is pdfTeX, Version 3.141592653
+(./main.tex
+LaTeX2e <2023-11-01>
+(/usr/share/texmf/tex/latex/base/article.cls
+Document Class: article 2023/05/17 v1.4n Standard LaTeX document class
+) (./chapters/intro.tex
+! Undefined control sequence.
+l.4 Some \\foo
+               (unbalanced text
+?
+) [1] (./main.aux) )
+";
+        let diags = parse(log);
+        assert_eq!(diags.len(), 1);
+        assert_eq!(diags[0].severity, Severity::Error);
+        assert_eq!(diags[0].message, "Undefined control sequence.");
+        assert_eq!(diags[0].file, "/work/chapters/intro.tex");
+        assert_eq!(diags[0].line, 4);
+        assert_eq!(diags[0].column, Some(9));
+    }
+
+    #[test]
+    fn file_line_errors_name_their_file() {
+        let log = "(./main.tex\n./sections/a.tex:12: Missing $ inserted.\n<inserted text> \n                $\nl.12 x^\n       2\n)\n";
+        let diags = parse(log);
+        assert_eq!(diags.len(), 1);
+        assert_eq!(diags[0].file, "/work/sections/a.tex");
+        assert_eq!(diags[0].line, 12);
+        assert_eq!(diags[0].column, Some(2));
+    }
+
+    #[test]
+    fn warnings_and_badboxes() {
+        let log = "\
+(./main.tex
+LaTeX Warning: Reference `fig:plot' on page 1 undefined on input line 7.
+
+LaTeX Warning: Citation `knuth84' on page 2 undefined on input line 15.
+
+Package hyperref Warning: Token not allowed in a PDF string (Unicode):
+(hyperref)                removing `math shift' on input line 21.
+
+Overfull \\hbox (15.0pt too wide) in paragraph at lines 30--32
+[]\\OT1/cmr/m/n/10 A very (long line
+ []
+
+Underfull \\vbox (badness 10000) detected at line 40
+ []
+
+LaTeX Warning: There were undefined references.
+)
+";
+        let diags = parse(log);
+        let summary: Vec<_> = diags.iter().map(|d| (d.severity, d.line, d.message.as_str())).collect();
+        assert_eq!(
+            summary,
+            vec![
+                (Severity::Warning, 7, "Reference `fig:plot' on page 1 undefined"),
+                (Severity::Warning, 15, "Citation `knuth84' on page 2 undefined"),
+                (Severity::Warning, 21, "hyperref: Token not allowed in a PDF string (Unicode): removing `math shift'"),
+                (Severity::Badbox, 30, "Overfull \\hbox (15.0pt too wide)"),
+                (Severity::Badbox, 40, "Underfull \\vbox (badness 10000)"),
+                (Severity::Warning, 0, "There were undefined references."),
+            ]
+        );
+        assert!(diags.iter().all(|d| d.file == "/work/main.tex"));
+    }
+
+    #[test]
+    fn wrapped_lines_are_rejoined() {
+        let path = "/usr/share/texlive/texmf-dist/tex/latex/some-long-package-name/longname.sty";
+        let opened = format!("(./main.tex ({}", path);
+        let (head, tail) = opened.split_at(MAX_PRINT_LINE);
+        let log = format!("{}\n{}\n! Emergency stop.\nl.3 \\end\n)\n", head, tail);
+        let diags = parse(&log);
+        assert_eq!(diags[0].file, path);
+        assert_eq!(diags[0].line, 3);
+    }
+}